@@ -5,8 +5,9 @@ mod p1_switches;
 mod p2_laundry_machine;
 mod p3_atm;
 mod p4_accounted_currency;
-mod p5_digital_cash;
+pub mod p5_digital_cash;
 mod p6_open_ended;
+mod p7_scripted_cash;
 
 /// A state machine - Generic over the transition type
 pub trait StateMachine {
@@ -19,6 +20,67 @@ pub trait StateMachine {
     /// Calculate the resulting state when this state undergoes the given transition
     fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State;
 
+    /// Apply each of the given transitions in order, starting from the given state, and return
+    /// the final state.
+    fn apply_all(start: &Self::State, transitions: &[Self::Transition]) -> Self::State
+    where
+        Self::State: Clone,
+    {
+        transitions
+            .iter()
+            .fold(start.clone(), |state, t| Self::next_state(&state, t))
+    }
+
+    /// Whether the given transition leaves the state unchanged. Many state machines treat invalid
+    /// transitions this way, so this is how callers can tell that a transition had no effect.
+    fn is_noop(state: &Self::State, t: &Self::Transition) -> bool
+    where
+        Self::State: PartialEq,
+    {
+        Self::next_state(state, t) == *state
+    }
+
+    /// The indices of the given transitions that would change the given state. Each transition is
+    /// checked against the same state on its own, not applied in sequence. This lets a simulation or
+    /// fuzzing harness prune the transitions that would be rejected.
+    fn filter_valid(state: &Self::State, transitions: &[Self::Transition]) -> Vec<usize>
+    where
+        Self::State: PartialEq,
+    {
+        transitions
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| !Self::is_noop(state, t))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Apply each of the given transitions in order, starting from the given state, and return
+    /// the state after every step. The trace has one state per transition and does not include
+    /// the starting state. This is useful for debugging a misbehaving sequence of transitions.
+    fn trace(start: &Self::State, transitions: &[Self::Transition]) -> Vec<Self::State> {
+        let mut states: Vec<Self::State> = Vec::with_capacity(transitions.len());
+        for t in transitions {
+            let next = Self::next_state(states.last().unwrap_or(start), t);
+            states.push(next);
+        }
+        states
+    }
+
+    /// Compare two traces and return the index of the first step at which they differ.
+    ///
+    /// If one trace is a prefix of the other, they diverge where the shorter one ends. Traces that
+    /// are identical do not diverge at all.
+    fn first_divergence(a: &[Self::State], b: &[Self::State]) -> Option<usize>
+    where
+        Self::State: PartialEq,
+    {
+        a.iter()
+            .zip(b.iter())
+            .position(|(a, b)| a != b)
+            .or_else(|| (a.len() != b.len()).then(|| a.len().min(b.len())))
+    }
+
     /// A human-readable name for this state machine. This may be used in user-facing
     /// programs such as the repl described below. This is not in any way related to
     /// the correctness of the state machine.
@@ -27,8 +89,109 @@ pub trait StateMachine {
     }
 }
 
+/// A state machine whose transitions can be rolled back cheaply.
+///
+/// Any state machine with a cloneable state can be rolled back by keeping a snapshot of the old state
+/// (see `apply_with_undo`). Machines with large states can do better by recording only what changed.
+pub trait ReversibleStateMachine: StateMachine {
+    /// The information needed to revert a single transition
+    type Undo;
+
+    /// Calculate the resulting state just like `next_state`, and also return a token that can later
+    /// be used to revert to the starting state
+    fn next_state_with_undo(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> (Self::State, Self::Undo);
+
+    /// Revert the transition recorded in the undo token, returning the state from before it was applied
+    fn revert(state: &Self::State, undo: &Self::Undo) -> Self::State;
+}
+
+/// Apply a transition and return both the new state and a snapshot of the old one. A client that may
+/// need to revert the most recent block can do so by discarding the new state and keeping the snapshot.
+pub fn apply_with_undo<SM: StateMachine>(
+    state: &SM::State,
+    t: &SM::Transition,
+) -> (SM::State, SM::State)
+where
+    SM::State: Clone,
+{
+    (SM::next_state(state, t), state.clone())
+}
+
+/// Execute a sequence of block bodies, starting from the genesis state, and return the hash of the
+/// state after each block. These are the `state_root` values that a valid header chain built on
+/// top of the given genesis state must commit to, in order. The genesis state's own root is not
+/// included.
+pub fn state_roots<SM: StateMachine>(genesis: SM::State, bodies: &[Vec<SM::Transition>]) -> Vec<u64>
+where
+    SM::State: std::hash::Hash + Clone,
+{
+    let mut state = genesis;
+    bodies
+        .iter()
+        .map(|body| {
+            state = SM::apply_all(&state, body);
+            crate::hash(&state)
+        })
+        .collect()
+}
+
+/// Decides whether a user may author a transition of the state machine `SM` in the given state.
+pub trait Authorization<SM: StateMachine> {
+    fn is_authorized(state: &SM::State, signer: &User, t: &SM::Transition) -> bool;
+}
+
+/// A transition along with the user who signed it.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct SignedTransition<T> {
+    pub signer: User,
+    pub transition: T,
+}
+
+/// A state machine adapter that requires every transition of the inner machine `SM` to be signed.
+/// Transitions whose signer is not authorized by `A` leave the state unchanged, just like any other
+/// invalid transition. This models authentication uniformly across state machines.
+pub struct Signed<SM, A>(std::marker::PhantomData<(SM, A)>);
+
+impl<SM, A> StateMachine for Signed<SM, A>
+where
+    SM: StateMachine,
+    SM::State: Clone,
+    A: Authorization<SM>,
+{
+    type State = SM::State;
+    type Transition = SignedTransition<SM::Transition>;
+
+    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+        if A::is_authorized(starting_state, &t.signer, &t.transition) {
+            SM::next_state(starting_state, &t.transition)
+        } else {
+            starting_state.clone()
+        }
+    }
+
+    fn human_name() -> String {
+        format!("Signed {}", SM::human_name())
+    }
+}
+
+/// Check that applying the same transition to the same state twice yields identical results.
+///
+/// `next_state` only takes shared references, so a state machine should always be deterministic.
+/// Nothing in the type system enforces that though, so tests use this helper to check it.
+#[cfg(test)]
+pub(crate) fn assert_deterministic<SM: StateMachine>(state: &SM::State, t: &SM::Transition)
+where
+    SM::State: PartialEq + std::fmt::Debug,
+{
+    assert_eq!(SM::next_state(state, t), SM::next_state(state, t));
+}
+
 /// A set of play users for experimenting with the multi-user state machines
-#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+#[derive(Hash, Eq, PartialEq, PartialOrd, Ord, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum User {
     Alice,
     Bob,
@@ -39,5 +202,20 @@ pub enum User {
     Noah,
 }
 
+impl User {
+    /// Every user, in declaration order.
+    pub fn all() -> &'static [User] {
+        &[
+            User::Alice,
+            User::Bob,
+            User::Charlie,
+            User::Dave,
+            User::Eve,
+            User::Frank,
+            User::Noah,
+        ]
+    }
+}
+
 //TODO Some kind of main program that allows users to interact with their state machine in a repl-like way.
 // Might require From<String> implementation for the transition type.
@@ -3,8 +3,9 @@
 //! cash bills. Each bill has an amount and an owner, and can be spent in its entirety.
 //! When a state transition spends bills, new bills are created in lesser or equal amount.
 
-use super::{StateMachine, User};
-use std::collections::HashSet;
+use super::{Authorization, ReversibleStateMachine, StateMachine, User};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 
 /// This state machine models a multi-user currency system. It tracks a set of bills in
 /// circulation, and updates that set when money is transferred.
@@ -14,10 +15,41 @@ pub struct DigitalCashSystem;
 /// it and an amount that it is worth. It also has serial number to ensure that each bill
 /// is unique.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Bill {
     owner: User,
     amount: u64,
     serial: u64,
+    /// The height after which the bill expires if it has not been spent, if any.
+    expiry: Option<u64>,
+}
+
+impl Bill {
+    pub fn new(owner: User, amount: u64, serial: u64) -> Self {
+        Bill {
+            owner,
+            amount,
+            serial,
+            expiry: None,
+        }
+    }
+
+    /// A bill that expires if it is not spent by the given height.
+    pub fn expiring(owner: User, amount: u64, serial: u64, expiry: u64) -> Self {
+        Bill {
+            expiry: Some(expiry),
+            ..Bill::new(owner, amount, serial)
+        }
+    }
+
+    /// Whether the bill has expired at the given height.
+    fn is_expired(&self, height: u64) -> bool {
+        self.expiry.is_some_and(|expiry| expiry < height)
+    }
+
+    pub fn amount(&self) -> u64 {
+        self.amount
+    }
 }
 
 /// The State of a digital cash system. Primarily just the set of currently circulating bills.,
@@ -28,6 +60,19 @@ pub struct State {
     bills: HashSet<Bill>,
     /// The next serial number to use when a bill is created.
     next_serial: u64,
+    /// The serials of bills that have been frozen and can not currently be spent.
+    frozen: HashSet<u64>,
+    /// The authority that may freeze and unfreeze bills, if any. Without one the
+    /// currency is entirely permissionless.
+    freeze_authority: Option<User>,
+    /// The only users that transfers may create bills for, if any. Without a whitelist anybody
+    /// may receive money, as in a permissionless currency.
+    allowed_recipients: Option<HashSet<User>>,
+    /// The most money that may ever be in circulation, if any. Mints that would exceed it are
+    /// rejected.
+    max_supply: Option<u64>,
+    /// The current block height, advanced by `Tick`. Bills expire relative to this clock.
+    current_height: u64,
 }
 
 impl State {
@@ -35,9 +80,54 @@ impl State {
         State {
             bills: HashSet::<Bill>::new(),
             next_serial: 0,
+            frozen: HashSet::new(),
+            freeze_authority: None,
+            allowed_recipients: None,
+            max_supply: None,
+            current_height: 0,
+        }
+    }
+
+    /// Configure the authority that may freeze and unfreeze bills, as in a regulated currency.
+    pub fn set_freeze_authority(&mut self, authority: User) {
+        self.freeze_authority = Some(authority);
+    }
+
+    /// Restrict transfers and mints to only create bills owned by the given users, as in a
+    /// permissioned stablecoin.
+    pub fn set_allowed_recipients(&mut self, recipients: impl IntoIterator<Item = User>) {
+        self.allowed_recipients = Some(recipients.into_iter().collect());
+    }
+
+    /// Whether transfers and mints may create bills owned by the given user.
+    pub fn may_receive(&self, user: User) -> bool {
+        self.allowed_recipients
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(&user))
+    }
+
+    /// Cap the total supply, as in a fixed supply currency.
+    pub fn set_max_supply(&mut self, max_supply: u64) {
+        self.max_supply = Some(max_supply);
+    }
+
+    /// Whether minting the given amount keeps the total supply within the cap. An amount that
+    /// could not even be calculated without overflowing, given as `None`, never fits under a cap.
+    fn within_supply_cap(&self, minted: Option<u64>) -> bool {
+        match self.max_supply {
+            None => true,
+            Some(cap) => minted
+                .zip(self.checked_total_supply())
+                .and_then(|(minted, supply)| supply.checked_add(minted))
+                .is_some_and(|supply| supply <= cap),
         }
     }
 
+    /// Whether the bill with the given serial is currently frozen.
+    pub fn is_frozen(&self, serial: u64) -> bool {
+        self.frozen.contains(&serial)
+    }
+
     pub fn set_serial(&mut self, serial: u64) {
         self.next_serial = serial;
     }
@@ -54,6 +144,183 @@ impl State {
         self.bills.insert(elem);
         self.increment_serial()
     }
+
+    /// Check the invariants every reachable state should satisfy: no circulating bill is worthless,
+    /// no two circulating bills share a serial, and the total supply does not overflow.
+    pub fn invariant_holds(&self) -> bool {
+        let serials: HashSet<u64> = self.bills.iter().map(|b| b.serial).collect();
+
+        serials.len() == self.bills.len()
+            && self.bills.iter().all(|b| b.amount > 0)
+            && self
+                .bills
+                .iter()
+                .try_fold(0u64, |acc, b| acc.checked_add(b.amount))
+                .is_some()
+    }
+
+    /// The total value of all circulating bills. It saturates at `u64::MAX` for a state built
+    /// with more value than fits, see `checked_total_supply`.
+    pub fn total_supply(&self) -> u64 {
+        self.checked_total_supply().unwrap_or(u64::MAX)
+    }
+
+    /// The total value of all circulating bills, or `None` if it overflows. Transitions never
+    /// create such a state, but one can be built directly from bills.
+    pub fn checked_total_supply(&self) -> Option<u64> {
+        self.bills
+            .iter()
+            .try_fold(0u64, |acc, b| acc.checked_add(b.amount))
+    }
+
+    /// The total value of all bills owned by the given user.
+    pub fn balance(&self, user: User) -> u64 {
+        self.bills
+            .iter()
+            .filter(|b| b.owner == user)
+            .map(|b| b.amount)
+            .sum()
+    }
+
+    /// The balance of every user, including a zero balance for users holding no bills.
+    pub fn balances(&self) -> BTreeMap<User, u64> {
+        let mut balances: BTreeMap<User, u64> = User::all().iter().map(|u| (*u, 0)).collect();
+        for bill in self.bills.iter() {
+            *balances.entry(bill.owner).or_insert(0) += bill.amount;
+        }
+        balances
+    }
+
+    /// Every user holding any money, along with how much they hold, richest first.
+    pub fn rich_list(&self) -> Vec<(User, u64)> {
+        let mut holdings: HashMap<User, u64> = HashMap::new();
+        for bill in self.bills.iter() {
+            *holdings.entry(bill.owner).or_insert(0) += bill.amount;
+        }
+
+        let mut rich_list: Vec<(User, u64)> = holdings.into_iter().collect();
+        rich_list.sort_by_key(|(_, amount)| std::cmp::Reverse(*amount));
+        rich_list
+    }
+
+    /// The fraction of the total supply held by the `k` richest users.
+    ///
+    /// This is a simple measure of how concentrated the wealth is. If `k` is at least the
+    /// number of holders, they hold everything and the result is 1.0. An empty ledger has
+    /// no concentration at all, so the result is 0.0.
+    pub fn wealth_concentration(&self, k: usize) -> f64 {
+        let total = self.total_supply();
+        if total == 0 {
+            return 0.0;
+        }
+
+        let top_k: u64 = self
+            .rich_list()
+            .iter()
+            .take(k)
+            .map(|(_, amount)| amount)
+            .sum();
+        top_k as f64 / total as f64
+    }
+
+    /// The circulating bills in serial order. Unlike the `HashSet` iteration order, this
+    /// order is deterministic, so it is suitable for building a Merkle tree.
+    fn sorted_bills(&self) -> Vec<&Bill> {
+        let mut bills: Vec<&Bill> = self.bills.iter().collect();
+        bills.sort_by_key(|b| b.serial);
+        bills
+    }
+
+    /// A Merkle root over the circulating bills. A light client holding only this root can check
+    /// that a bill is circulating using a proof from `prove_membership`. An empty set of bills has
+    /// a root of 0.
+    pub fn bills_root(&self) -> u64 {
+        let mut level: Vec<u64> = self.sorted_bills().into_iter().map(crate::hash).collect();
+        if level.is_empty() {
+            return 0;
+        }
+
+        while level.len() > 1 {
+            level = merkle_parents(&level);
+        }
+        level[0]
+    }
+
+    /// A Merkle path proving that the bill with the given serial is circulating, or `None` if it
+    /// is not. The path lists the sibling hashes from the leaf up to the root.
+    pub fn prove_membership(&self, serial: u64) -> Option<Vec<u64>> {
+        let bills = self.sorted_bills();
+        let mut index = bills.iter().position(|b| b.serial == serial)?;
+        let mut level: Vec<u64> = bills.into_iter().map(crate::hash).collect();
+        let mut proof = Vec::new();
+
+        while level.len() > 1 {
+            // An odd last node is paired with itself
+            let sibling = (index ^ 1).min(level.len() - 1);
+            proof.push(level[sibling]);
+            level = merkle_parents(&level);
+            index /= 2;
+        }
+        Some(proof)
+    }
+}
+
+/// Hash each pair of nodes of one level of a Merkle tree to form the level above. Pairs are hashed
+/// in sorted order so that a proof does not need to record which side each sibling is on. When the
+/// level has an odd number of nodes, the last one is paired with itself.
+fn merkle_parents(level: &[u64]) -> Vec<u64> {
+    level
+        .chunks(2)
+        .map(|pair| {
+            let (a, b) = (pair[0], *pair.last().unwrap());
+            crate::hash(&(a.min(b), a.max(b)))
+        })
+        .collect()
+}
+
+/// Check a Merkle proof from `State::prove_membership` showing that the given bill is part of the
+/// set of circulating bills committed to by the root.
+pub fn verify_membership(root: u64, bill: &Bill, proof: &[u64]) -> bool {
+    let computed = proof.iter().fold(crate::hash(bill), |node, sibling| {
+        crate::hash(&(node.min(*sibling), node.max(*sibling)))
+    });
+    computed == root
+}
+
+/// The bills a transition added and removed, as `(added, removed)`, each sorted by serial.
+/// Clients can apply these instead of replacing the full state.
+pub fn diff(before: &State, after: &State) -> (Vec<Bill>, Vec<Bill>) {
+    let sorted = |mut bills: Vec<Bill>| {
+        bills.sort_by_key(|b| b.serial);
+        bills
+    };
+
+    (
+        sorted(after.bills.difference(&before.bills).cloned().collect()),
+        sorted(before.bills.difference(&after.bills).cloned().collect()),
+    )
+}
+
+/// The bills are stored in a `HashSet` which does not implement `Hash` itself. We hash the bills
+/// in serial order so that equal states always produce the same hash, and therefore the same state root.
+impl Hash for State {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.sorted_bills().hash(state);
+        self.next_serial.hash(state);
+        let mut frozen: Vec<&u64> = self.frozen.iter().collect();
+        frozen.sort();
+        frozen.hash(state);
+        self.freeze_authority.hash(state);
+        let allowed_recipients: Option<Vec<&User>> =
+            self.allowed_recipients.as_ref().map(|allowed| {
+                let mut allowed: Vec<&User> = allowed.iter().collect();
+                allowed.sort();
+                allowed
+            });
+        allowed_recipients.hash(state);
+        self.max_supply.hash(state);
+        self.current_height.hash(state);
+    }
 }
 
 impl FromIterator<Bill> for State {
@@ -74,9 +341,19 @@ impl<const N: usize> From<[Bill; N]> for State {
 }
 
 /// The state transitions that users can make in a digital cash system
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CashTransaction {
     /// Mint a single new bill owned by the minter
     Mint { minter: User, amount: u64 },
+    /// Mint one new bill owned by the minter for each nonzero amount, with consecutive serials
+    MintBatch { minter: User, amounts: Vec<u64> },
+    /// Mint new money and distribute it in a single atomic step. One bill is created for each
+    /// recipient, with consecutive serials. If any amount is zero nothing is minted at all.
+    Airdrop {
+        minter: User,
+        distributions: Vec<(User, u64)>,
+    },
     /// Send some money from some users to other users. The money does not all need
     /// to come from the same user, and it does not all need to go to the same user.
     /// The total amount received must be less than or equal to the amount spent.
@@ -86,9 +363,66 @@ pub enum CashTransaction {
         spends: Vec<Bill>,
         receives: Vec<Bill>,
     },
+    /// Freeze a circulating bill so that it can not be spent. Only the configured
+    /// freeze authority may do this.
+    Freeze { authority: User, serial: u64 },
+    /// Make a frozen bill spendable again. Only the configured freeze authority may do this.
+    Unfreeze { authority: User, serial: u64 },
+    /// Advance the block height clock by one, removing any bills that have expired.
+    Tick,
+}
+
+impl CashTransaction {
+    /// The change in total supply this transaction causes if it is valid. Mints create value,
+    /// so their delta is positive. Transfers may destroy value, so their delta is negative or zero.
+    ///
+    /// The remaining transactions do not create or destroy value themselves. A `Tick` may remove
+    /// expired bills, but how much that destroys depends on the state rather than the transaction.
+    pub fn value_delta(&self) -> i128 {
+        let sum = |bills: &[Bill]| bills.iter().map(|b| b.amount as i128).sum::<i128>();
+        match self {
+            CashTransaction::Mint { amount, .. } => *amount as i128,
+            CashTransaction::MintBatch { amounts, .. } => {
+                amounts.iter().map(|amount| *amount as i128).sum()
+            }
+            CashTransaction::Airdrop { distributions, .. } => distributions
+                .iter()
+                .map(|(_, amount)| *amount as i128)
+                .sum(),
+            CashTransaction::Transfer { spends, receives } => sum(receives) - sum(spends),
+            CashTransaction::Freeze { .. }
+            | CashTransaction::Unfreeze { .. }
+            | CashTransaction::Tick => 0,
+        }
+    }
+
+    /// The implicit fee of this transaction, which is the value a transfer spends but does not
+    /// send to anybody. All other transactions pay no fee. Neither do transfers whose amounts
+    /// overflow, since those can never be valid anyway.
+    pub fn fee(&self) -> u64 {
+        match self {
+            CashTransaction::Transfer { spends, receives } => {
+                let total = |bills: &[Bill]| {
+                    bills
+                        .iter()
+                        .try_fold(0u64, |acc, b| acc.checked_add(b.amount()))
+                };
+                match (total(spends), total(receives)) {
+                    (Some(spent), Some(received)) => spent.saturating_sub(received),
+                    _ => 0,
+                }
+            }
+            CashTransaction::Mint { .. }
+            | CashTransaction::MintBatch { .. }
+            | CashTransaction::Airdrop { .. }
+            | CashTransaction::Freeze { .. }
+            | CashTransaction::Unfreeze { .. }
+            | CashTransaction::Tick => 0,
+        }
+    }
 }
 
-/// We model this system as a state machine with two possible transitions
+/// We model this system as a state machine with seven possible transitions
 impl StateMachine for DigitalCashSystem {
     type State = State;
     type Transition = CashTransaction;
@@ -96,7 +430,19 @@ impl StateMachine for DigitalCashSystem {
     fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
         match t {
             CashTransaction::Mint { minter, amount } => {
-                if *amount == 0 {
+                if *amount == 0
+                    || !starting_state.may_receive(*minter)
+                    || !starting_state.within_supply_cap(Some(*amount))
+                {
+                    return starting_state.clone();
+                }
+
+                // check overflow of the total supply
+                if starting_state
+                    .checked_total_supply()
+                    .and_then(|supply| supply.checked_add(*amount))
+                    .is_none()
+                {
                     return starting_state.clone();
                 }
 
@@ -105,10 +451,87 @@ impl StateMachine for DigitalCashSystem {
                     owner: *minter,
                     amount: *amount,
                     serial: new_state.next_serial(),
+                    expiry: None,
                 });
                 new_state
             }
 
+            CashTransaction::MintBatch { minter, amounts } => {
+                // check the minter is whitelisted
+                if !starting_state.may_receive(*minter) {
+                    return starting_state.clone();
+                }
+
+                // check overflow of the minted total
+                if starting_state
+                    .checked_total_supply()
+                    .and_then(|supply| {
+                        amounts
+                            .iter()
+                            .try_fold(supply, |acc, amount| acc.checked_add(*amount))
+                    })
+                    .is_none()
+                {
+                    return starting_state.clone();
+                }
+
+                // check the whole batch fits under the supply cap
+                let minted = amounts
+                    .iter()
+                    .try_fold(0u64, |acc, amount| acc.checked_add(*amount));
+                if !starting_state.within_supply_cap(minted) {
+                    return starting_state.clone();
+                }
+
+                let mut new_state = starting_state.clone();
+                for amount in amounts.iter().filter(|amount| **amount != 0) {
+                    new_state.add_bill(Bill::new(*minter, *amount, new_state.next_serial()));
+                }
+                new_state
+            }
+
+            CashTransaction::Airdrop { distributions, .. } => {
+                // check for Bills with output of 0
+                if distributions.iter().any(|(_, amount)| *amount == 0) {
+                    return starting_state.clone();
+                }
+
+                // check recipients are whitelisted
+                if distributions
+                    .iter()
+                    .any(|(recipient, _)| !starting_state.may_receive(*recipient))
+                {
+                    return starting_state.clone();
+                }
+
+                // check overflow of the minted total
+                if starting_state
+                    .checked_total_supply()
+                    .and_then(|supply| {
+                        distributions
+                            .iter()
+                            .try_fold(supply, |acc, (_, amount)| acc.checked_add(*amount))
+                    })
+                    .is_none()
+                {
+                    return starting_state.clone();
+                }
+
+                // check the whole airdrop fits under the supply cap
+                let minted = distributions
+                    .iter()
+                    .try_fold(0u64, |acc, (_, amount)| acc.checked_add(*amount));
+                if !starting_state.within_supply_cap(minted) {
+                    return starting_state.clone();
+                }
+
+                let mut new_state = starting_state.clone();
+                for (recipient, amount) in distributions {
+                    new_state.add_bill(Bill::new(*recipient, *amount, new_state.next_serial()));
+                }
+                new_state
+            }
+
             CashTransaction::Transfer { spends, receives } => {
                 // check serial max reached
                 if receives.iter().any(|b| b.serial == u64::MAX) {
@@ -130,11 +553,32 @@ impl StateMachine for DigitalCashSystem {
                     return starting_state.clone();
                 }
 
+                // check recipients are whitelisted
+                if receives
+                    .iter()
+                    .any(|b| !starting_state.may_receive(b.owner))
+                {
+                    return starting_state.clone();
+                }
+
                 // check if sends Bills exist in current State
                 if spends.iter().any(|b| !starting_state.bills.contains(b)) {
                     return starting_state.clone();
                 }
 
+                // check for frozen Bills
+                if spends.iter().any(|b| starting_state.is_frozen(b.serial)) {
+                    return starting_state.clone();
+                }
+
+                // check for expired Bills
+                if spends
+                    .iter()
+                    .any(|b| b.is_expired(starting_state.current_height))
+                {
+                    return starting_state.clone();
+                }
+
                 // check overflow
                 if has_overflow(spends, receives) {
                     return starting_state.clone();
@@ -158,8 +602,154 @@ impl StateMachine for DigitalCashSystem {
 
                 new_state
             }
+
+            CashTransaction::Freeze { authority, serial } => {
+                if starting_state.freeze_authority != Some(*authority)
+                    || !starting_state.bills.iter().any(|b| b.serial == *serial)
+                {
+                    return starting_state.clone();
+                }
+
+                let mut new_state = starting_state.clone();
+                new_state.frozen.insert(*serial);
+                new_state
+            }
+
+            CashTransaction::Unfreeze { authority, serial } => {
+                if starting_state.freeze_authority != Some(*authority) {
+                    return starting_state.clone();
+                }
+
+                let mut new_state = starting_state.clone();
+                new_state.frozen.remove(serial);
+                new_state
+            }
+
+            CashTransaction::Tick => {
+                let mut new_state = starting_state.clone();
+                new_state.current_height += 1;
+                let height = new_state.current_height;
+                new_state.bills.retain(|b| !b.is_expired(height));
+                new_state
+            }
+        }
+    }
+}
+
+/// Only the owners of the spent bills may sign a transfer, and only the minter may sign a mint.
+/// Use it with `Signed<DigitalCashSystem, OwnersSign>`. The remaining transitions carry their own
+/// authorization, or need none, so anybody may sign them.
+pub struct OwnersSign;
+
+impl Authorization<DigitalCashSystem> for OwnersSign {
+    fn is_authorized(_: &State, signer: &User, t: &CashTransaction) -> bool {
+        match t {
+            CashTransaction::Transfer { spends, .. } => spends.iter().all(|b| b.owner == *signer),
+            CashTransaction::Mint { minter, .. }
+            | CashTransaction::MintBatch { minter, .. }
+            | CashTransaction::Airdrop { minter, .. } => minter == signer,
+            CashTransaction::Freeze { .. }
+            | CashTransaction::Unfreeze { .. }
+            | CashTransaction::Tick => true,
+        }
+    }
+}
+
+/// A compact record of a digital cash transition. Rather than a snapshot of every circulating bill,
+/// only the bills that were removed and added, and the previous serial counter, are kept.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CashUndo {
+    /// Bills that were spent by the transition and must be restored
+    removed: Vec<Bill>,
+    /// Bills that were created by the transition and must be removed again
+    added: Vec<Bill>,
+    /// The serial counter before the transition
+    previous_serial: u64,
+    /// Serials whose frozen status was changed by the transition
+    toggled_frozen: Vec<u64>,
+    /// The block height before the transition
+    previous_height: u64,
+}
+
+impl ReversibleStateMachine for DigitalCashSystem {
+    type Undo = CashUndo;
+
+    fn next_state_with_undo(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> (Self::State, Self::Undo) {
+        let new_state = Self::next_state(starting_state, t);
+        let undo = CashUndo {
+            removed: starting_state
+                .bills
+                .difference(&new_state.bills)
+                .cloned()
+                .collect(),
+            added: new_state
+                .bills
+                .difference(&starting_state.bills)
+                .cloned()
+                .collect(),
+            previous_serial: starting_state.next_serial,
+            toggled_frozen: starting_state
+                .frozen
+                .symmetric_difference(&new_state.frozen)
+                .copied()
+                .collect(),
+            previous_height: starting_state.current_height,
+        };
+        (new_state, undo)
+    }
+
+    fn revert(state: &Self::State, undo: &Self::Undo) -> Self::State {
+        let mut reverted = state.clone();
+        for bill in &undo.added {
+            reverted.bills.remove(bill);
+        }
+        for bill in &undo.removed {
+            reverted.bills.insert(bill.clone());
+        }
+        reverted.next_serial = undo.previous_serial;
+        reverted.current_height = undo.previous_height;
+        for serial in &undo.toggled_frozen {
+            if !reverted.frozen.remove(serial) {
+                reverted.frozen.insert(*serial);
+            }
+        }
+        reverted
+    }
+}
+
+/// Roll back a `Transfer` without a recorded `CashUndo`, for example during a reorg. The spent
+/// bills are restored, the received bills are removed again, and the serial counter is reset to
+/// the given value from before the transfer.
+///
+/// Returns `None` if the reversal would be inconsistent with the post-state: a received bill is
+/// missing, a spent bill's serial is still in circulation, or the serial counter would move
+/// forward. Only transfers can be undone this way. Use `ReversibleStateMachine` for the rest.
+pub fn undo(post_state: &State, t: &CashTransaction, pre_serial: u64) -> Option<State> {
+    let CashTransaction::Transfer { spends, receives } = t else {
+        return None;
+    };
+
+    if pre_serial > post_state.next_serial || !receives.iter().all(|b| post_state.bills.contains(b))
+    {
+        return None;
+    }
+
+    let mut state = post_state.clone();
+    for bill in receives {
+        state.bills.remove(bill);
+    }
+    for bill in spends {
+        if state.bills.iter().any(|b| b.serial == bill.serial) {
+            return None;
         }
+        state.bills.insert(bill.clone());
     }
+    state.next_serial = pre_serial;
+
+    Some(state)
 }
 
 fn has_unique_serials(sends: &[Bill], receives: &[Bill]) -> bool {
@@ -193,6 +783,131 @@ fn has_overflow(spends: &[Bill], receives: &[Bill]) -> bool {
     spend_sum.is_none() || receive_sum.is_none()
 }
 
+/// The total value moved by a transaction. For a transfer this is the amount received,
+/// and for a mint it is the amount minted.
+fn value_moved(t: &CashTransaction) -> u64 {
+    match t {
+        CashTransaction::Mint { amount, .. } => *amount,
+        CashTransaction::MintBatch { amounts, .. } => amounts
+            .iter()
+            .fold(0u64, |acc, amount| acc.saturating_add(*amount)),
+        CashTransaction::Airdrop { distributions, .. } => distributions
+            .iter()
+            .fold(0u64, |acc, (_, amount)| acc.saturating_add(*amount)),
+        CashTransaction::Transfer { receives, .. } => receives
+            .iter()
+            .fold(0u64, |acc, b| acc.saturating_add(b.amount)),
+        CashTransaction::Freeze { .. }
+        | CashTransaction::Unfreeze { .. }
+        | CashTransaction::Tick => 0,
+    }
+}
+
+/// Select a subset of the given transactions that can all be included in a single block
+/// without double spending, trying to maximize the total value moved.
+///
+/// Finding the true optimum is a knapsack-like problem, so we use a greedy heuristic instead:
+/// transactions are considered from the highest value to the lowest, and each one is kept if
+/// it is still valid on top of the ones already kept. This is not always optimal, but it does
+/// prefer a single large transfer over several small ones that conflict with it.
+///
+/// Returns the indices of the selected transactions in the order they should be applied.
+fn select_max_value_block(start: &State, txs: Vec<CashTransaction>) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..txs.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(value_moved(&txs[i])));
+
+    let mut state = start.clone();
+    let mut selected = Vec::new();
+    for i in order {
+        let next = DigitalCashSystem::next_state(&state, &txs[i]);
+        // Invalid transactions, including double spends, leave the state untouched
+        if next != state {
+            state = next;
+            selected.push(i);
+        }
+    }
+    selected
+}
+
+/// Find the pairs of transactions in a batch that spend a common bill, identified by serial.
+///
+/// Applying the batch sequentially would silently drop the later of two such transfers, because
+/// the earlier one already removed the bill. Block level validation needs to catch them up front.
+///
+/// Returns the pairs of indices `(i, j)` with `i < j`, in ascending order.
+fn find_conflicts(txs: &[CashTransaction]) -> Vec<(usize, usize)> {
+    let spent_serials = |tx: &CashTransaction| -> HashSet<u64> {
+        match tx {
+            CashTransaction::Transfer { spends, .. } => spends.iter().map(|b| b.serial).collect(),
+            _ => HashSet::new(),
+        }
+    };
+    let spent: Vec<HashSet<u64>> = txs.iter().map(spent_serials).collect();
+
+    let mut conflicts = Vec::new();
+    for i in 0..spent.len() {
+        for j in i + 1..spent.len() {
+            if !spent[i].is_disjoint(&spent[j]) {
+                conflicts.push((i, j));
+            }
+        }
+    }
+    conflicts
+}
+
+/// A pool of pending transactions waiting to be included in a block, as a node would keep one.
+#[derive(Clone, Debug, Default)]
+pub struct Mempool {
+    pending: Vec<CashTransaction>,
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a transaction. It is not checked against any state until it is selected.
+    pub fn add(&mut self, tx: CashTransaction) {
+        self.pending.push(tx);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Select at most `max_transactions` of the queued transactions that can be applied to the
+    /// given state, as a revenue maximizing miner would. Transactions are considered by descending
+    /// fee, and in the order they were queued when their fees are equal.
+    ///
+    /// Each transaction is checked on top of the ones already selected, so the selection never
+    /// contains conflicts such as two transfers spending the same bill. Of two conflicting
+    /// transactions the one considered first wins.
+    pub fn select_valid(&self, state: &State, max_transactions: usize) -> Vec<CashTransaction> {
+        let mut by_fee: Vec<&CashTransaction> = self.pending.iter().collect();
+        by_fee.sort_by_key(|tx| std::cmp::Reverse(tx.fee()));
+
+        let mut state = state.clone();
+        let mut selected = Vec::new();
+        for tx in by_fee {
+            if selected.len() == max_transactions {
+                break;
+            }
+
+            let next = DigitalCashSystem::next_state(&state, tx);
+            // Invalid transactions, including double spends, leave the state untouched
+            if next != state {
+                state = next;
+                selected.push(tx.clone());
+            }
+        }
+        selected
+    }
+}
+
 #[test]
 fn sm_5_mint_new_cash() {
     let start = State::new();
@@ -208,6 +923,7 @@ fn sm_5_mint_new_cash() {
         owner: User::Alice,
         amount: 20,
         serial: 0,
+        expiry: None,
     }]);
     assert_eq!(end, expected);
 }
@@ -218,6 +934,7 @@ fn sm_5_overflow_receives_fails() {
         owner: User::Alice,
         amount: 42,
         serial: 0,
+        expiry: None,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
@@ -226,17 +943,20 @@ fn sm_5_overflow_receives_fails() {
                 owner: User::Alice,
                 amount: 42,
                 serial: 0,
+                expiry: None,
             }],
             receives: vec![
                 Bill {
                     owner: User::Alice,
                     amount: u64::MAX,
                     serial: 1,
+                    expiry: None,
                 },
                 Bill {
                     owner: User::Alice,
                     amount: 42,
                     serial: 2,
+                    expiry: None,
                 },
             ],
         },
@@ -245,6 +965,7 @@ fn sm_5_overflow_receives_fails() {
         owner: User::Alice,
         amount: 42,
         serial: 0,
+        expiry: None,
     }]);
     assert_eq!(end, expected);
 }
@@ -255,6 +976,7 @@ fn sm_5_empty_spend_fails() {
         owner: User::Alice,
         amount: 20,
         serial: 0,
+        expiry: None,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
@@ -264,6 +986,7 @@ fn sm_5_empty_spend_fails() {
                 owner: User::Alice,
                 amount: 15,
                 serial: 1,
+                expiry: None,
             }],
         },
     );
@@ -271,6 +994,7 @@ fn sm_5_empty_spend_fails() {
         owner: User::Alice,
         amount: 20,
         serial: 0,
+        expiry: None,
     }]);
     assert_eq!(end, expected);
 }
@@ -281,6 +1005,7 @@ fn sm_5_empty_receive_fails() {
         owner: User::Alice,
         amount: 20,
         serial: 0,
+        expiry: None,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
@@ -289,6 +1014,7 @@ fn sm_5_empty_receive_fails() {
                 owner: User::Alice,
                 amount: 20,
                 serial: 0,
+                expiry: None,
             }],
             receives: vec![],
         },
@@ -304,6 +1030,7 @@ fn sm_5_output_value_0_fails() {
         owner: User::Alice,
         amount: 20,
         serial: 0,
+        expiry: None,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
@@ -312,11 +1039,13 @@ fn sm_5_output_value_0_fails() {
                 owner: User::Alice,
                 amount: 20,
                 serial: 0,
+                expiry: None,
             }],
             receives: vec![Bill {
                 owner: User::Bob,
                 amount: 0,
                 serial: 1,
+                expiry: None,
             }],
         },
     );
@@ -324,6 +1053,7 @@ fn sm_5_output_value_0_fails() {
         owner: User::Alice,
         amount: 20,
         serial: 0,
+        expiry: None,
     }]);
     assert_eq!(end, expected);
 }
@@ -334,6 +1064,7 @@ fn sm_5_serial_number_already_seen_fails() {
         owner: User::Alice,
         amount: 20,
         serial: 0,
+        expiry: None,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
@@ -342,11 +1073,13 @@ fn sm_5_serial_number_already_seen_fails() {
                 owner: User::Alice,
                 amount: 20,
                 serial: 0,
+                expiry: None,
             }],
             receives: vec![Bill {
                 owner: User::Alice,
                 amount: 18,
                 serial: 0,
+                expiry: None,
             }],
         },
     );
@@ -354,6 +1087,7 @@ fn sm_5_serial_number_already_seen_fails() {
         owner: User::Alice,
         amount: 20,
         serial: 0,
+        expiry: None,
     }]);
     assert_eq!(end, expected);
 }
@@ -364,6 +1098,7 @@ fn sm_5_spending_and_receiving_same_bill_fails() {
         owner: User::Alice,
         amount: 20,
         serial: 0,
+        expiry: None,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
@@ -372,11 +1107,13 @@ fn sm_5_spending_and_receiving_same_bill_fails() {
                 owner: User::Alice,
                 amount: 20,
                 serial: 0,
+                expiry: None,
             }],
             receives: vec![Bill {
                 owner: User::Alice,
                 amount: 20,
                 serial: 0,
+                expiry: None,
             }],
         },
     );
@@ -384,6 +1121,7 @@ fn sm_5_spending_and_receiving_same_bill_fails() {
         owner: User::Alice,
         amount: 20,
         serial: 0,
+        expiry: None,
     }]);
     assert_eq!(end, expected);
 }
@@ -394,6 +1132,7 @@ fn sm_5_receiving_bill_with_incorrect_serial_fails() {
         owner: User::Alice,
         amount: 20,
         serial: 0,
+        expiry: None,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
@@ -402,17 +1141,20 @@ fn sm_5_receiving_bill_with_incorrect_serial_fails() {
                 owner: User::Alice,
                 amount: 20,
                 serial: 0,
+                expiry: None,
             }],
             receives: vec![
                 Bill {
                     owner: User::Alice,
                     amount: 10,
                     serial: u64::MAX,
+                    expiry: None,
                 },
                 Bill {
                     owner: User::Bob,
                     amount: 10,
                     serial: 4000,
+                    expiry: None,
                 },
             ],
         },
@@ -421,6 +1163,7 @@ fn sm_5_receiving_bill_with_incorrect_serial_fails() {
         owner: User::Alice,
         amount: 20,
         serial: 0,
+        expiry: None,
     }]);
     assert_eq!(end, expected);
 }
@@ -431,6 +1174,7 @@ fn sm_5_spending_bill_with_incorrect_amount_fails() {
         owner: User::Alice,
         amount: 20,
         serial: 0,
+        expiry: None,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
@@ -439,11 +1183,13 @@ fn sm_5_spending_bill_with_incorrect_amount_fails() {
                 owner: User::Alice,
                 amount: 40,
                 serial: 0,
+                expiry: None,
             }],
             receives: vec![Bill {
                 owner: User::Bob,
                 amount: 40,
                 serial: 1,
+                expiry: None,
             }],
         },
     );
@@ -451,6 +1197,7 @@ fn sm_5_spending_bill_with_incorrect_amount_fails() {
         owner: User::Alice,
         amount: 20,
         serial: 0,
+        expiry: None,
     }]);
     assert_eq!(end, expected);
 }
@@ -461,6 +1208,7 @@ fn sm_5_spending_same_bill_fails() {
         owner: User::Alice,
         amount: 40,
         serial: 0,
+        expiry: None,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
@@ -470,11 +1218,13 @@ fn sm_5_spending_same_bill_fails() {
                     owner: User::Alice,
                     amount: 40,
                     serial: 0,
+                    expiry: None,
                 },
                 Bill {
                     owner: User::Alice,
                     amount: 40,
                     serial: 0,
+                    expiry: None,
                 },
             ],
             receives: vec![
@@ -482,16 +1232,19 @@ fn sm_5_spending_same_bill_fails() {
                     owner: User::Bob,
                     amount: 20,
                     serial: 1,
+                    expiry: None,
                 },
                 Bill {
                     owner: User::Bob,
                     amount: 20,
                     serial: 2,
+                    expiry: None,
                 },
                 Bill {
                     owner: User::Alice,
                     amount: 40,
                     serial: 3,
+                    expiry: None,
                 },
             ],
         },
@@ -500,6 +1253,7 @@ fn sm_5_spending_same_bill_fails() {
         owner: User::Alice,
         amount: 40,
         serial: 0,
+        expiry: None,
     }]);
     assert_eq!(end, expected);
 }
@@ -511,11 +1265,13 @@ fn sm_5_spending_more_than_bill_fails() {
             owner: User::Alice,
             amount: 40,
             serial: 0,
+            expiry: None,
         },
         Bill {
             owner: User::Charlie,
             amount: 42,
             serial: 1,
+            expiry: None,
         },
     ]);
     let end = DigitalCashSystem::next_state(
@@ -526,11 +1282,13 @@ fn sm_5_spending_more_than_bill_fails() {
                     owner: User::Alice,
                     amount: 40,
                     serial: 0,
+                    expiry: None,
                 },
                 Bill {
                     owner: User::Charlie,
                     amount: 42,
                     serial: 1,
+                    expiry: None,
                 },
             ],
             receives: vec![
@@ -538,16 +1296,19 @@ fn sm_5_spending_more_than_bill_fails() {
                     owner: User::Bob,
                     amount: 20,
                     serial: 2,
+                    expiry: None,
                 },
                 Bill {
                     owner: User::Bob,
                     amount: 20,
                     serial: 3,
+                    expiry: None,
                 },
                 Bill {
                     owner: User::Alice,
                     amount: 52,
                     serial: 4,
+                    expiry: None,
                 },
             ],
         },
@@ -557,11 +1318,13 @@ fn sm_5_spending_more_than_bill_fails() {
             owner: User::Alice,
             amount: 40,
             serial: 0,
+            expiry: None,
         },
         Bill {
             owner: User::Charlie,
             amount: 42,
             serial: 1,
+            expiry: None,
         },
     ]);
     assert_eq!(end, expected);
@@ -573,6 +1336,7 @@ fn sm_5_spending_non_existent_bill_fails() {
         owner: User::Alice,
         amount: 32,
         serial: 0,
+        expiry: None,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
@@ -581,11 +1345,13 @@ fn sm_5_spending_non_existent_bill_fails() {
                 owner: User::Bob,
                 amount: 1000,
                 serial: 32,
+                expiry: None,
             }],
             receives: vec![Bill {
                 owner: User::Bob,
                 amount: 1000,
                 serial: 33,
+                expiry: None,
             }],
         },
     );
@@ -593,6 +1359,7 @@ fn sm_5_spending_non_existent_bill_fails() {
         owner: User::Alice,
         amount: 32,
         serial: 0,
+        expiry: None,
     }]);
     assert_eq!(end, expected);
 }
@@ -603,6 +1370,7 @@ fn sm_5_spending_from_alice_to_all() {
         owner: User::Alice,
         amount: 42,
         serial: 0,
+        expiry: None,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
@@ -611,22 +1379,26 @@ fn sm_5_spending_from_alice_to_all() {
                 owner: User::Alice,
                 amount: 42,
                 serial: 0,
+                expiry: None,
             }],
             receives: vec![
                 Bill {
                     owner: User::Alice,
                     amount: 10,
                     serial: 1,
+                    expiry: None,
                 },
                 Bill {
                     owner: User::Bob,
                     amount: 10,
                     serial: 2,
+                    expiry: None,
                 },
                 Bill {
                     owner: User::Charlie,
                     amount: 10,
                     serial: 3,
+                    expiry: None,
                 },
             ],
         },
@@ -636,16 +1408,19 @@ fn sm_5_spending_from_alice_to_all() {
             owner: User::Alice,
             amount: 10,
             serial: 1,
+            expiry: None,
         },
         Bill {
             owner: User::Bob,
             amount: 10,
             serial: 2,
+            expiry: None,
         },
         Bill {
             owner: User::Charlie,
             amount: 10,
             serial: 3,
+            expiry: None,
         },
     ]);
     expected.set_serial(4);
@@ -658,6 +1433,7 @@ fn sm_5_spending_from_bob_to_all() {
         owner: User::Bob,
         amount: 42,
         serial: 0,
+        expiry: None,
     }]);
     let end = DigitalCashSystem::next_state(
         &start,
@@ -666,22 +1442,26 @@ fn sm_5_spending_from_bob_to_all() {
                 owner: User::Bob,
                 amount: 42,
                 serial: 0,
+                expiry: None,
             }],
             receives: vec![
                 Bill {
                     owner: User::Alice,
                     amount: 10,
                     serial: 1,
+                    expiry: None,
                 },
                 Bill {
                     owner: User::Bob,
                     amount: 10,
                     serial: 2,
+                    expiry: None,
                 },
                 Bill {
                     owner: User::Charlie,
                     amount: 22,
                     serial: 3,
+                    expiry: None,
                 },
             ],
         },
@@ -691,16 +1471,19 @@ fn sm_5_spending_from_bob_to_all() {
             owner: User::Alice,
             amount: 10,
             serial: 1,
+            expiry: None,
         },
         Bill {
             owner: User::Bob,
             amount: 10,
             serial: 2,
+            expiry: None,
         },
         Bill {
             owner: User::Charlie,
             amount: 22,
             serial: 3,
+            expiry: None,
         },
     ]);
     expected.set_serial(4);
@@ -714,11 +1497,13 @@ fn sm_5_spending_from_charlie_to_all() {
             owner: User::Charlie,
             amount: 68,
             serial: 54,
+            expiry: None,
         },
         Bill {
             owner: User::Alice,
             amount: 4000,
             serial: 58,
+            expiry: None,
         },
     ]);
     start.set_serial(59);
@@ -729,22 +1514,26 @@ fn sm_5_spending_from_charlie_to_all() {
                 owner: User::Charlie,
                 amount: 68,
                 serial: 54,
+                expiry: None,
             }],
             receives: vec![
                 Bill {
                     owner: User::Alice,
                     amount: 42,
                     serial: 59,
+                    expiry: None,
                 },
                 Bill {
                     owner: User::Bob,
                     amount: 5,
                     serial: 60,
+                    expiry: None,
                 },
                 Bill {
                     owner: User::Charlie,
                     amount: 5,
                     serial: 61,
+                    expiry: None,
                 },
             ],
         },
@@ -754,23 +1543,1107 @@ fn sm_5_spending_from_charlie_to_all() {
             owner: User::Alice,
             amount: 4000,
             serial: 58,
+            expiry: None,
         },
         Bill {
             owner: User::Alice,
             amount: 42,
             serial: 59,
+            expiry: None,
         },
         Bill {
             owner: User::Bob,
             amount: 5,
             serial: 60,
+            expiry: None,
         },
         Bill {
             owner: User::Charlie,
             amount: 5,
             serial: 61,
+            expiry: None,
         },
     ]);
     expected.set_serial(62);
     assert_eq!(end, expected);
 }
+
+#[test]
+fn sm_5_select_max_value_block_prefers_high_value_transfer() {
+    let start = State::from([
+        Bill {
+            owner: User::Alice,
+            amount: 60,
+            serial: 0,
+            expiry: None,
+        },
+        Bill {
+            owner: User::Alice,
+            amount: 40,
+            serial: 1,
+            expiry: None,
+        },
+    ]);
+    let alice_60 = Bill {
+        owner: User::Alice,
+        amount: 60,
+        serial: 0,
+        expiry: None,
+    };
+    let alice_40 = Bill {
+        owner: User::Alice,
+        amount: 40,
+        serial: 1,
+        expiry: None,
+    };
+    let txs = vec![
+        CashTransaction::Transfer {
+            spends: vec![alice_60.clone()],
+            receives: vec![Bill {
+                owner: User::Bob,
+                amount: 50,
+                serial: 2,
+                expiry: None,
+            }],
+        },
+        CashTransaction::Transfer {
+            spends: vec![alice_40.clone()],
+            receives: vec![Bill {
+                owner: User::Charlie,
+                amount: 40,
+                serial: 3,
+                expiry: None,
+            }],
+        },
+        CashTransaction::Transfer {
+            spends: vec![alice_60, alice_40],
+            receives: vec![Bill {
+                owner: User::Dave,
+                amount: 100,
+                serial: 4,
+                expiry: None,
+            }],
+        },
+    ];
+
+    assert_eq!(select_max_value_block(&start, txs), vec![2]);
+}
+
+#[test]
+fn sm_5_wealth_concentration_skewed() {
+    let state = State::from([
+        Bill {
+            owner: User::Alice,
+            amount: 80,
+            serial: 0,
+            expiry: None,
+        },
+        Bill {
+            owner: User::Bob,
+            amount: 10,
+            serial: 1,
+            expiry: None,
+        },
+        Bill {
+            owner: User::Charlie,
+            amount: 5,
+            serial: 2,
+            expiry: None,
+        },
+        Bill {
+            owner: User::Alice,
+            amount: 5,
+            serial: 3,
+            expiry: None,
+        },
+    ]);
+
+    assert_eq!(state.rich_list()[0], (User::Alice, 85));
+    assert_eq!(state.wealth_concentration(1), 0.85);
+    assert_eq!(state.wealth_concentration(10), 1.0);
+}
+
+#[test]
+fn sm_5_wealth_concentration_uniform() {
+    let state = State::from([
+        Bill {
+            owner: User::Alice,
+            amount: 25,
+            serial: 0,
+            expiry: None,
+        },
+        Bill {
+            owner: User::Bob,
+            amount: 25,
+            serial: 1,
+            expiry: None,
+        },
+        Bill {
+            owner: User::Charlie,
+            amount: 25,
+            serial: 2,
+            expiry: None,
+        },
+        Bill {
+            owner: User::Dave,
+            amount: 25,
+            serial: 3,
+            expiry: None,
+        },
+    ]);
+
+    assert_eq!(state.wealth_concentration(1), 0.25);
+    assert_eq!(State::new().wealth_concentration(1), 0.0);
+}
+
+#[test]
+fn sm_5_apply_with_undo_restores_prior_state() {
+    let start = State::from([Bill {
+        owner: User::Alice,
+        amount: 20,
+        serial: 0,
+        expiry: None,
+    }]);
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill {
+            owner: User::Alice,
+            amount: 20,
+            serial: 0,
+            expiry: None,
+        }],
+        receives: vec![Bill {
+            owner: User::Bob,
+            amount: 20,
+            serial: 1,
+            expiry: None,
+        }],
+    };
+
+    super::assert_deterministic::<DigitalCashSystem>(&start, &transfer);
+    let (end, snapshot) = super::apply_with_undo::<DigitalCashSystem>(&start, &transfer);
+    assert_ne!(end, start);
+    assert_eq!(snapshot, start);
+
+    let (end, undo) = DigitalCashSystem::next_state_with_undo(&start, &transfer);
+    assert_eq!(undo.removed.len(), 1);
+    assert_eq!(undo.added.len(), 1);
+    assert_eq!(DigitalCashSystem::revert(&end, &undo), start);
+}
+
+#[test]
+fn sm_5_apply_all_matches_manual_chaining() {
+    let transitions = vec![
+        CashTransaction::Mint {
+            minter: User::Alice,
+            amount: 20,
+        },
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 15, 1)],
+        },
+        CashTransaction::Mint {
+            minter: User::Charlie,
+            amount: 5,
+        },
+    ];
+
+    let start = State::new();
+    let mut manual = start.clone();
+    for t in transitions.iter() {
+        super::assert_deterministic::<DigitalCashSystem>(&manual, t);
+        manual = DigitalCashSystem::next_state(&manual, t);
+    }
+
+    assert_eq!(DigitalCashSystem::apply_all(&start, &transitions), manual);
+    assert_eq!(DigitalCashSystem::apply_all(&start, &[]), start);
+}
+
+#[test]
+fn sm_5_invalid_transfer_is_noop() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+
+    let overspend = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 21, 1)],
+    };
+    assert!(DigitalCashSystem::is_noop(&start, &overspend));
+
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+    };
+    assert!(!DigitalCashSystem::is_noop(&start, &transfer));
+}
+
+#[test]
+fn sm_5_frozen_bill_cannot_be_spent() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0)]);
+    start.set_freeze_authority(User::Noah);
+    let spend = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+    };
+
+    let frozen = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Freeze {
+            authority: User::Noah,
+            serial: 0,
+        },
+    );
+    assert!(frozen.is_frozen(0));
+    assert!(DigitalCashSystem::is_noop(&frozen, &spend));
+
+    let unfrozen = DigitalCashSystem::next_state(
+        &frozen,
+        &CashTransaction::Unfreeze {
+            authority: User::Noah,
+            serial: 0,
+        },
+    );
+    assert!(!unfrozen.is_frozen(0));
+    assert_eq!(unfrozen, start);
+    assert_eq!(
+        DigitalCashSystem::next_state(&unfrozen, &spend).balance(User::Bob),
+        20
+    );
+}
+
+#[test]
+fn sm_5_only_freeze_authority_can_freeze() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let freeze = CashTransaction::Freeze {
+        authority: User::Eve,
+        serial: 0,
+    };
+
+    // Without a configured authority nobody can freeze
+    assert!(DigitalCashSystem::is_noop(&start, &freeze));
+
+    start.set_freeze_authority(User::Noah);
+    assert!(DigitalCashSystem::is_noop(&start, &freeze));
+
+    let frozen = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Freeze {
+            authority: User::Noah,
+            serial: 0,
+        },
+    );
+    let unfreeze = CashTransaction::Unfreeze {
+        authority: User::Eve,
+        serial: 0,
+    };
+    assert!(DigitalCashSystem::is_noop(&frozen, &unfreeze));
+
+    // Bills that do not exist can not be frozen in advance
+    assert!(DigitalCashSystem::is_noop(
+        &start,
+        &CashTransaction::Freeze {
+            authority: User::Noah,
+            serial: 7,
+        }
+    ));
+}
+
+#[test]
+fn sm_5_transfer_to_allowed_recipient() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0)]);
+    start.set_allowed_recipients([User::Alice, User::Bob]);
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 15, 1), Bill::new(User::Alice, 5, 2)],
+        },
+    );
+
+    assert_eq!(end.balance(User::Bob), 15);
+    assert_eq!(end.balance(User::Alice), 5);
+}
+
+#[test]
+fn sm_5_mint_to_blocked_minter_fails() {
+    let mut start = State::new();
+    start.set_allowed_recipients([User::Alice]);
+
+    assert!(!DigitalCashSystem::is_noop(
+        &start,
+        &CashTransaction::Mint {
+            minter: User::Alice,
+            amount: 20,
+        }
+    ));
+    assert!(DigitalCashSystem::is_noop(
+        &start,
+        &CashTransaction::Mint {
+            minter: User::Eve,
+            amount: 20,
+        }
+    ));
+    assert!(DigitalCashSystem::is_noop(
+        &start,
+        &CashTransaction::MintBatch {
+            minter: User::Eve,
+            amounts: vec![5, 10],
+        }
+    ));
+}
+
+#[test]
+fn sm_5_transfer_to_blocked_recipient_fails() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 15, 1), Bill::new(User::Eve, 5, 2)],
+    };
+
+    // Without a whitelist anybody may receive
+    assert!(start.may_receive(User::Eve));
+    assert!(!DigitalCashSystem::is_noop(&start, &transfer));
+
+    start.set_allowed_recipients([User::Alice, User::Bob]);
+    assert!(!start.may_receive(User::Eve));
+    assert!(DigitalCashSystem::is_noop(&start, &transfer));
+}
+
+#[test]
+fn sm_5_freeze_can_be_undone() {
+    let mut start = State::from([Bill::new(User::Alice, 20, 0)]);
+    start.set_freeze_authority(User::Noah);
+    let freeze = CashTransaction::Freeze {
+        authority: User::Noah,
+        serial: 0,
+    };
+
+    let (end, undo) = DigitalCashSystem::next_state_with_undo(&start, &freeze);
+    assert!(end.is_frozen(0));
+    assert_eq!(DigitalCashSystem::revert(&end, &undo), start);
+}
+
+#[test]
+fn sm_5_bill_expires_after_enough_ticks() {
+    let start = State::from([
+        Bill::expiring(User::Alice, 20, 0, 2),
+        Bill::new(User::Bob, 10, 1),
+    ]);
+
+    let trace = DigitalCashSystem::trace(
+        &start,
+        &[
+            CashTransaction::Tick,
+            CashTransaction::Tick,
+            CashTransaction::Tick,
+        ],
+    );
+    assert_eq!(trace[1].balance(User::Alice), 20);
+    assert_eq!(trace[2].balance(User::Alice), 0);
+    // Bills without an expiry are unaffected
+    assert_eq!(trace[2].balance(User::Bob), 10);
+}
+
+#[test]
+fn sm_5_bill_spent_just_before_expiry() {
+    let start = State::from([Bill::expiring(User::Alice, 20, 0, 2)]);
+    let at_expiry =
+        DigitalCashSystem::apply_all(&start, &[CashTransaction::Tick, CashTransaction::Tick]);
+    let spend = CashTransaction::Transfer {
+        spends: vec![Bill::expiring(User::Alice, 20, 0, 2)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+    };
+
+    let spent = DigitalCashSystem::next_state(&at_expiry, &spend);
+    assert_eq!(spent.balance(User::Bob), 20);
+
+    // The received bill does not expire, so it survives the next tick
+    let later = DigitalCashSystem::next_state(&spent, &CashTransaction::Tick);
+    assert_eq!(later.balance(User::Bob), 20);
+}
+
+#[test]
+fn sm_5_expired_bill_cannot_be_spent() {
+    let mut start = State::from([Bill::expiring(User::Alice, 20, 0, 0)]);
+    // Simulate a clock that has passed the expiry without the bill having been removed yet
+    start.current_height = 1;
+    let spend = CashTransaction::Transfer {
+        spends: vec![Bill::expiring(User::Alice, 20, 0, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+    };
+
+    assert!(DigitalCashSystem::is_noop(&start, &spend));
+}
+
+#[test]
+fn sm_5_bills_root_changes_when_bill_added() {
+    let state = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 10, 1)]);
+    let minted = DigitalCashSystem::next_state(
+        &state,
+        &CashTransaction::Mint {
+            minter: User::Charlie,
+            amount: 5,
+        },
+    );
+
+    assert_ne!(state.bills_root(), minted.bills_root());
+    assert_eq!(state.bills_root(), state.clone().bills_root());
+    assert_eq!(State::new().bills_root(), 0);
+}
+
+#[test]
+fn sm_5_membership_proof_verifies() {
+    let bills: Vec<Bill> = (0..5).map(|i| Bill::new(User::Alice, 10 + i, i)).collect();
+    let state = State::from_iter(bills.clone());
+    let root = state.bills_root();
+
+    for bill in bills.iter() {
+        let proof = state.prove_membership(bill.serial).unwrap();
+        assert!(verify_membership(root, bill, &proof));
+    }
+
+    // A proof does not verify for a different bill, and missing bills have no proof
+    let proof = state.prove_membership(0).unwrap();
+    assert!(!verify_membership(
+        root,
+        &Bill::new(User::Eve, 10, 0),
+        &proof
+    ));
+    assert!(state.prove_membership(9).is_none());
+}
+
+#[test]
+fn sm_5_mint_batch_skips_zero_amounts() {
+    let mut start = State::new();
+    start.set_serial(3);
+
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::MintBatch {
+            minter: User::Alice,
+            amounts: vec![5, 0, 7, 0, 9],
+        },
+    );
+
+    let mut expected = State::from([
+        Bill::new(User::Alice, 5, 3),
+        Bill::new(User::Alice, 7, 4),
+        Bill::new(User::Alice, 9, 5),
+    ]);
+    expected.set_serial(6);
+    assert_eq!(end, expected);
+    assert_eq!(end.next_serial(), start.next_serial() + 3);
+}
+
+#[test]
+fn sm_5_mint_batch_of_zeros_is_noop() {
+    let start = State::from([Bill::new(User::Bob, 10, 0)]);
+
+    assert!(DigitalCashSystem::is_noop(
+        &start,
+        &CashTransaction::MintBatch {
+            minter: User::Alice,
+            amounts: vec![0, 0],
+        }
+    ));
+    assert!(DigitalCashSystem::is_noop(
+        &start,
+        &CashTransaction::MintBatch {
+            minter: User::Alice,
+            amounts: vec![],
+        }
+    ));
+}
+
+#[test]
+fn sm_5_mint_overflowing_supply_is_noop() {
+    let start = DigitalCashSystem::next_state(
+        &State::new(),
+        &CashTransaction::Mint {
+            minter: User::Alice,
+            amount: u64::MAX,
+        },
+    );
+    assert_eq!(start.total_supply(), u64::MAX);
+
+    assert!(DigitalCashSystem::is_noop(
+        &start,
+        &CashTransaction::Mint {
+            minter: User::Bob,
+            amount: 1,
+        }
+    ));
+}
+
+#[test]
+fn sm_5_mints_on_overflowed_supply_are_rejected() {
+    let mut start = State::from([
+        Bill::new(User::Alice, u64::MAX, 0),
+        Bill::new(User::Bob, u64::MAX, 1),
+    ]);
+    assert_eq!(start.checked_total_supply(), None);
+    assert_eq!(start.total_supply(), u64::MAX);
+
+    let batch = CashTransaction::MintBatch {
+        minter: User::Charlie,
+        amounts: vec![1],
+    };
+    assert!(DigitalCashSystem::is_noop(&start, &batch));
+
+    start.set_max_supply(u64::MAX);
+    assert!(DigitalCashSystem::is_noop(
+        &start,
+        &CashTransaction::Mint {
+            minter: User::Charlie,
+            amount: 1,
+        }
+    ));
+}
+
+#[test]
+fn sm_5_mint_batch_overflowing_supply_is_noop() {
+    let start = State::from([Bill::new(User::Bob, 10, 0)]);
+
+    // Without a supply cap the total must still fit
+    assert!(DigitalCashSystem::is_noop(
+        &start,
+        &CashTransaction::MintBatch {
+            minter: User::Alice,
+            amounts: vec![u64::MAX - 10, 1],
+        }
+    ));
+    assert!(DigitalCashSystem::is_noop(
+        &start,
+        &CashTransaction::MintBatch {
+            minter: User::Alice,
+            amounts: vec![u64::MAX, u64::MAX],
+        }
+    ));
+}
+
+#[test]
+fn sm_5_airdrop_to_many_recipients() {
+    let mut start = State::from([Bill::new(User::Eve, 10, 0)]);
+    start.set_serial(4);
+    let airdrop = CashTransaction::Airdrop {
+        minter: User::Noah,
+        distributions: vec![(User::Alice, 5), (User::Bob, 7), (User::Alice, 3)],
+    };
+
+    let end = DigitalCashSystem::next_state(&start, &airdrop);
+
+    let mut expected = State::from([
+        Bill::new(User::Eve, 10, 0),
+        Bill::new(User::Alice, 5, 4),
+        Bill::new(User::Bob, 7, 5),
+        Bill::new(User::Alice, 3, 6),
+    ]);
+    expected.set_serial(7);
+    assert_eq!(end, expected);
+    assert_eq!(end.total_supply(), start.total_supply() + 15);
+    assert_eq!(airdrop.value_delta(), 15);
+}
+
+#[test]
+fn sm_5_airdrop_with_zero_amount_is_rejected() {
+    let start = State::from([Bill::new(User::Eve, 10, 0)]);
+
+    assert!(DigitalCashSystem::is_noop(
+        &start,
+        &CashTransaction::Airdrop {
+            minter: User::Noah,
+            distributions: vec![(User::Alice, 5), (User::Bob, 0), (User::Charlie, 3)],
+        }
+    ));
+}
+
+#[test]
+fn sm_5_signed_transfer_by_owner() {
+    type SignedCash = super::Signed<DigitalCashSystem, OwnersSign>;
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+    };
+
+    let end = SignedCash::next_state(
+        &start,
+        &super::SignedTransition {
+            signer: User::Alice,
+            transition: transfer.clone(),
+        },
+    );
+    assert_eq!(end, DigitalCashSystem::next_state(&start, &transfer));
+}
+
+#[test]
+fn sm_5_signed_transfer_by_non_owner_fails() {
+    type SignedCash = super::Signed<DigitalCashSystem, OwnersSign>;
+    let start = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Eve, 5, 1)]);
+
+    // Eve owns one of the spent bills, but not the other
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0), Bill::new(User::Eve, 5, 1)],
+        receives: vec![Bill::new(User::Eve, 25, 2)],
+    };
+    assert!(SignedCash::is_noop(
+        &start,
+        &super::SignedTransition {
+            signer: User::Eve,
+            transition: transfer,
+        }
+    ));
+}
+
+#[test]
+fn sm_5_state_roots_change_with_state() {
+    let mint = CashTransaction::Mint {
+        minter: User::Alice,
+        amount: 20,
+    };
+    let double_spend = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Bob, 20, 0)],
+        receives: vec![Bill::new(User::Charlie, 20, 1)],
+    };
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+    };
+    let bodies = vec![
+        vec![mint.clone()],
+        vec![],
+        vec![double_spend],
+        vec![transfer.clone()],
+    ];
+
+    let roots = super::state_roots::<DigitalCashSystem>(State::new(), &bodies);
+
+    assert_eq!(roots.len(), bodies.len());
+    assert_ne!(roots[0], crate::hash(&State::new()));
+    // Neither an empty block nor a block of invalid transitions changes the state
+    assert_eq!(roots[1], roots[0]);
+    assert_eq!(roots[2], roots[0]);
+    assert_ne!(roots[3], roots[2]);
+    assert_eq!(
+        roots[3],
+        crate::hash(&DigitalCashSystem::apply_all(
+            &State::new(),
+            &[mint, transfer]
+        ))
+    );
+}
+
+#[test]
+fn sm_5_mint_up_to_max_supply() {
+    let mut start = State::from([Bill::new(User::Alice, 60, 0)]);
+    start.set_max_supply(100);
+
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Mint {
+            minter: User::Bob,
+            amount: 40,
+        },
+    );
+    assert_eq!(end.total_supply(), 100);
+
+    // Once the cap is reached, nothing more can be minted
+    assert!(DigitalCashSystem::is_noop(
+        &end,
+        &CashTransaction::Mint {
+            minter: User::Bob,
+            amount: 1,
+        }
+    ));
+}
+
+#[test]
+fn sm_5_mint_exceeding_max_supply_is_rejected_wholesale() {
+    let mut start = State::from([Bill::new(User::Alice, 60, 0)]);
+    start.set_max_supply(100);
+
+    assert!(DigitalCashSystem::is_noop(
+        &start,
+        &CashTransaction::Mint {
+            minter: User::Bob,
+            amount: 41,
+        }
+    ));
+    // A batch is not minted partially up to the cap
+    assert!(DigitalCashSystem::is_noop(
+        &start,
+        &CashTransaction::MintBatch {
+            minter: User::Bob,
+            amounts: vec![30, 20],
+        }
+    ));
+    assert!(DigitalCashSystem::is_noop(
+        &start,
+        &CashTransaction::Airdrop {
+            minter: User::Noah,
+            distributions: vec![(User::Bob, 30), (User::Charlie, 20)],
+        }
+    ));
+}
+
+#[test]
+fn sm_5_filter_valid_transitions() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let transitions = [
+        // Bob can not spend Alice's bill
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Bob, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 1)],
+        },
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 1)],
+        },
+        // Receiving more than is spent
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 21, 1)],
+        },
+    ];
+
+    assert_eq!(
+        DigitalCashSystem::filter_valid(&start, &transitions),
+        vec![1]
+    );
+}
+
+#[test]
+fn sm_5_value_delta() {
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 15, 1)],
+    };
+    assert_eq!(transfer.value_delta(), -5);
+    assert_eq!(
+        CashTransaction::MintBatch {
+            minter: User::Alice,
+            amounts: vec![1, 2, 3],
+        }
+        .value_delta(),
+        6
+    );
+    assert_eq!(CashTransaction::Tick.value_delta(), 0);
+}
+
+#[test]
+fn sm_5_supply_only_grows_through_mint() {
+    // A tiny deterministic pseudo random number generator so the test is reproducible
+    let mut seed = 42u64;
+    let mut next = |bound: u64| {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 33) % bound
+    };
+    let users = [User::Alice, User::Bob, User::Charlie];
+
+    let mut state = State::new();
+    for _ in 0..200 {
+        let t = match next(3) {
+            0 => CashTransaction::Mint {
+                minter: users[next(3) as usize],
+                amount: next(50),
+            },
+            1 => CashTransaction::Tick,
+            _ => {
+                let spends: Vec<Bill> = state.sorted_bills().into_iter().take(2).cloned().collect();
+                // Honest authors give the new bills the next unused serials
+                let receives = (0..next(3))
+                    .map(|i| {
+                        Bill::new(
+                            users[next(3) as usize],
+                            next(40) + 1,
+                            state.next_serial() + i,
+                        )
+                    })
+                    .collect();
+                CashTransaction::Transfer { spends, receives }
+            }
+        };
+
+        let next_state = DigitalCashSystem::next_state(&state, &t);
+        assert!(next_state.invariant_holds());
+
+        let supply_change = next_state.total_supply() as i128 - state.total_supply() as i128;
+        if next_state != state {
+            match t {
+                CashTransaction::Mint { .. } | CashTransaction::Transfer { .. } => {
+                    assert_eq!(supply_change, t.value_delta())
+                }
+                _ => assert!(supply_change <= 0),
+            }
+        }
+        if !matches!(t, CashTransaction::Mint { .. }) {
+            assert!(supply_change <= 0);
+        }
+
+        state = next_state;
+    }
+}
+
+#[test]
+fn sm_5_mempool_selects_only_one_of_two_double_spends() {
+    let bill = Bill::new(User::Alice, 20, 0);
+    let start = State::from([bill.clone()]);
+    let to_bob = CashTransaction::Transfer {
+        spends: vec![bill.clone()],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+    };
+    let to_charlie = CashTransaction::Transfer {
+        spends: vec![bill],
+        receives: vec![Bill::new(User::Charlie, 20, 1)],
+    };
+
+    let mut mempool = Mempool::new();
+    mempool.add(to_bob.clone());
+    mempool.add(to_charlie);
+    mempool.add(CashTransaction::Mint {
+        minter: User::Dave,
+        amount: 5,
+    });
+
+    assert_eq!(mempool.len(), 3);
+    assert_eq!(
+        mempool.select_valid(&start, usize::MAX),
+        vec![
+            to_bob,
+            CashTransaction::Mint {
+                minter: User::Dave,
+                amount: 5,
+            },
+        ]
+    );
+}
+
+#[test]
+fn sm_5_mempool_skips_invalid_transactions() {
+    let mut mempool = Mempool::new();
+    assert!(mempool.is_empty());
+
+    mempool.add(CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+    });
+
+    assert!(mempool.select_valid(&State::new(), usize::MAX).is_empty());
+}
+
+#[test]
+fn sm_5_mempool_handles_overflowing_fee() {
+    let overflowing = CashTransaction::Transfer {
+        spends: vec![
+            Bill::new(User::Alice, u64::MAX, 0),
+            Bill::new(User::Alice, u64::MAX, 1),
+        ],
+        receives: vec![Bill::new(User::Bob, 20, 2)],
+    };
+    assert_eq!(overflowing.fee(), 0);
+
+    let mint = CashTransaction::Mint {
+        minter: User::Bob,
+        amount: 5,
+    };
+    let mut mempool = Mempool::new();
+    mempool.add(overflowing);
+    mempool.add(mint.clone());
+
+    assert_eq!(mempool.select_valid(&State::new(), usize::MAX), vec![mint]);
+}
+
+#[test]
+fn sm_5_balances_cover_every_user() {
+    let state = State::from([
+        Bill::new(User::Alice, 20, 0),
+        Bill::new(User::Alice, 5, 1),
+        Bill::new(User::Bob, 10, 2),
+    ]);
+    let balances = state.balances();
+
+    assert!(User::all().iter().all(|u| balances.contains_key(u)));
+    assert_eq!(balances.len(), User::all().len());
+    assert_eq!(balances[&User::Alice], 25);
+    assert_eq!(balances[&User::Bob], 10);
+    assert_eq!(balances[&User::Charlie], 0);
+    assert_eq!(balances.values().sum::<u64>(), state.total_supply());
+}
+
+#[test]
+fn sm_5_mempool_prefers_high_fee_transactions() {
+    let start = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 20, 1)]);
+    let low_fee = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Charlie, 19, 2)],
+    };
+    let high_fee = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Bob, 20, 1)],
+        receives: vec![Bill::new(User::Charlie, 15, 2)],
+    };
+    assert_eq!(low_fee.fee(), 1);
+    assert_eq!(high_fee.fee(), 5);
+
+    let mut mempool = Mempool::new();
+    mempool.add(low_fee.clone());
+    mempool.add(high_fee.clone());
+
+    // With room for a single transaction, the high fee one is selected
+    assert_eq!(mempool.select_valid(&start, 1), vec![high_fee.clone()]);
+    assert!(mempool.select_valid(&start, 0).is_empty());
+}
+
+#[test]
+fn sm_5_mempool_orders_selection_by_fee() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let mint = CashTransaction::Mint {
+        minter: User::Bob,
+        amount: 5,
+    };
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Charlie, 10, 1)],
+    };
+
+    let mut mempool = Mempool::new();
+    mempool.add(mint.clone());
+    mempool.add(transfer.clone());
+
+    assert_eq!(
+        mempool.select_valid(&start, usize::MAX),
+        vec![transfer, mint]
+    );
+}
+
+#[test]
+fn sm_5_find_conflicts_reports_common_spend() {
+    let bill = Bill::new(User::Alice, 20, 0);
+    let other = Bill::new(User::Bob, 10, 1);
+    let txs = vec![
+        CashTransaction::Transfer {
+            spends: vec![bill.clone()],
+            receives: vec![Bill::new(User::Bob, 20, 2)],
+        },
+        CashTransaction::Transfer {
+            spends: vec![other],
+            receives: vec![Bill::new(User::Alice, 10, 3)],
+        },
+        CashTransaction::Mint {
+            minter: User::Charlie,
+            amount: 5,
+        },
+        CashTransaction::Transfer {
+            spends: vec![bill],
+            receives: vec![Bill::new(User::Charlie, 20, 4)],
+        },
+    ];
+
+    assert_eq!(find_conflicts(&txs), vec![(0, 3)]);
+}
+
+#[test]
+fn sm_5_find_conflicts_in_conflict_free_batch() {
+    let txs = vec![
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 2)],
+        },
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Bob, 10, 1)],
+            receives: vec![Bill::new(User::Alice, 10, 3)],
+        },
+    ];
+
+    assert!(find_conflicts(&txs).is_empty());
+}
+
+#[test]
+fn sm_5_diff_of_mint() {
+    let before = State::new();
+    let after = DigitalCashSystem::next_state(
+        &before,
+        &CashTransaction::Mint {
+            minter: User::Alice,
+            amount: 20,
+        },
+    );
+
+    assert_eq!(
+        diff(&before, &after),
+        (vec![Bill::new(User::Alice, 20, 0)], vec![])
+    );
+}
+
+#[test]
+fn sm_5_diff_of_transfer() {
+    let before = State::from([Bill::new(User::Alice, 20, 0)]);
+    let after = DigitalCashSystem::next_state(
+        &before,
+        &CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 15, 1), Bill::new(User::Charlie, 5, 2)],
+        },
+    );
+
+    assert_eq!(
+        diff(&before, &after),
+        (
+            vec![Bill::new(User::Bob, 15, 1), Bill::new(User::Charlie, 5, 2)],
+            vec![Bill::new(User::Alice, 20, 0)]
+        )
+    );
+}
+
+#[test]
+fn sm_5_diff_of_noop() {
+    let before = State::from([Bill::new(User::Alice, 20, 0)]);
+    let after = DigitalCashSystem::next_state(
+        &before,
+        &CashTransaction::Mint {
+            minter: User::Alice,
+            amount: 0,
+        },
+    );
+
+    assert_eq!(diff(&before, &after), (vec![], vec![]));
+}
+
+#[test]
+fn sm_5_undo_transfer_recovers_original_state() {
+    let start = State::from([Bill::new(User::Alice, 20, 0), Bill::new(User::Bob, 7, 1)]);
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 15, 2), Bill::new(User::Charlie, 4, 3)],
+    };
+    let end = DigitalCashSystem::next_state(&start, &transfer);
+    assert_ne!(end, start);
+
+    assert_eq!(undo(&end, &transfer, start.next_serial()), Some(start));
+}
+
+#[test]
+fn sm_5_undo_inconsistent_transfer_fails() {
+    let start = State::from([Bill::new(User::Alice, 20, 0)]);
+    let transfer = CashTransaction::Transfer {
+        spends: vec![Bill::new(User::Alice, 20, 0)],
+        receives: vec![Bill::new(User::Bob, 20, 1)],
+    };
+    let end = DigitalCashSystem::next_state(&start, &transfer);
+
+    // Undoing against a state that never received the bill
+    assert_eq!(undo(&start, &transfer, 0), None);
+    // Moving the serial counter forward is not a reversal
+    assert_eq!(undo(&end, &transfer, 5), None);
+    // Only transfers can be undone
+    assert_eq!(
+        undo(
+            &end,
+            &CashTransaction::Mint {
+                minter: User::Alice,
+                amount: 1
+            },
+            0
+        ),
+        None
+    );
+}
@@ -21,6 +21,19 @@
 //!   * Reputation System
 
 use super::{StateMachine, User};
+use crate::hash;
+use std::collections::{BTreeMap, HashMap};
+
+/// The voting phase of a proposal. Committed votes can only be revealed once the
+/// proposal has left the commit phase, so that nobody can see how others voted
+/// before committing to their own vote. Proposals that do not use commit-reveal
+/// are open for plain, visible votes instead, for their whole lifetime.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum ProposalPhase {
+    Open,
+    Commit,
+    Reveal,
+}
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 struct Proposal {
@@ -28,12 +41,22 @@ struct Proposal {
     proposed_action: String,
     proposed_by: User,
     pending_until_time_unit: u64,
+    phase: ProposalPhase,
+    /// Cancelled proposals can no longer be voted on.
+    cancelled: bool,
+    /// The ayes and nays the proposal ended with. Only set once the proposal is archived.
+    final_tally: Option<(u64, u64)>,
+    /// The options of a multi choice proposal. Empty for a plain aye or nay proposal.
+    options: Vec<String>,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
-enum VoteType {
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum VoteType {
     Aye,
     Nay,
+    /// Take part in the vote without supporting either side. Abstentions count toward turnout
+    /// but not toward the outcome.
+    Abstain,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -41,11 +64,73 @@ struct Vote {
     proposal_id: u64,
     vote: VoteType,
     user: User,
+    /// How strongly the voter backs their vote, from 0 to `MAX_CONVICTION`. Each level doubles
+    /// the weight of the vote, in exchange for locking the voter's power for longer.
+    conviction: u8,
+    /// The time unit in which the vote was cast. The vote is weighted by the stake in force then.
+    cast_at: u64,
+}
+
+/// A vote for one of the options of a multi choice proposal.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct ChoiceVote {
+    proposal_id: u64,
+    user: User,
+    option: usize,
+    cast_at: u64,
+}
+
+/// The highest conviction a vote may carry.
+const MAX_CONVICTION: u8 = 6;
+
+/// The number of time units a voter's power stays locked per level of conviction.
+const CONVICTION_LOCK_PERIOD: u64 = 4;
+
+/// A hidden vote. The commitment is the hash of the vote and a secret nonce.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct VoteCommitment {
+    proposal_id: u64,
+    user: User,
+    commitment: u64,
+}
+
+/// Calculate the commitment for a vote that will later be revealed with the same nonce.
+pub fn vote_commitment(vote: &VoteType, nonce: u64) -> u64 {
+    hash(&(vote, nonce))
 }
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct GovernanceState {
+    /// The proposals that have not expired yet.
     proposals: Vec<Proposal>,
+    /// Expired proposals along with their final tally. Their votes are no longer kept.
+    archived: Vec<Proposal>,
     votes: Vec<Vote>,
+    choice_votes: Vec<ChoiceVote>,
+    commitments: Vec<VoteCommitment>,
+    /// The registered voting power of each user. Votes are weighted by this power, and users
+    /// without registered power do not carry any weight.
+    voting_power: HashMap<User, u64>,
+    /// A snapshot of all registered voting power, keyed by the time unit in which it was taken.
+    /// A new snapshot is recorded whenever voting power is registered.
+    stake_history: Vec<(u64, BTreeMap<User, u64>)>,
+    /// The time unit until which each user's voting power is locked by a conviction vote.
+    /// Locked voting power can not be reduced.
+    locked_until: HashMap<User, u64>,
+    /// The free balance of each user, from which proposal deposits are paid.
+    balances: HashMap<User, u64>,
+    /// The deposit a proposer has to lock up when submitting a proposal.
+    deposit: u64,
+    /// The deposits locked by pending proposals, keyed by proposal id. A deposit is refunded
+    /// when its proposal is enacted or cancelled, and slashed when it is rejected.
+    locked_deposits: BTreeMap<u64, (User, u64)>,
+    /// The minimum number of time units between submitting a proposal and its last pending time
+    /// unit. With the default of zero, a proposal may be pending for only the time unit in which
+    /// it was submitted.
+    min_lifetime: u64,
+    /// The fraction of the aye and nay voting power, as (numerator, denominator), that must be in
+    /// favor for a proposal to pass. Defaults to a simple majority.
+    pass_ratio: (u64, u64),
     time_units_passed: u64,
 }
 
@@ -53,47 +138,314 @@ impl GovernanceState {
     fn new() -> GovernanceState {
         GovernanceState {
             proposals: vec![],
+            archived: vec![],
             votes: vec![],
+            choice_votes: vec![],
+            commitments: vec![],
+            voting_power: HashMap::new(),
+            stake_history: vec![],
+            locked_until: HashMap::new(),
+            balances: HashMap::new(),
+            deposit: 0,
+            locked_deposits: BTreeMap::new(),
+            min_lifetime: 0,
+            pass_ratio: (1, 2),
             time_units_passed: 0,
         }
     }
 
+    /// A governance system in which every proposal requires the given deposit.
+    fn with_deposit(deposit: u64) -> GovernanceState {
+        GovernanceState {
+            deposit,
+            ..GovernanceState::new()
+        }
+    }
+
+    /// A governance system in which every proposal must stay pending for at least the given number
+    /// of time units after it is submitted.
+    fn with_min_lifetime(min_lifetime: u64) -> GovernanceState {
+        GovernanceState {
+            min_lifetime,
+            ..GovernanceState::new()
+        }
+    }
+
+    /// A governance system in which proposals need at least the given fraction of the aye and nay
+    /// voting power in favor to pass, such as `(2, 3)` for constitutional changes.
+    fn with_pass_ratio(numerator: u64, denominator: u64) -> GovernanceState {
+        GovernanceState {
+            pass_ratio: (numerator, denominator),
+            ..GovernanceState::new()
+        }
+    }
+
+    /// A governance system seeded with the given binary proposals, given as the proposed action,
+    /// the proposer and the last time unit in which the proposal is pending.
+    fn with_proposals(proposals: impl IntoIterator<Item = (String, User, u64)>) -> GovernanceState {
+        let mut state = GovernanceState::new();
+        for (proposed_action, user, pending_until_time_unit) in proposals {
+            state.add_proposal(
+                proposed_action,
+                user,
+                pending_until_time_unit,
+                vec![],
+                ProposalPhase::Open,
+            );
+        }
+        state
+    }
+
+    /// Set the clock to the given time unit. Proposals that have expired by then are archived.
+    fn with_time(mut self, time_units_passed: u64) -> GovernanceState {
+        self.time_units_passed = time_units_passed;
+        self.archive_expired_proposals();
+        self
+    }
+
     fn one_time_unit_passed(&mut self) {
         self.time_units_passed += 1;
+        self.archive_expired_proposals();
+    }
+
+    /// Move the proposals that have just expired out of the active set, recording their final
+    /// tally. The deposit of a passed proposal is refunded as it gets enacted, that of a rejected
+    /// proposal is slashed.
+    fn archive_expired_proposals(&mut self) {
+        let now = self.time_units_passed;
+        let (expired, active): (Vec<Proposal>, Vec<Proposal>) = self
+            .proposals
+            .drain(..)
+            .partition(|p| p.pending_until_time_unit < now);
+        self.proposals = active;
+
+        for mut proposal in expired {
+            if self.proposal_passed(proposal.id, None) {
+                self.refund_deposit(proposal.id);
+            } else {
+                self.locked_deposits.remove(&proposal.id);
+            }
+
+            proposal.final_tally = Some(self.tally(proposal.id));
+            self.votes.retain(|v| v.proposal_id != proposal.id);
+            self.choice_votes.retain(|v| v.proposal_id != proposal.id);
+            self.commitments.retain(|c| c.proposal_id != proposal.id);
+            self.archived.push(proposal);
+        }
     }
 
-    fn vote_in_favor(&mut self, proposal_id: u64, user: User) {
+    /// Whether an archived proposal passed. Returns `None` for proposals that are still active
+    /// or do not exist.
+    fn final_result(&self, proposal_id: u64) -> Option<bool> {
+        self.archived
+            .iter()
+            .find(|p| p.id == proposal_id)
+            .and_then(|p| p.final_tally)
+            .map(|(ayes, nays)| self.meets_pass_ratio(ayes, nays))
+    }
+
+    fn refund_deposit(&mut self, proposal_id: u64) {
+        if let Some((user, amount)) = self.locked_deposits.remove(&proposal_id) {
+            let balance = self.balances.entry(user).or_insert(0);
+            *balance = balance.saturating_add(amount);
+        }
+    }
+
+    fn balance_of(&self, user: &User) -> u64 {
+        self.balances.get(user).copied().unwrap_or(0)
+    }
+
+    fn fund(&mut self, user: User, amount: u64) {
+        let balance = self.balances.entry(user).or_insert(0);
+        *balance = balance.saturating_add(amount);
+    }
+
+    fn vote_in_favor(&mut self, proposal_id: u64, user: User, conviction: u8) {
         let vote = Vote {
             proposal_id,
             vote: VoteType::Aye,
             user,
+            conviction,
+            cast_at: self.time_units_passed,
         };
+        self.lock_for_conviction(user, conviction);
         self.votes.push(vote);
     }
 
-    fn vote_against(&mut self, proposal_id: u64, user: User) {
+    fn vote_against(&mut self, proposal_id: u64, user: User, conviction: u8) {
         let vote = Vote {
             proposal_id,
             vote: VoteType::Nay,
             user,
+            conviction,
+            cast_at: self.time_units_passed,
         };
+        self.lock_for_conviction(user, conviction);
         self.votes.push(vote);
     }
 
-    fn add_proposal(&mut self, proposed_action: String, user: User, pending_until_time_unit: u64) {
+    fn abstain(&mut self, proposal_id: u64, user: User) {
+        let vote = Vote {
+            proposal_id,
+            vote: VoteType::Abstain,
+            user,
+            conviction: 0,
+            cast_at: self.time_units_passed,
+        };
+        self.votes.push(vote);
+    }
+
+    /// Lock the user's voting power for a number of time units proportional to the conviction.
+    /// An existing longer lock is kept.
+    fn lock_for_conviction(&mut self, user: User, conviction: u8) {
+        if conviction == 0 {
+            return;
+        }
+
+        let until = self.time_units_passed + conviction as u64 * CONVICTION_LOCK_PERIOD;
+        let lock = self.locked_until.entry(user).or_insert(0);
+        *lock = (*lock).max(until);
+    }
+
+    fn is_locked(&self, user: &User) -> bool {
+        self.locked_until
+            .get(user)
+            .is_some_and(|until| *until > self.time_units_passed)
+    }
+
+    fn add_proposal(
+        &mut self,
+        proposed_action: String,
+        user: User,
+        pending_until_time_unit: u64,
+        options: Vec<String>,
+        phase: ProposalPhase,
+    ) {
         let proposal = Proposal {
-            id: self.proposals.len() as u64 + 1,
+            id: (self.proposals.len() + self.archived.len()) as u64 + 1,
             proposed_action,
             pending_until_time_unit,
             proposed_by: user,
+            phase,
+            cancelled: false,
+            final_tally: None,
+            options,
         };
+
+        // The caller has checked that the proposer can afford the deposit
+        if let Some(balance) = self.balances.get_mut(&user) {
+            *balance -= self.deposit;
+        }
+        self.locked_deposits
+            .insert(proposal.id, (user, self.deposit));
         self.proposals.push(proposal);
     }
 
-    fn proposal_exists_and_pending(&self, proposal_id: u64) -> bool {
+    fn is_multi_choice(&self, proposal_id: u64) -> bool {
+        self.option_count(proposal_id) > 0
+    }
+
+    fn option_count(&self, proposal_id: u64) -> usize {
         self.proposals
             .iter()
-            .any(|p| p.id == proposal_id && p.pending_until_time_unit >= self.time_units_passed)
+            .find(|p| p.id == proposal_id)
+            .map(|p| p.options.len())
+            .unwrap_or(0)
+    }
+
+    fn has_user_chosen(&self, proposal_id: u64, user: &User) -> bool {
+        self.choice_votes
+            .iter()
+            .any(|v| v.proposal_id == proposal_id && &v.user == user)
+    }
+
+    fn vote_choice(&mut self, proposal_id: u64, user: User, option: usize) {
+        self.choice_votes.push(ChoiceVote {
+            proposal_id,
+            user,
+            option,
+            cast_at: self.time_units_passed,
+        });
+    }
+
+    /// The option of a multi choice proposal with the most voting power behind it. Returns `None`
+    /// when nobody voted, or when the lead is tied between several options.
+    fn winning_option(&self, proposal_id: u64) -> Option<usize> {
+        let mut totals = vec![0u64; self.option_count(proposal_id)];
+        for v in self
+            .choice_votes
+            .iter()
+            .filter(|v| v.proposal_id == proposal_id)
+        {
+            totals[v.option] = totals[v.option].saturating_add(self.stake_at(v.user, v.cast_at));
+        }
+
+        let max = *totals.iter().max()?;
+        let mut leaders = (0..totals.len()).filter(|i| totals[*i] == max);
+        match (leaders.next(), leaders.next()) {
+            (Some(winner), None) if max > 0 => Some(winner),
+            _ => None,
+        }
+    }
+
+    /// Whether the user may submit a proposal that stays pending until the given time unit. The
+    /// proposal's lifetime, `pending_until_time_unit - time_units_passed`, must be at least the
+    /// `min_lifetime`. A lifetime exactly at the minimum is accepted.
+    fn can_propose(&self, user: &User, pending_until_time_unit: u64) -> bool {
+        pending_until_time_unit
+            .checked_sub(self.time_units_passed)
+            .is_some_and(|lifetime| lifetime >= self.min_lifetime)
+            && self.balance_of(user) >= self.deposit
+    }
+
+    fn cancel_proposal(&mut self, proposal_id: u64) {
+        if let Some(proposal) = self.proposals.iter_mut().find(|p| p.id == proposal_id) {
+            proposal.cancelled = true;
+        }
+        self.refund_deposit(proposal_id);
+    }
+
+    fn proposed_by(&self, proposal_id: u64) -> Option<User> {
+        self.proposals
+            .iter()
+            .find(|p| p.id == proposal_id)
+            .map(|p| p.proposed_by)
+    }
+
+    fn commit_vote(&mut self, proposal_id: u64, user: User, commitment: u64) {
+        let commitment = VoteCommitment {
+            proposal_id,
+            user,
+            commitment,
+        };
+        self.commitments.push(commitment);
+    }
+
+    fn start_reveal_phase(&mut self, proposal_id: u64) {
+        if let Some(proposal) = self.proposals.iter_mut().find(|p| p.id == proposal_id) {
+            proposal.phase = ProposalPhase::Reveal;
+        }
+    }
+
+    fn proposal_in_phase(&self, proposal_id: u64, phase: ProposalPhase) -> bool {
+        self.proposals
+            .iter()
+            .any(|p| p.id == proposal_id && p.phase == phase)
+    }
+
+    fn commitment_of(&self, proposal_id: u64, user: &User) -> Option<u64> {
+        self.commitments
+            .iter()
+            .find(|c| c.proposal_id == proposal_id && &c.user == user)
+            .map(|c| c.commitment)
+    }
+
+    fn proposal_exists_and_pending(&self, proposal_id: u64) -> bool {
+        self.proposals.iter().any(|p| {
+            p.id == proposal_id
+                && !p.cancelled
+                && p.pending_until_time_unit >= self.time_units_passed
+        })
     }
 
     fn has_user_voted(&self, proposal_id: u64, user: &User) -> bool {
@@ -101,13 +453,146 @@ impl GovernanceState {
             .iter()
             .any(|v| v.proposal_id == proposal_id && &v.user == user)
     }
+
+    fn register_voting_power(&mut self, user: User, power: u64) {
+        self.voting_power.insert(user, power);
+
+        let snapshot: BTreeMap<User, u64> = self
+            .voting_power
+            .iter()
+            .map(|(user, power)| (*user, *power))
+            .collect();
+        match self.stake_history.last_mut() {
+            // Only the last snapshot within a time unit is kept
+            Some((time, latest)) if *time == self.time_units_passed => *latest = snapshot,
+            _ => self.stake_history.push((self.time_units_passed, snapshot)),
+        }
+    }
+
+    /// The voting power the user had registered at the given time unit.
+    fn stake_at(&self, user: User, time: u64) -> u64 {
+        self.stake_history
+            .iter()
+            .rev()
+            .find(|(snapshot_time, _)| *snapshot_time <= time)
+            .and_then(|(_, snapshot)| snapshot.get(&user).copied())
+            .unwrap_or(0)
+    }
+
+    fn voting_power_of(&self, user: &User) -> u64 {
+        self.voting_power.get(user).copied().unwrap_or(0)
+    }
+
+    /// The voting power registered by all users together. Like the tallies, it saturates rather
+    /// than overflowing.
+    fn total_voting_power(&self) -> u64 {
+        self.voting_power
+            .values()
+            .fold(0, |total, power| total.saturating_add(*power))
+    }
+
+    /// The quorum as `fraction_pct` percent of all registered voting power. Unlike a fixed
+    /// number, this quorum scales with the size of the electorate.
+    fn dynamic_quorum(&self, fraction_pct: u8) -> u64 {
+        (self.total_voting_power() as u128 * fraction_pct as u128 / 100) as u64
+    }
+
+    /// The weight of a vote, which is the voter's power at the time the vote was cast, multiplied
+    /// by two to the power of its conviction. The weight saturates rather than losing high bits,
+    /// so more conviction never weighs less.
+    fn vote_weight(&self, vote: &Vote) -> u64 {
+        self.stake_at(vote.user, vote.cast_at)
+            .saturating_mul(1 << vote.conviction)
+    }
+
+    /// The voting power in favor of and against the given proposal. Abstentions are not counted.
+    /// Each side saturates at `u64::MAX` rather than overflowing.
+    fn tally(&self, proposal_id: u64) -> (u64, u64) {
+        self.votes
+            .iter()
+            .filter(|v| v.proposal_id == proposal_id)
+            .fold((0, 0), |(ayes, nays), v| match v.vote {
+                VoteType::Aye => (ayes.saturating_add(self.vote_weight(v)), nays),
+                VoteType::Nay => (ayes, nays.saturating_add(self.vote_weight(v))),
+                VoteType::Abstain => (ayes, nays),
+            })
+    }
+
+    /// The total voting power that turned out for the given proposal, including abstentions.
+    fn turnout(&self, proposal_id: u64) -> u64 {
+        self.votes
+            .iter()
+            .filter(|v| v.proposal_id == proposal_id)
+            .fold(0, |turnout, v| turnout.saturating_add(self.vote_weight(v)))
+    }
+
+    /// Whether the ayes make up at least the pass ratio of the aye and nay voting power. There
+    /// must always be more ayes than nays, so a tie never passes a simple majority.
+    fn meets_pass_ratio(&self, ayes: u64, nays: u64) -> bool {
+        let (numerator, denominator) = self.pass_ratio;
+        ayes > nays
+            && ayes as u128 * denominator as u128
+                >= (ayes as u128 + nays as u128) * numerator as u128
+    }
+
+    /// Whether the given proposal has enough voting power in favor to meet the pass ratio. When a
+    /// quorum percentage is given, the `turnout`, including abstentions, must also reach the
+    /// `dynamic_quorum`.
+    fn proposal_passed(&self, proposal_id: u64, quorum_pct: Option<u8>) -> bool {
+        let (ayes, nays) = self.tally(proposal_id);
+        let quorum_reached = match quorum_pct {
+            Some(pct) => self.turnout(proposal_id) >= self.dynamic_quorum(pct),
+            None => true,
+        };
+
+        quorum_reached && self.meets_pass_ratio(ayes, nays)
+    }
+}
+
+/// A human readable overview of the active proposals and their live tally.
+impl std::fmt::Display for GovernanceState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Time unit: {}", self.time_units_passed)?;
+        writeln!(
+            f,
+            "{:>4} | {:<8} | {:>9} | {:>6} | {:>6}",
+            "id", "proposer", "remaining", "ayes", "nays"
+        )?;
+        for proposal in &self.proposals {
+            let (ayes, nays) = self.tally(proposal.id);
+            writeln!(
+                f,
+                "{:>4} | {:<8} | {:>9} | {:>6} | {:>6}",
+                proposal.id,
+                format!("{:?}", proposal.proposed_by),
+                proposal
+                    .pending_until_time_unit
+                    .saturating_sub(self.time_units_passed),
+                ayes,
+                nays
+            )?;
+        }
+        Ok(())
+    }
 }
 
 pub enum GovernanceAction {
     OneTimeUnitPassed,
-    VoteInFavor(u64, User),         // proposal_id, user
-    VoteAgainst(u64, User),         // proposal_id, user
+    VoteInFavor(u64, User, u8),     // proposal_id, user, conviction
+    VoteAgainst(u64, User, u8),     // proposal_id, user, conviction
+    VoteAbstain(u64, User),         // proposal_id, user
     AddProposal(String, User, u64), // proposed_action, proposed_by, pending_until_time_unit
+    // proposed_action, proposed_by, pending_until_time_unit
+    AddCommitRevealProposal(String, User, u64),
+    CommitVote(u64, User, u64),           // proposal_id, user, commitment
+    StartRevealPhase(u64, User),          // proposal_id, proposed_by
+    RevealVote(u64, User, VoteType, u64), // proposal_id, user, vote, nonce
+    RegisterVotingPower(User, u64),       // user, voting_power
+    Fund(User, u64),                      // user, amount
+    CancelProposal(u64, User),            // proposal_id, proposed_by
+    VoteChoice(u64, User, usize),         // proposal_id, user, option
+    // proposed_action, proposed_by, pending_until_time_unit, options
+    AddMultiChoiceProposal(String, User, u64, Vec<String>),
 }
 
 impl StateMachine for GovernanceState {
@@ -122,24 +607,44 @@ impl StateMachine for GovernanceState {
                 new_state
             }
 
-            GovernanceAction::VoteInFavor(proposal_id, user) => {
+            GovernanceAction::VoteInFavor(proposal_id, user, conviction) => {
+                if starting_state.proposal_exists_and_pending(*proposal_id)
+                    && starting_state.proposal_in_phase(*proposal_id, ProposalPhase::Open)
+                    && !starting_state.is_multi_choice(*proposal_id)
+                    && !starting_state.has_user_voted(*proposal_id, user)
+                    && *conviction <= MAX_CONVICTION
+                {
+                    let mut new_state = starting_state.clone();
+                    new_state.vote_in_favor(*proposal_id, *user, *conviction);
+                    new_state
+                } else {
+                    starting_state.clone()
+                }
+            }
+
+            GovernanceAction::VoteAgainst(proposal_id, user, conviction) => {
                 if starting_state.proposal_exists_and_pending(*proposal_id)
+                    && starting_state.proposal_in_phase(*proposal_id, ProposalPhase::Open)
+                    && !starting_state.is_multi_choice(*proposal_id)
                     && !starting_state.has_user_voted(*proposal_id, user)
+                    && *conviction <= MAX_CONVICTION
                 {
                     let mut new_state = starting_state.clone();
-                    new_state.vote_in_favor(*proposal_id, user.clone());
+                    new_state.vote_against(*proposal_id, *user, *conviction);
                     new_state
                 } else {
                     starting_state.clone()
                 }
             }
 
-            GovernanceAction::VoteAgainst(proposal_id, user) => {
+            GovernanceAction::VoteAbstain(proposal_id, user) => {
                 if starting_state.proposal_exists_and_pending(*proposal_id)
+                    && starting_state.proposal_in_phase(*proposal_id, ProposalPhase::Open)
+                    && !starting_state.is_multi_choice(*proposal_id)
                     && !starting_state.has_user_voted(*proposal_id, user)
                 {
                     let mut new_state = starting_state.clone();
-                    new_state.vote_against(*proposal_id, user.clone());
+                    new_state.abstain(*proposal_id, *user);
                     new_state
                 } else {
                     starting_state.clone()
@@ -151,18 +656,153 @@ impl StateMachine for GovernanceState {
                 proposed_by,
                 pending_until_time_unit,
             ) => {
-                if *pending_until_time_unit >= starting_state.time_units_passed {
+                if starting_state.can_propose(proposed_by, *pending_until_time_unit) {
                     let mut new_state = starting_state.clone();
                     new_state.add_proposal(
                         proposed_action.clone(),
                         proposed_by.clone(),
                         *pending_until_time_unit,
+                        vec![],
+                        ProposalPhase::Open,
+                    );
+                    new_state
+                } else {
+                    starting_state.clone()
+                }
+            }
+
+            GovernanceAction::AddCommitRevealProposal(
+                proposed_action,
+                proposed_by,
+                pending_until_time_unit,
+            ) => {
+                if starting_state.can_propose(proposed_by, *pending_until_time_unit) {
+                    let mut new_state = starting_state.clone();
+                    new_state.add_proposal(
+                        proposed_action.clone(),
+                        *proposed_by,
+                        *pending_until_time_unit,
+                        vec![],
+                        ProposalPhase::Commit,
                     );
                     new_state
                 } else {
                     starting_state.clone()
                 }
             }
+
+            GovernanceAction::CommitVote(proposal_id, user, commitment) => {
+                if starting_state.proposal_exists_and_pending(*proposal_id)
+                    && starting_state.proposal_in_phase(*proposal_id, ProposalPhase::Commit)
+                    && starting_state.commitment_of(*proposal_id, user).is_none()
+                {
+                    let mut new_state = starting_state.clone();
+                    new_state.commit_vote(*proposal_id, *user, *commitment);
+                    new_state
+                } else {
+                    starting_state.clone()
+                }
+            }
+
+            GovernanceAction::StartRevealPhase(proposal_id, user) => {
+                // Only the proposer may end the commit phase, and only once
+                if starting_state.proposal_exists_and_pending(*proposal_id)
+                    && starting_state.proposal_in_phase(*proposal_id, ProposalPhase::Commit)
+                    && starting_state.proposed_by(*proposal_id) == Some(*user)
+                {
+                    let mut new_state = starting_state.clone();
+                    new_state.start_reveal_phase(*proposal_id);
+                    new_state
+                } else {
+                    starting_state.clone()
+                }
+            }
+
+            GovernanceAction::RevealVote(proposal_id, user, vote, nonce) => {
+                if starting_state.proposal_exists_and_pending(*proposal_id)
+                    && starting_state.proposal_in_phase(*proposal_id, ProposalPhase::Reveal)
+                    && !starting_state.has_user_voted(*proposal_id, user)
+                    && starting_state.commitment_of(*proposal_id, user)
+                        == Some(vote_commitment(vote, *nonce))
+                {
+                    let mut new_state = starting_state.clone();
+                    match vote {
+                        VoteType::Aye => new_state.vote_in_favor(*proposal_id, *user, 0),
+                        VoteType::Nay => new_state.vote_against(*proposal_id, *user, 0),
+                        VoteType::Abstain => new_state.abstain(*proposal_id, *user),
+                    }
+                    new_state
+                } else {
+                    starting_state.clone()
+                }
+            }
+
+            GovernanceAction::RegisterVotingPower(user, power) => {
+                // Power locked by a conviction vote can not be withdrawn
+                if starting_state.is_locked(user) && *power < starting_state.voting_power_of(user) {
+                    return starting_state.clone();
+                }
+
+                let mut new_state = starting_state.clone();
+                new_state.register_voting_power(*user, *power);
+                new_state
+            }
+
+            GovernanceAction::Fund(user, amount) => {
+                let mut new_state = starting_state.clone();
+                new_state.fund(*user, *amount);
+                new_state
+            }
+
+            GovernanceAction::CancelProposal(proposal_id, user) => {
+                // Only the proposer may withdraw a proposal, and only while it is pending
+                if starting_state.proposal_exists_and_pending(*proposal_id)
+                    && starting_state.proposed_by(*proposal_id) == Some(*user)
+                {
+                    let mut new_state = starting_state.clone();
+                    new_state.cancel_proposal(*proposal_id);
+                    new_state
+                } else {
+                    starting_state.clone()
+                }
+            }
+
+            GovernanceAction::AddMultiChoiceProposal(
+                proposed_action,
+                proposed_by,
+                pending_until_time_unit,
+                options,
+            ) => {
+                // A choice needs at least two options
+                if options.len() >= 2
+                    && starting_state.can_propose(proposed_by, *pending_until_time_unit)
+                {
+                    let mut new_state = starting_state.clone();
+                    new_state.add_proposal(
+                        proposed_action.clone(),
+                        *proposed_by,
+                        *pending_until_time_unit,
+                        options.clone(),
+                        ProposalPhase::Open,
+                    );
+                    new_state
+                } else {
+                    starting_state.clone()
+                }
+            }
+
+            GovernanceAction::VoteChoice(proposal_id, user, option) => {
+                if starting_state.proposal_exists_and_pending(*proposal_id)
+                    && *option < starting_state.option_count(*proposal_id)
+                    && !starting_state.has_user_chosen(*proposal_id, user)
+                {
+                    let mut new_state = starting_state.clone();
+                    new_state.vote_choice(*proposal_id, *user, *option);
+                    new_state
+                } else {
+                    starting_state.clone()
+                }
+            }
         }
     }
 }
@@ -176,6 +816,8 @@ mod tests {
         let state = GovernanceState::new();
         assert_eq!(state.proposals.len(), 0);
         assert_eq!(state.votes.len(), 0);
+        assert_eq!(state.commitments.len(), 0);
+        assert_eq!(state.voting_power.len(), 0);
         assert_eq!(state.time_units_passed, 0);
     }
 
@@ -211,9 +853,13 @@ mod tests {
                 10,
             ),
         );
+        crate::c1_state_machine::assert_deterministic::<GovernanceState>(
+            &state_with_proposal,
+            &GovernanceAction::VoteInFavor(1, User::Bob, 0),
+        );
         let final_state = GovernanceState::next_state(
             &state_with_proposal,
-            &GovernanceAction::VoteInFavor(1, User::Bob),
+            &GovernanceAction::VoteInFavor(1, User::Bob, 0),
         );
         assert_eq!(final_state.votes.len(), 1);
         assert_eq!(final_state.votes[0].vote, VoteType::Aye);
@@ -224,7 +870,7 @@ mod tests {
     fn test_invalid_id_voting() {
         let state = GovernanceState::new();
         let new_state =
-            GovernanceState::next_state(&state, &GovernanceAction::VoteInFavor(1, User::Bob));
+            GovernanceState::next_state(&state, &GovernanceAction::VoteInFavor(1, User::Bob, 0));
         assert_eq!(new_state.votes.len(), 0); // No proposals yet
     }
 
@@ -241,16 +887,16 @@ mod tests {
         );
         let state_after_first_vote = GovernanceState::next_state(
             &state_with_proposal,
-            &GovernanceAction::VoteInFavor(1, User::Bob),
+            &GovernanceAction::VoteInFavor(1, User::Bob, 0),
         );
 
         let first_duplicate = GovernanceState::next_state(
             &state_after_first_vote,
-            &GovernanceAction::VoteInFavor(1, User::Bob),
+            &GovernanceAction::VoteInFavor(1, User::Bob, 0),
         ); // Duplicate vote
         let second_duplicate = GovernanceState::next_state(
             &first_duplicate,
-            &GovernanceAction::VoteAgainst(1, User::Bob),
+            &GovernanceAction::VoteAgainst(1, User::Bob, 0),
         ); // Duplicate vote
 
         assert_eq!(second_duplicate.votes.len(), 1); // Should still be 1
@@ -277,7 +923,8 @@ mod tests {
         }
 
         assert_eq!(final_state.time_units_passed, 6);
-        assert_eq!(final_state.proposals.len(), 1);
+        assert_eq!(final_state.proposals.len(), 0);
+        assert_eq!(final_state.archived.len(), 1);
     }
 
     #[test]
@@ -307,11 +954,696 @@ mod tests {
         // Attempt to vote on the expired proposal
         let final_state = GovernanceState::next_state(
             &state_after_expiration,
-            &GovernanceAction::VoteInFavor(1, User::Bob),
+            &GovernanceAction::VoteInFavor(1, User::Bob, 0),
         );
 
         // Check if the state remains unchanged (vote should have no effect)
         assert_eq!(final_state.votes.len(), state_after_expiration.votes.len());
         assert!(!final_state.proposal_exists_and_pending(1));
     }
+
+    fn state_with_committed_vote(vote: VoteType, nonce: u64) -> GovernanceState {
+        let state = GovernanceState::new();
+        let state_with_proposal = GovernanceState::next_state(
+            &state,
+            &GovernanceAction::AddCommitRevealProposal(
+                "Hide votes until everyone has voted".to_string(),
+                User::Alice,
+                10,
+            ),
+        );
+        GovernanceState::next_state(
+            &state_with_proposal,
+            &GovernanceAction::CommitVote(1, User::Bob, vote_commitment(&vote, nonce)),
+        )
+    }
+
+    #[test]
+    fn test_commit_then_reveal_vote() {
+        let committed = state_with_committed_vote(VoteType::Nay, 42);
+        assert_eq!(committed.commitments.len(), 1);
+        assert_eq!(committed.votes.len(), 0);
+
+        let revealing = GovernanceState::next_state(
+            &committed,
+            &GovernanceAction::StartRevealPhase(1, User::Alice),
+        );
+        let final_state = GovernanceState::next_state(
+            &revealing,
+            &GovernanceAction::RevealVote(1, User::Bob, VoteType::Nay, 42),
+        );
+
+        assert_eq!(final_state.votes.len(), 1);
+        assert_eq!(final_state.votes[0].vote, VoteType::Nay);
+        assert_eq!(final_state.votes[0].user, User::Bob);
+    }
+
+    #[test]
+    fn test_mismatched_reveal_rejected() {
+        let committed = state_with_committed_vote(VoteType::Nay, 42);
+        let revealing = GovernanceState::next_state(
+            &committed,
+            &GovernanceAction::StartRevealPhase(1, User::Alice),
+        );
+
+        let wrong_vote = GovernanceState::next_state(
+            &revealing,
+            &GovernanceAction::RevealVote(1, User::Bob, VoteType::Aye, 42),
+        );
+        let wrong_nonce = GovernanceState::next_state(
+            &revealing,
+            &GovernanceAction::RevealVote(1, User::Bob, VoteType::Nay, 43),
+        );
+
+        assert_eq!(wrong_vote, revealing);
+        assert_eq!(wrong_nonce, revealing);
+    }
+
+    #[test]
+    fn test_reveal_during_commit_phase_rejected() {
+        let committed = state_with_committed_vote(VoteType::Aye, 7);
+        let final_state = GovernanceState::next_state(
+            &committed,
+            &GovernanceAction::RevealVote(1, User::Bob, VoteType::Aye, 7),
+        );
+
+        assert_eq!(final_state, committed);
+    }
+
+    #[test]
+    fn test_plain_vote_rejected_on_commit_reveal_proposal() {
+        let committed = state_with_committed_vote(VoteType::Aye, 7);
+
+        for action in [
+            GovernanceAction::VoteInFavor(1, User::Charlie, 0),
+            GovernanceAction::VoteAgainst(1, User::Charlie, 0),
+            GovernanceAction::VoteAbstain(1, User::Charlie),
+        ] {
+            assert_eq!(GovernanceState::next_state(&committed, &action), committed);
+        }
+
+        // Committing is not possible on a proposal with plain votes either
+        let open = GovernanceState::next_state(
+            &GovernanceState::new(),
+            &GovernanceAction::AddProposal("Show all votes".to_string(), User::Alice, 10),
+        );
+        let final_state = GovernanceState::next_state(
+            &open,
+            &GovernanceAction::CommitVote(1, User::Bob, vote_commitment(&VoteType::Aye, 7)),
+        );
+        assert_eq!(final_state, open);
+    }
+
+    #[test]
+    fn test_only_proposer_starts_reveal_phase_once() {
+        let committed = state_with_committed_vote(VoteType::Aye, 7);
+
+        let by_other = GovernanceState::next_state(
+            &committed,
+            &GovernanceAction::StartRevealPhase(1, User::Bob),
+        );
+        assert_eq!(by_other, committed);
+
+        let revealing = GovernanceState::next_state(
+            &committed,
+            &GovernanceAction::StartRevealPhase(1, User::Alice),
+        );
+        assert_ne!(revealing, committed);
+        assert_eq!(
+            GovernanceState::next_state(
+                &revealing,
+                &GovernanceAction::StartRevealPhase(1, User::Alice)
+            ),
+            revealing
+        );
+    }
+
+    #[test]
+    fn test_reveal_without_commit_rejected() {
+        let committed = state_with_committed_vote(VoteType::Aye, 7);
+        let revealing = GovernanceState::next_state(
+            &committed,
+            &GovernanceAction::StartRevealPhase(1, User::Alice),
+        );
+        let final_state = GovernanceState::next_state(
+            &revealing,
+            &GovernanceAction::RevealVote(1, User::Charlie, VoteType::Aye, 7),
+        );
+
+        assert_eq!(final_state, revealing);
+        assert_eq!(final_state.votes.len(), 0);
+    }
+
+    #[test]
+    fn test_dynamic_quorum_scales_with_voting_power() {
+        let mut state = GovernanceState::new();
+        for action in [
+            GovernanceAction::RegisterVotingPower(User::Alice, 10),
+            GovernanceAction::RegisterVotingPower(User::Bob, 10),
+            GovernanceAction::AddProposal("Lower the quorum".to_string(), User::Alice, 10),
+            GovernanceAction::VoteInFavor(1, User::Alice, 0),
+        ] {
+            state = GovernanceState::next_state(&state, &action);
+        }
+
+        assert_eq!(state.dynamic_quorum(50), 10);
+        assert!(state.proposal_passed(1, Some(50)));
+
+        // More voting power raises the quorum, and the same votes no longer suffice
+        let state = GovernanceState::next_state(
+            &state,
+            &GovernanceAction::RegisterVotingPower(User::Charlie, 30),
+        );
+
+        assert_eq!(state.dynamic_quorum(50), 25);
+        assert!(!state.proposal_passed(1, Some(50)));
+        assert!(state.proposal_passed(1, None));
+    }
+
+    #[test]
+    fn test_apply_all_matches_manual_chaining() {
+        let actions = vec![
+            GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 10),
+            GovernanceAction::VoteInFavor(1, User::Bob, 0),
+            GovernanceAction::VoteInFavor(1, User::Charlie, 0),
+        ];
+
+        let start = GovernanceState::new();
+        let mut manual = start.clone();
+        for action in actions.iter() {
+            crate::c1_state_machine::assert_deterministic::<GovernanceState>(&manual, action);
+            manual = GovernanceState::next_state(&manual, action);
+        }
+
+        let applied = GovernanceState::apply_all(&start, &actions);
+        assert_eq!(applied, manual);
+        assert_eq!(applied.votes.len(), 2);
+    }
+
+    #[test]
+    fn test_trace_of_proposal_and_votes() {
+        let actions = vec![
+            GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 10),
+            GovernanceAction::VoteInFavor(1, User::Bob, 0),
+            GovernanceAction::VoteAgainst(1, User::Charlie, 0),
+        ];
+
+        let trace = GovernanceState::trace(&GovernanceState::new(), &actions);
+        assert_eq!(trace.len(), 3);
+        assert_eq!(trace[0].proposals.len(), 1);
+        assert_eq!(trace[0].votes.len(), 0);
+        assert_eq!(trace[1].votes.len(), 1);
+        assert_eq!(trace[2].votes.len(), 2);
+        assert_eq!(
+            trace[2],
+            GovernanceState::apply_all(&GovernanceState::new(), &actions)
+        );
+    }
+
+    #[test]
+    fn test_first_divergence_between_traces() {
+        let start = GovernanceState::new();
+        let honest = vec![
+            GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 10),
+            GovernanceAction::VoteInFavor(1, User::Bob, 0),
+            GovernanceAction::VoteInFavor(1, User::Charlie, 0),
+        ];
+        let tampered = vec![
+            GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 10),
+            GovernanceAction::VoteAgainst(1, User::Bob, 0),
+            GovernanceAction::VoteInFavor(1, User::Charlie, 0),
+        ];
+
+        let honest_trace = GovernanceState::trace(&start, &honest);
+        let tampered_trace = GovernanceState::trace(&start, &tampered);
+
+        assert_eq!(
+            GovernanceState::first_divergence(&honest_trace, &honest_trace),
+            None
+        );
+        assert_eq!(
+            GovernanceState::first_divergence(&honest_trace, &tampered_trace),
+            Some(1)
+        );
+        assert_eq!(
+            GovernanceState::first_divergence(&honest_trace, &honest_trace[..2]),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_conviction_outweighs_several_plain_votes() {
+        let actions = vec![
+            GovernanceAction::RegisterVotingPower(User::Alice, 10),
+            GovernanceAction::RegisterVotingPower(User::Bob, 10),
+            GovernanceAction::RegisterVotingPower(User::Charlie, 10),
+            GovernanceAction::RegisterVotingPower(User::Dave, 10),
+            GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 100),
+            GovernanceAction::VoteInFavor(1, User::Alice, 2),
+            GovernanceAction::VoteAgainst(1, User::Bob, 0),
+            GovernanceAction::VoteAgainst(1, User::Charlie, 0),
+            GovernanceAction::VoteAgainst(1, User::Dave, 0),
+        ];
+        let state = GovernanceState::apply_all(&GovernanceState::new(), &actions);
+
+        assert_eq!(state.tally(1), (40, 30));
+        assert!(state.proposal_passed(1, None));
+    }
+
+    #[test]
+    fn test_conviction_lock_is_recorded() {
+        let actions = vec![
+            GovernanceAction::RegisterVotingPower(User::Alice, 10),
+            GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 100),
+            GovernanceAction::VoteInFavor(1, User::Alice, 3),
+        ];
+        let state = GovernanceState::apply_all(&GovernanceState::new(), &actions);
+
+        assert_eq!(
+            state.locked_until.get(&User::Alice),
+            Some(&(3 * CONVICTION_LOCK_PERIOD))
+        );
+        assert!(state.is_locked(&User::Alice));
+
+        // Locked power can not be reduced until the lock expires
+        let withdraw = GovernanceAction::RegisterVotingPower(User::Alice, 0);
+        assert!(GovernanceState::is_noop(&state, &withdraw));
+
+        let mut later = state.clone();
+        for _ in 0..3 * CONVICTION_LOCK_PERIOD {
+            later = GovernanceState::next_state(&later, &GovernanceAction::OneTimeUnitPassed);
+        }
+        assert!(!later.is_locked(&User::Alice));
+        assert!(!GovernanceState::is_noop(&later, &withdraw));
+    }
+
+    #[test]
+    fn test_conviction_above_max_rejected() {
+        let state = GovernanceState::next_state(
+            &GovernanceState::new(),
+            &GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 100),
+        );
+
+        assert!(GovernanceState::is_noop(
+            &state,
+            &GovernanceAction::VoteInFavor(1, User::Bob, MAX_CONVICTION + 1)
+        ));
+    }
+
+    fn state_with_deposited_proposal() -> GovernanceState {
+        let actions = vec![
+            GovernanceAction::Fund(User::Alice, 100),
+            GovernanceAction::RegisterVotingPower(User::Bob, 10),
+            GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 2),
+        ];
+        GovernanceState::apply_all(&GovernanceState::with_deposit(30), &actions)
+    }
+
+    #[test]
+    fn test_proposal_locks_deposit() {
+        let state = state_with_deposited_proposal();
+
+        assert_eq!(state.balance_of(&User::Alice), 70);
+        assert_eq!(state.locked_deposits.get(&1), Some(&(User::Alice, 30)));
+    }
+
+    #[test]
+    fn test_proposal_requires_sufficient_balance() {
+        let state = GovernanceState::next_state(
+            &GovernanceState::with_deposit(30),
+            &GovernanceAction::Fund(User::Alice, 29),
+        );
+
+        assert!(GovernanceState::is_noop(
+            &state,
+            &GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 2)
+        ));
+    }
+
+    #[test]
+    fn test_deposit_refunded_on_passed_proposal() {
+        let actions = vec![
+            GovernanceAction::VoteInFavor(1, User::Bob, 0),
+            GovernanceAction::OneTimeUnitPassed,
+            GovernanceAction::OneTimeUnitPassed,
+            GovernanceAction::OneTimeUnitPassed,
+        ];
+        let state = GovernanceState::apply_all(&state_with_deposited_proposal(), &actions);
+
+        assert_eq!(state.balance_of(&User::Alice), 100);
+        assert!(state.locked_deposits.is_empty());
+    }
+
+    #[test]
+    fn test_deposit_slashed_on_failed_proposal() {
+        let actions = vec![
+            GovernanceAction::VoteAgainst(1, User::Bob, 0),
+            GovernanceAction::OneTimeUnitPassed,
+            GovernanceAction::OneTimeUnitPassed,
+            GovernanceAction::OneTimeUnitPassed,
+        ];
+        let state = GovernanceState::apply_all(&state_with_deposited_proposal(), &actions);
+
+        assert_eq!(state.balance_of(&User::Alice), 70);
+        assert!(state.locked_deposits.is_empty());
+    }
+
+    #[test]
+    fn test_deposit_refunded_on_cancellation() {
+        let state = state_with_deposited_proposal();
+
+        // Only the proposer can cancel
+        assert!(GovernanceState::is_noop(
+            &state,
+            &GovernanceAction::CancelProposal(1, User::Bob)
+        ));
+
+        let cancelled =
+            GovernanceState::next_state(&state, &GovernanceAction::CancelProposal(1, User::Alice));
+        assert_eq!(cancelled.balance_of(&User::Alice), 100);
+        assert!(!cancelled.proposal_exists_and_pending(1));
+    }
+
+    #[test]
+    fn test_deposit_refund_saturates_balance() {
+        let state = GovernanceState::next_state(
+            &state_with_deposited_proposal(),
+            &GovernanceAction::Fund(User::Alice, u64::MAX),
+        );
+        assert_eq!(state.balance_of(&User::Alice), u64::MAX);
+
+        let cancelled =
+            GovernanceState::next_state(&state, &GovernanceAction::CancelProposal(1, User::Alice));
+        assert_eq!(cancelled.balance_of(&User::Alice), u64::MAX);
+    }
+
+    #[test]
+    fn test_expired_proposal_is_archived_after_tick() {
+        let actions = vec![
+            GovernanceAction::RegisterVotingPower(User::Bob, 10),
+            GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 1),
+            GovernanceAction::VoteInFavor(1, User::Bob, 0),
+            GovernanceAction::OneTimeUnitPassed,
+        ];
+        let state = GovernanceState::apply_all(&GovernanceState::new(), &actions);
+
+        // Still pending at the last time unit of its lifetime
+        assert_eq!(state.proposals.len(), 1);
+        assert_eq!(state.final_result(1), None);
+
+        let state = GovernanceState::next_state(&state, &GovernanceAction::OneTimeUnitPassed);
+        assert!(state.proposals.is_empty());
+        assert!(state.votes.is_empty());
+        assert_eq!(state.archived[0].final_tally, Some((10, 0)));
+        assert_eq!(state.final_result(1), Some(true));
+    }
+
+    #[test]
+    fn test_proposal_ids_stay_unique_after_archiving() {
+        let actions = vec![
+            GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 0),
+            GovernanceAction::OneTimeUnitPassed,
+            GovernanceAction::AddProposal("Raise the fees".to_string(), User::Bob, 5),
+        ];
+        let state = GovernanceState::apply_all(&GovernanceState::new(), &actions);
+
+        assert_eq!(state.final_result(1), Some(false));
+        assert_eq!(state.proposals[0].id, 2);
+    }
+
+    #[test]
+    fn test_vote_uses_stake_in_force_when_cast() {
+        let actions = vec![
+            GovernanceAction::RegisterVotingPower(User::Alice, 10),
+            GovernanceAction::AddProposal("Lower the fees".to_string(), User::Bob, 10),
+            GovernanceAction::OneTimeUnitPassed,
+            GovernanceAction::OneTimeUnitPassed,
+            GovernanceAction::RegisterVotingPower(User::Alice, 30),
+            GovernanceAction::OneTimeUnitPassed,
+            GovernanceAction::VoteInFavor(1, User::Alice, 0),
+            // Alice's stake changes after she voted at time 3
+            GovernanceAction::OneTimeUnitPassed,
+            GovernanceAction::RegisterVotingPower(User::Alice, 100),
+        ];
+        let state = GovernanceState::apply_all(&GovernanceState::new(), &actions);
+
+        assert_eq!(state.votes[0].cast_at, 3);
+        assert_eq!(state.stake_at(User::Alice, 1), 10);
+        assert_eq!(state.stake_at(User::Alice, 3), 30);
+        assert_eq!(state.stake_at(User::Alice, 4), 100);
+        assert_eq!(state.voting_power_of(&User::Alice), 100);
+        assert_eq!(state.tally(1), (30, 0));
+    }
+
+    #[test]
+    fn test_stake_before_first_snapshot_is_zero() {
+        let state = GovernanceState::apply_all(
+            &GovernanceState::new(),
+            &[
+                GovernanceAction::OneTimeUnitPassed,
+                GovernanceAction::RegisterVotingPower(User::Alice, 10),
+            ],
+        );
+
+        assert_eq!(state.stake_history.len(), 1);
+        assert_eq!(state.stake_at(User::Alice, 0), 0);
+        assert_eq!(state.stake_at(User::Bob, 1), 0);
+    }
+
+    fn state_with_three_options() -> GovernanceState {
+        let actions = vec![
+            GovernanceAction::RegisterVotingPower(User::Alice, 10),
+            GovernanceAction::RegisterVotingPower(User::Bob, 10),
+            GovernanceAction::RegisterVotingPower(User::Charlie, 10),
+            GovernanceAction::AddMultiChoiceProposal(
+                "Pick the block time".to_string(),
+                User::Alice,
+                10,
+                vec!["6s".to_string(), "12s".to_string(), "24s".to_string()],
+            ),
+        ];
+        GovernanceState::apply_all(&GovernanceState::new(), &actions)
+    }
+
+    #[test]
+    fn test_multi_choice_clear_winner() {
+        let actions = vec![
+            GovernanceAction::VoteChoice(1, User::Alice, 1),
+            GovernanceAction::VoteChoice(1, User::Bob, 1),
+            GovernanceAction::VoteChoice(1, User::Charlie, 2),
+        ];
+        let state = GovernanceState::apply_all(&state_with_three_options(), &actions);
+
+        assert_eq!(state.winning_option(1), Some(1));
+    }
+
+    #[test]
+    fn test_multi_choice_totals_saturate() {
+        let actions = vec![
+            GovernanceAction::RegisterVotingPower(User::Alice, u64::MAX / 2 + 1),
+            GovernanceAction::RegisterVotingPower(User::Bob, u64::MAX / 2 + 1),
+            GovernanceAction::VoteChoice(1, User::Alice, 0),
+            GovernanceAction::VoteChoice(1, User::Bob, 0),
+            GovernanceAction::VoteChoice(1, User::Charlie, 1),
+        ];
+        let state = GovernanceState::apply_all(&state_with_three_options(), &actions);
+
+        assert_eq!(state.winning_option(1), Some(0));
+    }
+
+    #[test]
+    fn test_multi_choice_tie() {
+        let actions = vec![
+            GovernanceAction::VoteChoice(1, User::Alice, 0),
+            GovernanceAction::VoteChoice(1, User::Bob, 2),
+        ];
+        let state = GovernanceState::apply_all(&state_with_three_options(), &actions);
+
+        assert_eq!(state.winning_option(1), None);
+    }
+
+    #[test]
+    fn test_multi_choice_invalid_votes_rejected() {
+        let state = state_with_three_options();
+
+        // There is no fourth option
+        assert!(GovernanceState::is_noop(
+            &state,
+            &GovernanceAction::VoteChoice(1, User::Alice, 3)
+        ));
+        // Aye and nay do not apply to a multi choice proposal
+        assert!(GovernanceState::is_noop(
+            &state,
+            &GovernanceAction::VoteInFavor(1, User::Alice, 0)
+        ));
+        // Voting twice is not allowed
+        let voted =
+            GovernanceState::next_state(&state, &GovernanceAction::VoteChoice(1, User::Alice, 0));
+        assert!(GovernanceState::is_noop(
+            &voted,
+            &GovernanceAction::VoteChoice(1, User::Alice, 1)
+        ));
+        assert_eq!(voted.winning_option(1), Some(0));
+    }
+
+    #[test]
+    fn test_proposal_at_min_lifetime_accepted() {
+        let state = GovernanceState::apply_all(
+            &GovernanceState::with_min_lifetime(3),
+            &[
+                GovernanceAction::OneTimeUnitPassed,
+                GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 4),
+            ],
+        );
+
+        assert_eq!(state.proposals.len(), 1);
+    }
+
+    #[test]
+    fn test_proposal_below_min_lifetime_rejected() {
+        let state = GovernanceState::next_state(
+            &GovernanceState::with_min_lifetime(3),
+            &GovernanceAction::OneTimeUnitPassed,
+        );
+
+        assert!(GovernanceState::is_noop(
+            &state,
+            &GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 3)
+        ));
+    }
+
+    #[test]
+    fn test_display_shows_proposals_and_tally() {
+        let actions = vec![
+            GovernanceAction::RegisterVotingPower(User::Alice, 10),
+            GovernanceAction::RegisterVotingPower(User::Bob, 4),
+            GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 7),
+            GovernanceAction::OneTimeUnitPassed,
+            GovernanceAction::VoteInFavor(1, User::Alice, 0),
+            GovernanceAction::VoteAgainst(1, User::Bob, 0),
+        ];
+        let rendered = GovernanceState::apply_all(&GovernanceState::new(), &actions).to_string();
+
+        assert!(rendered.starts_with("Time unit: 1\n"));
+        assert!(rendered.contains("   1 | Alice    |         6 |     10 |      4"));
+    }
+
+    #[test]
+    fn test_with_proposals_and_time() {
+        let state = GovernanceState::with_proposals([
+            ("Lower the fees".to_string(), User::Alice, 8),
+            ("Raise the block size".to_string(), User::Bob, 10),
+        ])
+        .with_time(5);
+
+        assert_eq!(state.time_units_passed, 5);
+        assert_eq!(state.proposals.len(), 2);
+        assert_eq!(state.proposed_by(1), Some(User::Alice));
+        assert_eq!(state.proposed_by(2), Some(User::Bob));
+        assert!(state.proposal_exists_and_pending(2));
+    }
+
+    #[test]
+    fn test_supermajority_pass_ratio() {
+        let actions = vec![
+            GovernanceAction::RegisterVotingPower(User::Alice, 6),
+            GovernanceAction::RegisterVotingPower(User::Bob, 4),
+            GovernanceAction::AddProposal("Amend the constitution".to_string(), User::Alice, 10),
+            GovernanceAction::VoteInFavor(1, User::Alice, 0),
+            GovernanceAction::VoteAgainst(1, User::Bob, 0),
+        ];
+
+        // 6 out of 10 is a simple majority
+        let simple = GovernanceState::apply_all(&GovernanceState::new(), &actions);
+        assert!(simple.proposal_passed(1, None));
+
+        // But not a two thirds supermajority
+        let supermajority =
+            GovernanceState::apply_all(&GovernanceState::with_pass_ratio(2, 3), &actions);
+        assert!(!supermajority.proposal_passed(1, None));
+
+        // Exactly two thirds is enough
+        let more = GovernanceState::next_state(
+            &supermajority,
+            &GovernanceAction::RegisterVotingPower(User::Charlie, 2),
+        );
+        let more =
+            GovernanceState::next_state(&more, &GovernanceAction::VoteInFavor(1, User::Charlie, 0));
+        assert_eq!(more.tally(1), (8, 4));
+        assert!(more.proposal_passed(1, None));
+    }
+
+    #[test]
+    fn test_abstentions_count_toward_quorum_only() {
+        let actions = vec![
+            GovernanceAction::RegisterVotingPower(User::Alice, 10),
+            GovernanceAction::RegisterVotingPower(User::Bob, 5),
+            GovernanceAction::RegisterVotingPower(User::Charlie, 25),
+            GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 10),
+            GovernanceAction::VoteInFavor(1, User::Alice, 0),
+            GovernanceAction::VoteAgainst(1, User::Bob, 0),
+        ];
+        let state = GovernanceState::apply_all(&GovernanceState::new(), &actions);
+
+        // 15 out of 40 voting power turned out, short of a 50% quorum
+        assert_eq!(state.turnout(1), 15);
+        assert!(!state.proposal_passed(1, Some(50)));
+
+        let state =
+            GovernanceState::next_state(&state, &GovernanceAction::VoteAbstain(1, User::Charlie));
+
+        // The abstention reaches the quorum without changing the outcome
+        assert_eq!(state.turnout(1), 40);
+        assert_eq!(state.tally(1), (10, 5));
+        assert!(state.proposal_passed(1, Some(50)));
+
+        // An abstaining user can not vote again
+        let again = GovernanceState::next_state(
+            &state,
+            &GovernanceAction::VoteAgainst(1, User::Charlie, 0),
+        );
+        assert_eq!(again.tally(1), (10, 5));
+    }
+
+    #[test]
+    fn test_tally_saturates_with_huge_voting_power() {
+        let actions = vec![
+            GovernanceAction::RegisterVotingPower(User::Alice, u64::MAX),
+            GovernanceAction::RegisterVotingPower(User::Bob, u64::MAX),
+            GovernanceAction::AddProposal("Print more money".to_string(), User::Alice, 1),
+            GovernanceAction::VoteInFavor(1, User::Alice, 0),
+            GovernanceAction::VoteInFavor(1, User::Bob, 0),
+        ];
+        let state = GovernanceState::apply_all(&GovernanceState::new(), &actions);
+
+        assert_eq!(state.total_voting_power(), u64::MAX);
+        assert_eq!(state.tally(1), (u64::MAX, 0));
+        assert_eq!(state.turnout(1), u64::MAX);
+        assert!(state.proposal_passed(1, Some(100)));
+        assert!(!state.to_string().is_empty());
+
+        let state = GovernanceState::apply_all(
+            &state,
+            &[
+                GovernanceAction::OneTimeUnitPassed,
+                GovernanceAction::OneTimeUnitPassed,
+            ],
+        );
+        assert_eq!(state.final_result(1), Some(true));
+    }
+
+    #[test]
+    fn test_conviction_never_lowers_a_large_stake() {
+        let actions = vec![
+            GovernanceAction::RegisterVotingPower(User::Alice, u64::MAX / 2),
+            GovernanceAction::RegisterVotingPower(User::Bob, u64::MAX / 2),
+            GovernanceAction::AddProposal("Lower the fees".to_string(), User::Alice, 10),
+            GovernanceAction::VoteInFavor(1, User::Alice, MAX_CONVICTION),
+            GovernanceAction::VoteAgainst(1, User::Bob, 0),
+        ];
+        let state = GovernanceState::apply_all(&GovernanceState::new(), &actions);
+
+        assert_eq!(state.tally(1), (u64::MAX, u64::MAX / 2));
+        assert!(state.proposal_passed(1, None));
+    }
 }
@@ -0,0 +1,281 @@
+//! In the digital cash system every bill simply belongs to an owner. Here we take a step toward
+//! scriptable UTXOs. Rather than an owner, each bill carries a tiny spending condition, and a
+//! transfer must satisfy the condition of every bill it spends.
+
+use super::{StateMachine, User};
+use std::collections::{BTreeMap, HashSet};
+
+/// This state machine models a currency of bills that are locked by spending conditions.
+pub struct ScriptedCashSystem;
+
+/// The condition that must be satisfied to spend a bill.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SpendingCondition {
+    /// The given user must sign the spending transfer.
+    RequireSigner(User),
+    /// The bill can not be spent before the given time.
+    Timelock(u64),
+    /// Every one of the conditions must be satisfied.
+    AllOf(Vec<SpendingCondition>),
+}
+
+impl SpendingCondition {
+    /// Whether the condition is satisfied by a transfer with the given signers at the given time.
+    fn is_satisfied(&self, signers: &[User], now: u64) -> bool {
+        match self {
+            SpendingCondition::RequireSigner(user) => signers.contains(user),
+            SpendingCondition::Timelock(unlock_time) => now >= *unlock_time,
+            SpendingCondition::AllOf(conditions) => {
+                conditions.iter().all(|c| c.is_satisfied(signers, now))
+            }
+        }
+    }
+}
+
+/// A bill worth some amount, that can be spent by whoever satisfies its condition.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ScriptedBill {
+    amount: u64,
+    condition: SpendingCondition,
+}
+
+impl ScriptedBill {
+    pub fn new(amount: u64, condition: SpendingCondition) -> Self {
+        ScriptedBill { amount, condition }
+    }
+}
+
+/// The state of the scripted cash system. The circulating bills are keyed by their serial.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct State {
+    bills: BTreeMap<u64, ScriptedBill>,
+    /// The serial that the next created bill will get.
+    next_serial: u64,
+    /// The current time, advanced by `Tick`. Timelocks are relative to this clock.
+    current_time: u64,
+}
+
+impl State {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bill(&self, serial: u64) -> Option<&ScriptedBill> {
+        self.bills.get(&serial)
+    }
+
+    /// The total value of all circulating bills, saturating at `u64::MAX`.
+    pub fn total_supply(&self) -> u64 {
+        self.checked_total_supply().unwrap_or(u64::MAX)
+    }
+
+    /// The total value of all circulating bills, or `None` if it overflows.
+    pub fn checked_total_supply(&self) -> Option<u64> {
+        self.bills
+            .values()
+            .try_fold(0u64, |acc, b| acc.checked_add(b.amount))
+    }
+
+    fn add_bill(&mut self, bill: ScriptedBill) {
+        self.bills.insert(self.next_serial, bill);
+        self.next_serial += 1;
+    }
+}
+
+/// The state transitions that users can make in the scripted cash system.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ScriptedTransaction {
+    /// Mint a new bill locked by the given condition.
+    Mint { bill: ScriptedBill },
+    /// Spend some bills, identified by serial, and create new ones. The signers must satisfy the
+    /// condition of every spent bill. As in the digital cash system, the amount received must not
+    /// exceed the amount spent, and the discrepancy is destroyed.
+    Transfer {
+        spends: Vec<u64>,
+        signers: Vec<User>,
+        receives: Vec<ScriptedBill>,
+    },
+    /// Advance the clock by one.
+    Tick,
+}
+
+impl StateMachine for ScriptedCashSystem {
+    type State = State;
+    type Transition = ScriptedTransaction;
+
+    fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+        match t {
+            ScriptedTransaction::Mint { bill } => {
+                // check for zero mints and mints that would overflow the total supply
+                if bill.amount == 0
+                    || starting_state
+                        .checked_total_supply()
+                        .and_then(|supply| supply.checked_add(bill.amount))
+                        .is_none()
+                {
+                    return starting_state.clone();
+                }
+
+                let mut new_state = starting_state.clone();
+                new_state.add_bill(bill.clone());
+                new_state
+            }
+
+            ScriptedTransaction::Transfer {
+                spends,
+                signers,
+                receives,
+            } => {
+                // check empty and duplicate spends
+                let unique: HashSet<&u64> = spends.iter().collect();
+                if spends.is_empty() || unique.len() != spends.len() {
+                    return starting_state.clone();
+                }
+
+                // check the spent bills exist and their conditions are satisfied
+                let spent: Option<Vec<&ScriptedBill>> =
+                    spends.iter().map(|s| starting_state.bill(*s)).collect();
+                let Some(spent) = spent else {
+                    return starting_state.clone();
+                };
+                if !spent.iter().all(|b| {
+                    b.condition
+                        .is_satisfied(signers, starting_state.current_time)
+                }) {
+                    return starting_state.clone();
+                }
+
+                // check for bills with output of 0
+                if receives.iter().any(|b| b.amount == 0) {
+                    return starting_state.clone();
+                }
+
+                // check spends >= receives, without overflowing
+                let total_spent = spent
+                    .iter()
+                    .try_fold(0u64, |acc, b| acc.checked_add(b.amount));
+                let total_received = receives
+                    .iter()
+                    .try_fold(0u64, |acc, b| acc.checked_add(b.amount));
+                match (total_spent, total_received) {
+                    (Some(spent), Some(received)) if received <= spent => {}
+                    _ => return starting_state.clone(),
+                }
+
+                let mut new_state = starting_state.clone();
+                for serial in spends {
+                    new_state.bills.remove(serial);
+                }
+                for bill in receives {
+                    new_state.add_bill(bill.clone());
+                }
+                new_state
+            }
+
+            ScriptedTransaction::Tick => {
+                let mut new_state = starting_state.clone();
+                new_state.current_time += 1;
+                new_state
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn timelocked_state(unlock_time: u64) -> State {
+    ScriptedCashSystem::next_state(
+        &State::new(),
+        &ScriptedTransaction::Mint {
+            bill: ScriptedBill::new(
+                20,
+                SpendingCondition::AllOf(vec![
+                    SpendingCondition::RequireSigner(User::Alice),
+                    SpendingCondition::Timelock(unlock_time),
+                ]),
+            ),
+        },
+    )
+}
+
+#[cfg(test)]
+fn spend_to_bob() -> ScriptedTransaction {
+    ScriptedTransaction::Transfer {
+        spends: vec![0],
+        signers: vec![User::Alice],
+        receives: vec![ScriptedBill::new(
+            20,
+            SpendingCondition::RequireSigner(User::Bob),
+        )],
+    }
+}
+
+#[test]
+fn sm_7_timelocked_bill_cannot_be_spent_early() {
+    let start = ScriptedCashSystem::apply_all(&timelocked_state(2), &[ScriptedTransaction::Tick]);
+
+    assert!(ScriptedCashSystem::is_noop(&start, &spend_to_bob()));
+}
+
+#[test]
+fn sm_7_timelocked_bill_can_be_spent_after_unlock() {
+    let start = ScriptedCashSystem::apply_all(
+        &timelocked_state(2),
+        &[ScriptedTransaction::Tick, ScriptedTransaction::Tick],
+    );
+    let end = ScriptedCashSystem::next_state(&start, &spend_to_bob());
+
+    assert_eq!(end.bill(0), None);
+    assert_eq!(
+        end.bill(1),
+        Some(&ScriptedBill::new(
+            20,
+            SpendingCondition::RequireSigner(User::Bob)
+        ))
+    );
+    assert_eq!(end.total_supply(), 20);
+}
+
+#[test]
+fn sm_7_spend_requires_signer() {
+    let start = timelocked_state(0);
+    let transfer = ScriptedTransaction::Transfer {
+        spends: vec![0],
+        signers: vec![User::Bob],
+        receives: vec![ScriptedBill::new(
+            20,
+            SpendingCondition::RequireSigner(User::Bob),
+        )],
+    };
+
+    assert!(ScriptedCashSystem::is_noop(&start, &transfer));
+    assert!(!ScriptedCashSystem::is_noop(&start, &spend_to_bob()));
+}
+
+#[test]
+fn sm_7_transfer_cannot_create_value() {
+    let start = timelocked_state(0);
+    let transfer = ScriptedTransaction::Transfer {
+        spends: vec![0],
+        signers: vec![User::Alice],
+        receives: vec![ScriptedBill::new(
+            21,
+            SpendingCondition::RequireSigner(User::Bob),
+        )],
+    };
+
+    assert!(ScriptedCashSystem::is_noop(&start, &transfer));
+}
+
+#[test]
+fn sm_7_mint_overflowing_supply_is_noop() {
+    let mint = ScriptedTransaction::Mint {
+        bill: ScriptedBill::new(
+            u64::MAX / 2 + 1,
+            SpendingCondition::RequireSigner(User::Alice),
+        ),
+    };
+    let start = ScriptedCashSystem::next_state(&State::new(), &mint);
+
+    assert!(ScriptedCashSystem::is_noop(&start, &mint));
+    assert_eq!(start.total_supply(), u64::MAX / 2 + 1);
+}
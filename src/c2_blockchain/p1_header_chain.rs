@@ -66,6 +66,18 @@ impl Header {
     }
 }
 
+/// Check that each header's height is exactly one more than its predecessor's. The chain does not
+/// have to start at genesis, so the first header may have any height.
+fn has_contiguous_heights(chain: &[Header]) -> bool {
+    chain.windows(2).all(|w| w[1].height == w[0].height + 1)
+}
+
+/// Check that the headers form a hash-linked chain without height gaps. Like
+/// `has_contiguous_heights`, this does not require the chain to start at genesis.
+fn is_well_linked(chain: &[Header]) -> bool {
+    has_contiguous_heights(chain) && chain.windows(2).all(|w| w[1].parent == hash(&w[0]))
+}
+
 // And finally a few functions to use the code we just
 
 /// Build and return a valid chain with exactly five blocks including the genesis block.
@@ -176,3 +188,32 @@ fn bc_1_invalid_chain_is_really_invalid() {
     let invalid_chain = build_an_invalid_chain();
     assert!(!invalid_chain[0].verify_sub_chain(&invalid_chain[1..]))
 }
+
+#[test]
+fn bc_1_well_linked_chain() {
+    let chain = build_valid_chain_length_5();
+    assert!(has_contiguous_heights(&chain));
+    assert!(is_well_linked(&chain));
+
+    // A chain that does not start at genesis is fine too
+    assert!(is_well_linked(&chain[2..]));
+    assert!(is_well_linked(&[]));
+}
+
+#[test]
+fn bc_1_height_gap_is_not_well_linked() {
+    let mut chain = build_valid_chain_length_5();
+    chain[3].height += 1;
+
+    assert!(!has_contiguous_heights(&chain));
+    assert!(!is_well_linked(&chain));
+}
+
+#[test]
+fn bc_1_broken_parent_is_not_well_linked() {
+    let mut chain = build_valid_chain_length_5();
+    chain[3].parent = 10;
+
+    assert!(has_contiguous_heights(&chain));
+    assert!(!is_well_linked(&chain));
+}
@@ -6,6 +6,12 @@ type Hash = u64;
 
 const THRESHOLD: u64 = u64::max_value() / 10;
 
+/// The furthest back, in generations from the including block, that an uncle's parent may be.
+const MAX_UNCLE_GENERATIONS: usize = 7;
+
+/// The reward for authoring a block. Uncles earn a fraction of it that shrinks with their age.
+const BLOCK_REWARD: u64 = 64;
+
 /// The header no longer contains an extrinsic directly. Rather a vector of extrinsics will be stored in
 /// the block body. We are still storing the state in the header for now. This will change in an upcoming
 /// lesson as well.
@@ -17,10 +23,29 @@ pub struct Header {
     // This is basically a concise cryptographic commitment to the complete list of extrinsics.
     // For example, a hash or a Merkle root.
     extrinsics_root: Hash,
+    // A commitment to the block's uncles, so that they can not be swapped out after the fact.
+    uncles_root: Hash,
     state: u64,
     pub consensus_digest: u64,
 }
 
+/// The commitment to a list of uncles that is stored in the header.
+fn uncles_root(uncles: &[Header]) -> Hash {
+    hash(&uncles)
+}
+
+/// The parameters that distinguish one network's genesis from another's.
+///
+/// Without these, every network built from this code would share an identical genesis block,
+/// and blocks from one network would be valid on all the others.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct GenesisConfig {
+    /// The initial state of the chain.
+    pub state: u64,
+    /// A unique identifier for the network.
+    pub chain_id: u64,
+}
+
 // Methods for creating and verifying headers.
 //
 // With the extrinsics no longer stored in the header, we can no longer do
@@ -30,11 +55,21 @@ pub struct Header {
 impl Header {
     /// Returns a new valid genesis header.
     pub fn genesis() -> Self {
+        Self::genesis_with(&GenesisConfig::default())
+    }
+
+    /// Returns a new valid genesis header for the network described by the given config.
+    ///
+    /// The genesis header has no parent, so its parent field carries the chain id instead.
+    /// That way the chain id is committed to in the genesis hash, and therefore in every
+    /// block that descends from it.
+    pub fn genesis_with(config: &GenesisConfig) -> Self {
         Header {
-            parent: 0,
+            parent: config.chain_id,
             height: 0,
             extrinsics_root: 0,
-            state: 0,
+            uncles_root: 0,
+            state: config.state,
             consensus_digest: 0,
         }
     }
@@ -43,11 +78,17 @@ impl Header {
     /// Without the extrinsics themselves, we cannot calculate the final state
     /// so that information is passed in.
     pub fn child(&self, extrinsics_root: Hash, state: u64) -> Self {
+        self.child_with_uncles(extrinsics_root, uncles_root(&[]), state)
+    }
+
+    /// Create and return a valid child header that commits to the given uncles root.
+    pub fn child_with_uncles(&self, extrinsics_root: Hash, uncles_root: Hash, state: u64) -> Self {
         for nonce in 0.. {
             let try_header = Header {
                 parent: hash(&self),
                 height: self.height + 1,
                 extrinsics_root,
+                uncles_root,
                 state,
                 consensus_digest: nonce,
             };
@@ -59,6 +100,29 @@ impl Header {
         unreachable!();
     }
 
+    /// Create a child header with an explicit height and parent instead of deriving them from this
+    /// header. The state is carried over unchanged and no mining is done.
+    ///
+    /// This is strictly for building intentionally malformed fixtures, such as headers with a
+    /// skipped height or a wrong parent, for negative tests.
+    #[cfg(test)]
+    fn child_raw(
+        &self,
+        extrinsics_root: Hash,
+        consensus_digest: u64,
+        height: u64,
+        parent: Hash,
+    ) -> Self {
+        Header {
+            parent,
+            height,
+            extrinsics_root,
+            uncles_root: uncles_root(&[]),
+            state: self.state,
+            consensus_digest,
+        }
+    }
+
     /// Verify a single child header.
     ///
     /// This is a slightly different interface from the previous units. Rather
@@ -106,10 +170,16 @@ impl Header {
 }
 
 /// A complete Block is a header and the extrinsics.
+///
+/// The extrinsics are plain numbers by default, but a block can carry any hashable extrinsic type,
+/// such as the transactions of one of the state machines from the first chapter.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
-pub struct Block {
+pub struct Block<E = u64> {
     pub(crate) header: Header,
-    pub(crate) body: Vec<u64>,
+    pub(crate) body: Vec<E>,
+    /// Valid headers that lost a fork race shortly before this block. Including them lets their
+    /// miners collect a reduced reward for the work that did not make it into the main chain.
+    pub(crate) uncles: Vec<Header>,
 }
 
 // Methods for creating and verifying blocks.
@@ -117,30 +187,123 @@ pub struct Block {
 // These methods are analogous to the methods on the headers. All of the
 // transaction execution logic is now handled at the block level because
 // the transactions are no longer available at the Header level.
-impl Block {
-    /// Returns a new valid genesis block. By convention this block has no extrinsics.
-    pub fn genesis() -> Self {
+//
+// Executing the extrinsics requires knowing what they are, so only blocks of plain numbers can do
+// that. Everything else works for any extrinsic type.
+impl<E: std::hash::Hash> Block<E> {
+    /// Returns a new valid genesis block for the network described by the given config.
+    /// By convention this block has no extrinsics.
+    pub fn genesis_with(config: &GenesisConfig) -> Self {
         Block {
-            header: Header::genesis(),
+            header: Header::genesis_with(config),
             body: vec![],
+            uncles: vec![],
+        }
+    }
+
+    /// Create and return a valid child block, given the state that results from executing the
+    /// extrinsics.
+    pub fn child_with_state(&self, extrinsics: Vec<E>, state: u64) -> Self {
+        Block {
+            header: self.header.child(Self::root_of(&extrinsics), state),
+            body: extrinsics,
+            uncles: vec![],
         }
     }
 
+    /// The commitment to the block's extrinsics that is stored in the header.
+    pub fn extrinsics_root(&self) -> Hash {
+        Self::root_of(&self.body)
+    }
+
+    fn root_of(extrinsics: &[E]) -> Hash {
+        hash(&extrinsics)
+    }
+
+    /// The commitment to the block's uncles that is stored in the header.
+    pub fn uncles_root(&self) -> Hash {
+        uncles_root(&self.uncles)
+    }
+
+    /// Verify the uncles of this block against the main chain leading up to it. The ancestors
+    /// must end with this block's parent and reach back at least `MAX_UNCLE_GENERATIONS`
+    /// generations, or to genesis.
+    ///
+    /// Each uncle must be a valid child of an ancestor other than the parent, at most
+    /// `MAX_UNCLE_GENERATIONS` generations back. It must not be part of the main chain, and must
+    /// not have been included as an uncle before.
+    pub fn verify_uncles(&self, ancestors: &[Block<E>]) -> bool {
+        if self.uncles.is_empty() {
+            return true;
+        }
+
+        match ancestors.last() {
+            Some(parent) if hash(&parent.header) == self.header.parent => {}
+            _ => return false,
+        }
+
+        let oldest = ancestors.len().saturating_sub(MAX_UNCLE_GENERATIONS);
+        let eligible_parents = &ancestors[oldest..ancestors.len() - 1];
+
+        self.uncles.iter().enumerate().all(|(i, uncle)| {
+            let already_included = self.uncles[..i].contains(uncle)
+                || ancestors
+                    .iter()
+                    .any(|b| &b.header == uncle || b.uncles.contains(uncle));
+
+            !already_included
+                && eligible_parents
+                    .iter()
+                    .any(|b| b.header.verify_child(uncle))
+        })
+    }
+
+    /// The total reward paid to the miners of this block's uncles. An uncle one generation older
+    /// than this block earns 7/8 of the block reward, and each further generation costs another
+    /// eighth.
+    pub fn uncle_reward(&self) -> u64 {
+        let denominator = MAX_UNCLE_GENERATIONS as u64 + 1;
+        self.uncles
+            .iter()
+            .map(|uncle| {
+                let depth = self.header.height.saturating_sub(uncle.height);
+                BLOCK_REWARD * denominator.saturating_sub(depth) / denominator
+            })
+            .sum()
+    }
+}
+
+impl Block {
+    /// Returns a new valid genesis block. By convention this block has no extrinsics.
+    pub fn genesis() -> Self {
+        Self::genesis_with(&GenesisConfig::default())
+    }
+
     /// Create and return a valid child block.
     /// The extrinsics are batched now, so we need to execute each of them.
     pub fn child(&self, extrinsics: Vec<u64>) -> Self {
+        self.child_with_uncles(extrinsics, vec![])
+    }
+
+    /// Create and return a valid child block that includes the given uncles.
+    pub fn child_with_uncles(&self, extrinsics: Vec<u64>, uncles: Vec<Header>) -> Self {
+        let state = self.header.state + extrinsics.iter().sum::<u64>();
         Block {
-            header: self.header.child(
-                hash(&extrinsics),
-                self.header.state + extrinsics.iter().sum::<u64>(),
+            header: self.header.child_with_uncles(
+                Self::root_of(&extrinsics),
+                uncles_root(&uncles),
+                state,
             ),
             body: extrinsics,
+            uncles,
         }
     }
 
     /// Verify that all the given blocks form a valid chain from this block to the tip.
     ///
     /// We need to verify the headers as well as execute all transactions and check the final state.
+    /// The uncles must match the header's commitment, but whether they are eligible is not checked
+    /// here, see `verify_uncles`.
     pub fn verify_sub_chain(&self, chain: &[Block]) -> bool {
         if chain.is_empty() {
             return true;
@@ -153,7 +316,8 @@ impl Block {
         if child_header.parent != hash(&self.header)
             || child_header.height != self.header.height + 1
             || child_header.state != self.header.state + child.body.iter().sum::<u64>()
-            || child_header.extrinsics_root != hash(&child.body)
+            || child_header.extrinsics_root != child.extrinsics_root()
+            || child_header.uncles_root != child.uncles_root()
             || hash(&child_header) >= THRESHOLD
         {
             return false;
@@ -187,7 +351,11 @@ fn build_invalid_child_block_with_valid_header(parent: &Header) -> Block {
     );
     let body = extrinsics;
 
-    Block { header, body }
+    Block {
+        header,
+        body,
+        uncles: vec![],
+    }
 }
 
 #[test]
@@ -219,7 +387,8 @@ fn bc_4_child_block_empty() {
         b1,
         Block {
             header: b1.header.clone(),
-            body: vec![]
+            body: vec![],
+            uncles: vec![],
         }
     );
 }
@@ -235,7 +404,8 @@ fn bc_4_child_block() {
         b1,
         Block {
             header: b1.header.clone(),
-            body: vec![1, 2, 3, 4, 5]
+            body: vec![1, 2, 3, 4, 5],
+            uncles: vec![],
         }
     );
 }
@@ -274,6 +444,7 @@ fn bc_4_invalid_header_does_not_check() {
         parent: 0,
         height: 100,
         extrinsics_root: 0,
+        uncles_root: 0,
         state: 100,
         consensus_digest: 0,
     };
@@ -313,3 +484,157 @@ fn bc_4_student_invalid_block_really_is_invalid() {
     // Make sure that the block is not valid when executed.
     assert!(!gb.verify_sub_chain(&[b1]));
 }
+
+#[test]
+fn bc_4_genesis_depends_on_chain_id() {
+    let main = Block::<u64>::genesis_with(&GenesisConfig {
+        state: 0,
+        chain_id: 1,
+    });
+    let test = Block::<u64>::genesis_with(&GenesisConfig {
+        state: 0,
+        chain_id: 2,
+    });
+
+    assert_ne!(hash(&main.header), hash(&test.header));
+    assert_eq!(
+        Block::genesis(),
+        Block::genesis_with(&GenesisConfig::default())
+    );
+
+    // Chains built on each genesis stay distinct
+    let b1 = main.child(vec![1]);
+    assert!(main.verify_sub_chain(std::slice::from_ref(&b1)));
+    assert!(!test.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_4_genesis_with_initial_state() {
+    let g = Header::genesis_with(&GenesisConfig {
+        state: 10,
+        chain_id: 0,
+    });
+    let h1 = g.child(hash(&[1, 2]), 13);
+
+    assert_eq!(g.state, 10);
+    assert!(g.verify_child(&h1));
+}
+
+#[test]
+fn bc_4_skipped_height_does_not_check() {
+    let g = Header::genesis();
+    let h1 = g.child(hash(&[1]), 1);
+
+    // Everything but the height is right, and the nonce is mined honestly
+    let mut skipped = h1.child_raw(hash(&[2]), 0, h1.height + 2, hash(&h1));
+    while hash(&skipped) >= THRESHOLD {
+        skipped.consensus_digest += 1;
+    }
+
+    assert!(!h1.verify_child(&skipped));
+    assert!(!g.verify_sub_chain(&[h1, skipped]));
+}
+
+/// Build a main chain of the given length including genesis.
+#[cfg(test)]
+fn build_main_chain(length: u64) -> Vec<Block> {
+    let mut chain = vec![Block::genesis()];
+    for i in 1..length {
+        chain.push(chain.last().unwrap().child(vec![i]));
+    }
+    chain
+}
+
+#[test]
+fn bc_4_block_with_valid_uncle() {
+    let chain = build_main_chain(4);
+    // A competitor of the block at height 2 that lost the race
+    let uncle = chain[1]
+        .header
+        .child(hash(&[99]), chain[1].header.state + 99);
+
+    let b4 = chain[3].child_with_uncles(vec![4], vec![uncle.clone()]);
+    assert!(b4.verify_uncles(&chain));
+    assert!(chain[0].verify_sub_chain(&[&chain[1..], std::slice::from_ref(&b4)].concat()));
+
+    // The uncle is two generations older than the block
+    assert_eq!(b4.uncle_reward(), BLOCK_REWARD * 6 / 8);
+    assert_eq!(chain[3].child(vec![4]).uncle_reward(), 0);
+
+    // The same uncle can not be rewarded twice
+    let mut extended = chain.clone();
+    extended.push(b4.clone());
+    let b5 = b4.child_with_uncles(vec![5], vec![uncle]);
+    assert!(!b5.verify_uncles(&extended));
+}
+
+#[test]
+fn bc_4_block_with_tampered_uncles_does_not_check() {
+    let chain = build_main_chain(4);
+    let uncle = chain[1]
+        .header
+        .child(hash(&[99]), chain[1].header.state + 99);
+    let b4 = chain[3].child_with_uncles(vec![4], vec![uncle.clone()]);
+
+    // Adding uncles that the header does not commit to inflates the reward
+    let mut padded = b4.clone();
+    padded.uncles.push(uncle);
+    assert!(padded.uncle_reward() > b4.uncle_reward());
+    assert!(!chain[3].verify_sub_chain(&[padded]));
+
+    // Dropping them is caught as well
+    let mut stripped = b4;
+    stripped.uncles.clear();
+    assert!(!chain[3].verify_sub_chain(&[stripped]));
+}
+
+#[test]
+fn bc_4_block_with_main_chain_uncle_does_not_check() {
+    let chain = build_main_chain(4);
+
+    let b4 = chain[3].child_with_uncles(vec![4], vec![chain[2].header.clone()]);
+    assert!(!b4.verify_uncles(&chain));
+}
+
+#[test]
+fn bc_4_block_with_too_old_uncle_does_not_check() {
+    let chain = build_main_chain(MAX_UNCLE_GENERATIONS as u64 + 1);
+    let uncle = chain[0].header.child(hash(&[99]), 99);
+
+    // The uncle's parent is the genesis block, one generation too far back
+    let block = chain
+        .last()
+        .unwrap()
+        .child_with_uncles(vec![], vec![uncle.clone()]);
+    assert!(!block.verify_uncles(&chain));
+
+    // One block earlier the same uncle would still have been accepted
+    let parent = &chain[chain.len() - 2];
+    let block = parent.child_with_uncles(vec![], vec![uncle]);
+    assert!(block.verify_uncles(&chain[..chain.len() - 1]));
+}
+
+#[test]
+fn bc_4_block_of_cash_transactions() {
+    use crate::c1_state_machine::p5_digital_cash::{Bill, CashTransaction};
+    use crate::c1_state_machine::User;
+
+    let extrinsics = vec![
+        CashTransaction::Mint {
+            minter: User::Alice,
+            amount: 20,
+        },
+        CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, 20, 0)],
+            receives: vec![Bill::new(User::Bob, 20, 1)],
+        },
+    ];
+
+    let g = Block::<CashTransaction>::genesis_with(&GenesisConfig::default());
+    let b1 = g.child_with_state(extrinsics.clone(), 20);
+
+    assert_eq!(b1.extrinsics_root(), hash(&extrinsics));
+    assert_eq!(b1.header.extrinsics_root, b1.extrinsics_root());
+    assert_eq!(b1.body, extrinsics);
+    assert!(g.header.verify_child(&b1.header));
+}
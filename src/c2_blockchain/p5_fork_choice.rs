@@ -8,7 +8,8 @@
 //! we will import them from the previous lesson.
 
 use super::p4_batched_extrinsics::{Block, Header};
-use crate::hash;
+use crate::{hash, DefaultHash, Hasher};
+use std::marker::PhantomData;
 
 const THRESHOLD: u64 = u64::max_value() / 100;
 
@@ -62,7 +63,13 @@ impl ForkChoice for LongestChainRule {
 /// nonces. Modeling the amount of work required to achieve a particular hash
 /// is out of scope for this exercise, so we will use the not-really-right-but
 /// conceptually-good-enough formula `work = THRESHOLD - block_hash`
-pub struct HeaviestChainRule;
+///
+/// The hasher that block hashes are calculated with can be swapped out. Most users want
+/// `HeaviestChainRule`, which uses the crate's default hasher.
+pub struct HeaviestChainRuleBy<H>(PhantomData<H>);
+
+/// The heaviest chain rule using the crate's default hasher.
+pub type HeaviestChainRule = HeaviestChainRuleBy<DefaultHash>;
 
 /// Mutates a block (and its embedded header) to contain more PoW difficulty.
 /// This will be useful for exploring the heaviest chain rule. The expected
@@ -80,15 +87,16 @@ fn mine_extra_hard(block: &mut Block, threshold: u64) {
     }
 }
 
-impl ForkChoice for HeaviestChainRule {
+impl<H: Hasher + Default> ForkChoice for HeaviestChainRuleBy<H> {
     fn first_chain_is_better(chain_1: &[Header], chain_2: &[Header]) -> bool {
+        let hasher = H::default();
         let work_chain1: u64 = chain_1
             .iter()
-            .map(|h| THRESHOLD.checked_sub(hash(h)).unwrap_or(0))
+            .map(|h| THRESHOLD.checked_sub(hasher.hash(h)).unwrap_or(0))
             .sum();
         let work_chain2: u64 = chain_2
             .iter()
-            .map(|h| THRESHOLD.checked_sub(hash(h)).unwrap_or(0))
+            .map(|h| THRESHOLD.checked_sub(hasher.hash(h)).unwrap_or(0))
             .sum();
 
         print!("work_chain1: {}, work_chain2: {}", work_chain1, work_chain2);
@@ -98,17 +106,76 @@ impl ForkChoice for HeaviestChainRule {
 
     fn best_chain<'a>(candidate_chains: &[&'a [Header]]) -> &'a [Header] {
         // Remember, this method is provided.
+        let hasher = H::default();
+        candidate_chains
+            .iter()
+            .max_by_key(|chain| {
+                chain
+                    .iter()
+                    .map(|h| THRESHOLD.checked_sub(hasher.hash(h)).unwrap_or(0))
+                    .sum::<u64>()
+            })
+            .unwrap()
+    }
+}
+
+/// A header along with its hash, which is computed once when the header is wrapped.
+///
+/// Fork choice rules hash every header of every candidate chain, often repeatedly. Clients that
+/// compare the same chains many times can wrap their headers to avoid that. The header can not be
+/// mutated through the wrapper, so the cached hash never goes stale. To change the header, unwrap
+/// it and wrap the changed header again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HashedHeader {
+    header: Header,
+    hash: u64,
+}
+
+impl HashedHeader {
+    pub fn new(header: Header) -> Self {
+        let hash = hash(&header);
+        HashedHeader { header, hash }
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The cached hash of the header.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl From<Header> for HashedHeader {
+    fn from(header: Header) -> Self {
+        HashedHeader::new(header)
+    }
+}
+
+impl From<HashedHeader> for Header {
+    fn from(hashed: HashedHeader) -> Self {
+        hashed.header
+    }
+}
+
+impl HeaviestChainRule {
+    /// Like `best_chain`, but for headers whose hashes are already known, so nothing is re-hashed.
+    /// The cached hashes are calculated with the default hasher, so this is only available for
+    /// the default heaviest chain rule.
+    pub fn best_hashed_chain<'a>(candidate_chains: &[&'a [HashedHeader]]) -> &'a [HashedHeader] {
         candidate_chains
             .iter()
             .max_by_key(|chain| {
                 chain
                     .iter()
-                    .map(|h| THRESHOLD.checked_sub(hash(h)).unwrap_or(0))
+                    .map(|h| THRESHOLD.saturating_sub(h.hash()))
                     .sum::<u64>()
             })
             .unwrap()
     }
 }
+
 /// The best chain is the one with the most blocks that have even hashes.
 ///
 /// This exact rule is a bit contrived, but it does model a family of fork choice rules
@@ -123,25 +190,161 @@ impl ForkChoice for HeaviestChainRule {
 /// 2. Interleaved Pow/PoA. In each round there is one author who is allowed to author.
 ///    Anyone else is allowed to mine a PoW-style block. The best chain is the one with
 ///    the most PoA blocks, and ties are broken by the most accumulated work.
-pub struct MostBlocksWithEvenHash;
+///
+/// As with the heaviest chain rule, the hasher can be swapped out.
+pub struct MostBlocksWithEvenHashBy<H>(PhantomData<H>);
+
+/// The most even blocks rule using the crate's default hasher.
+pub type MostBlocksWithEvenHash = MostBlocksWithEvenHashBy<DefaultHash>;
 
-impl ForkChoice for MostBlocksWithEvenHash {
+impl<H: Hasher + Default> ForkChoice for MostBlocksWithEvenHashBy<H> {
     fn first_chain_is_better(chain_1: &[Header], chain_2: &[Header]) -> bool {
-        let even_hashes_chain1: u64 = chain_1.iter().filter(|h| hash(h) % 2 == 0).count() as u64;
-        let even_hashes_chain2: u64 = chain_2.iter().filter(|h| hash(h) % 2 == 0).count() as u64;
+        let hasher = H::default();
+        let even_hashes_chain1: u64 =
+            chain_1.iter().filter(|h| hasher.hash(h) % 2 == 0).count() as u64;
+        let even_hashes_chain2: u64 =
+            chain_2.iter().filter(|h| hasher.hash(h) % 2 == 0).count() as u64;
 
         even_hashes_chain1 > even_hashes_chain2
     }
 
     fn best_chain<'a>(candidate_chains: &[&'a [Header]]) -> &'a [Header] {
         // Remember, this method is provided.
+        let hasher = H::default();
+        candidate_chains
+            .iter()
+            .max_by_key(|chain| chain.iter().filter(|h| hasher.hash(h) % 2 == 0).count() as u64)
+            .unwrap()
+    }
+}
+
+/// The best chain is the one with the highest average work per block.
+///
+/// This favors quality over quantity. Where the `HeaviestChainRule` prefers a long chain of
+/// weakly mined blocks as soon as their total work exceeds that of a short chain of strongly
+/// mined blocks, this rule keeps preferring the short, strong chain no matter how long the weak
+/// chain grows. An empty chain has no work per block at all.
+pub struct AverageWorkRule;
+
+impl AverageWorkRule {
+    fn average_work(chain: &[Header]) -> u128 {
+        if chain.is_empty() {
+            return 0;
+        }
+
+        chain_work(chain) / chain.len() as u128
+    }
+}
+
+impl ForkChoice for AverageWorkRule {
+    fn first_chain_is_better(chain_1: &[Header], chain_2: &[Header]) -> bool {
+        Self::average_work(chain_1) > Self::average_work(chain_2)
+    }
+
+    fn best_chain<'a>(candidate_chains: &[&'a [Header]]) -> &'a [Header] {
         candidate_chains
             .iter()
-            .max_by_key(|chain| chain.iter().filter(|h| hash(h) % 2 == 0).count() as u64)
+            .max_by_key(|chain| Self::average_work(chain))
             .unwrap()
     }
 }
 
+/// The best chain is the one with the most accumulated work, like in the `HeaviestChainRule`.
+/// Only when both chains contain exactly the same work is the longer chain better, like in the
+/// `LongestChainRule`.
+pub struct HeaviestThenLongest;
+
+impl ForkChoice for HeaviestThenLongest {
+    fn first_chain_is_better(chain_1: &[Header], chain_2: &[Header]) -> bool {
+        (chain_work(chain_1), chain_1.len()) > (chain_work(chain_2), chain_2.len())
+    }
+
+    fn best_chain<'a>(candidate_chains: &[&'a [Header]]) -> &'a [Header] {
+        candidate_chains
+            .iter()
+            .max_by_key(|chain| (chain_work(chain), chain.len()))
+            .unwrap()
+    }
+}
+
+/// The accumulated work of a chain, using the same formula as the `HeaviestChainRule`.
+fn chain_work(chain: &[Header]) -> u128 {
+    chain
+        .iter()
+        .map(|h| THRESHOLD.saturating_sub(hash(h)) as u128)
+        .sum()
+}
+
+/// How much more work the first chain contains than the second. Negative if the
+/// second chain contains more work.
+fn work_difference(chain_1: &[Header], chain_2: &[Header]) -> i128 {
+    chain_work(chain_1) as i128 - chain_work(chain_2) as i128
+}
+
+/// A summary of how a peer's advertised chain compares to our local chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerScore {
+    /// Whether the peer's chain is better according to the fork choice rule.
+    pub ahead: bool,
+    /// How much more work the peer's chain contains than ours.
+    pub work_delta: i128,
+    /// How many more blocks the peer's chain contains than ours.
+    pub length_delta: i64,
+}
+
+/// Score a peer by comparing the chain it advertises to our local chain.
+///
+/// Clients use this to prioritize which peers to download blocks from. The comparison
+/// does not depend on the consensus engine, only on the given fork choice rule.
+fn score_peer_tip<F: ForkChoice>(local: &[Header], peer_tip_chain: &[Header]) -> PeerScore {
+    PeerScore {
+        ahead: F::first_chain_is_better(peer_tip_chain, local),
+        work_delta: work_difference(peer_tip_chain, local),
+        length_delta: peer_tip_chain.len() as i64 - local.len() as i64,
+    }
+}
+
+/// The header buried `k` blocks below the tip of the chain. Clients usually treat such a block
+/// as final, because a competing fork would have to outgrow all `k` confirmations on top of it.
+///
+/// Returns `None` if the chain has fewer than `k + 1` blocks.
+fn finalized_tip(chain: &[Header], k: usize) -> Option<&Header> {
+    chain.len().checked_sub(k + 1).map(|i| &chain[i])
+}
+
+/// Whether the target header is part of the chain and buried under at least `k` confirmations.
+fn is_final(chain: &[Header], target: &Header, k: usize) -> bool {
+    match chain.len().checked_sub(k + 1) {
+        Some(finalized) => chain[..=finalized].contains(target),
+        None => false,
+    }
+}
+
+/// Whether the candidate chain extends the base chain, i.e. the base chain is a prefix of the
+/// candidate. Headers are matched by their hash. A chain counts as an extension of itself.
+///
+/// Switching to an extension is a cheap append, while switching to any other chain is a reorg.
+fn is_extension_of(base: &[Header], candidate: &[Header]) -> bool {
+    base.len() <= candidate.len() && base.iter().zip(candidate).all(|(b, c)| hash(b) == hash(c))
+}
+
+/// The blocks that change when switching from the old chain to the new chain. Both chains are
+/// expected to start from the same block, and the last block they share is their common ancestor.
+///
+/// Returns the blocks reverted from the old chain, tip first, which is the order a wallet has to
+/// undo their transitions in. Also returns the blocks applied from the new chain, in chain order.
+fn reorg(old_chain: &[Header], new_chain: &[Header]) -> (Vec<Header>, Vec<Header>) {
+    let common = old_chain
+        .iter()
+        .zip(new_chain)
+        .take_while(|(old, new)| hash(old) == hash(new))
+        .count();
+
+    let reverted = old_chain[common..].iter().rev().cloned().collect();
+    let applied = new_chain[common..].to_vec();
+    (reverted, applied)
+}
+
 // This lesson has omitted one popular fork choice rule:
 // GHOST - Greedy Heaviest Observed SubTree
 //
@@ -183,6 +386,7 @@ fn create_fork_one_side_longer_other_side_heavier() -> (Vec<Header>, Vec<Header>
         let mut block_mined_extra_hard = Block {
             header: last_header.child(hash(&[i]), 28 + i),
             body: vec![i],
+            uncles: vec![],
         };
         mine_extra_hard(&mut block_mined_extra_hard, THRESHOLD / 10); // 10 times harder
         suffix_2.push(block_mined_extra_hard.header);
@@ -191,6 +395,41 @@ fn create_fork_one_side_longer_other_side_heavier() -> (Vec<Header>, Vec<Header>
     (prefix, suffix_1, suffix_2)
 }
 
+/// Build two competing chains that fork from the genesis block, for simulating a 51% attack.
+///
+/// The honest chain is built with the normal `child` method. The attacker chain is mined in
+/// private to the given threshold, so that users can experiment with when the
+/// `LongestChainRule` or `HeaviestChainRule` would switch over to the attacker's chain.
+///
+/// Neither returned chain includes the common genesis block.
+fn build_attack_scenario(
+    honest_len: usize,
+    attacker_len: usize,
+    threshold: u64,
+) -> (Vec<Header>, Vec<Header>) {
+    let genesis = Header::genesis();
+
+    let mut honest: Vec<Header> = vec![];
+    for i in 0..honest_len as u64 {
+        let parent = honest.last().unwrap_or(&genesis);
+        honest.push(parent.child(hash(&[i]), i));
+    }
+
+    let mut attacker: Vec<Header> = vec![];
+    for i in 0..attacker_len as u64 {
+        let parent = attacker.last().unwrap_or(&genesis);
+        let mut block = Block {
+            header: parent.child(hash(&[i, 1]), i),
+            body: vec![i, 1],
+            uncles: vec![],
+        };
+        mine_extra_hard(&mut block, threshold);
+        attacker.push(block.header);
+    }
+
+    (honest, attacker)
+}
+
 #[test]
 fn bc_5_longest_chain() {
     let g = Header::genesis();
@@ -323,3 +562,318 @@ fn bc_5_longest_vs_heaviest() {
         &pow_chain
     );
 }
+
+#[test]
+fn bc_5_score_peer_ahead() {
+    let g = Header::genesis();
+    let h1 = g.child(hash(&[1]), 1);
+    let h2 = h1.child(hash(&[2]), 3);
+    let local = &[g.clone(), h1.clone()];
+    let peer = &[g, h1, h2];
+
+    let score = score_peer_tip::<LongestChainRule>(local, peer);
+
+    assert!(score.ahead);
+    assert_eq!(score.length_delta, 1);
+    assert_eq!(score.work_delta, work_difference(peer, local));
+    assert!(score.work_delta >= 0);
+}
+
+#[test]
+fn bc_5_score_peer_behind() {
+    let g = Header::genesis();
+    let h1 = g.child(hash(&[1]), 1);
+    let h2 = h1.child(hash(&[2]), 3);
+    let local = &[g.clone(), h1.clone(), h2];
+    let peer = &[g, h1];
+
+    let score = score_peer_tip::<LongestChainRule>(local, peer);
+
+    assert!(!score.ahead);
+    assert_eq!(score.length_delta, -1);
+    assert_eq!(score.work_delta, work_difference(peer, local));
+    assert!(score.work_delta <= 0);
+}
+
+#[test]
+fn bc_5_score_peer_equal() {
+    let g = Header::genesis();
+    let h1 = g.child(hash(&[1]), 1);
+    let local = &[g.clone(), h1.clone()];
+    let peer = &[g, h1];
+
+    let score = score_peer_tip::<HeaviestChainRule>(local, peer);
+
+    assert_eq!(
+        score,
+        PeerScore {
+            ahead: false,
+            work_delta: 0,
+            length_delta: 0,
+        }
+    );
+}
+
+#[test]
+fn bc_5_attack_succeeds_with_longer_chain() {
+    let (honest, attacker) = build_attack_scenario(3, 4, THRESHOLD);
+
+    assert_eq!(honest.len(), 3);
+    assert_eq!(attacker.len(), 4);
+    assert!(attacker.iter().all(|h| hash(h) < THRESHOLD));
+
+    assert!(LongestChainRule::first_chain_is_better(&attacker, &honest));
+    assert_eq!(
+        LongestChainRule::best_chain(&[&honest, &attacker]),
+        &attacker
+    );
+}
+
+/// Build a chain of the given length on top of genesis, with every block mined to the threshold.
+#[cfg(test)]
+fn mined_chain(len: u64, threshold: u64, seed: u64) -> Vec<Header> {
+    let genesis = Block::genesis();
+    let mut chain: Vec<Block> = vec![];
+    for i in 0..len {
+        let mut block = chain.last().unwrap_or(&genesis).child(vec![seed, i]);
+        mine_extra_hard(&mut block, threshold);
+        chain.push(block);
+    }
+
+    chain.into_iter().map(|b| b.header).collect()
+}
+
+#[test]
+fn bc_5_average_work_vs_heaviest() {
+    // A long chain of weak blocks against a single block mined a hundred times harder
+    let long_weak = mined_chain(6, THRESHOLD, 0);
+    let short_strong = mined_chain(1, THRESHOLD / 100, 1);
+
+    assert!(HeaviestChainRule::first_chain_is_better(
+        &long_weak,
+        &short_strong
+    ));
+    assert!(AverageWorkRule::first_chain_is_better(
+        &short_strong,
+        &long_weak
+    ));
+    assert_eq!(
+        AverageWorkRule::best_chain(&[&long_weak, &short_strong]),
+        &short_strong
+    );
+}
+
+#[test]
+fn bc_5_hashed_header_caches_hash() {
+    let header = Header::genesis().child(hash(&[1]), 1);
+    let hashed = HashedHeader::from(header.clone());
+
+    assert_eq!(hashed.hash(), hash(&header));
+    assert_eq!(hashed.header(), &header);
+
+    // Changing the header requires unwrapping it, and wrapping it again recalculates the hash
+    let mut changed: Header = hashed.into();
+    changed.consensus_digest += 1;
+    let rehashed = HashedHeader::new(changed.clone());
+    assert_eq!(rehashed.hash(), hash(&changed));
+    assert_ne!(rehashed.hash(), hash(&header));
+}
+
+#[test]
+fn bc_5_best_hashed_chain_matches_best_chain() {
+    let weak = mined_chain(3, THRESHOLD, 0);
+    let strong = mined_chain(3, THRESHOLD / 10, 1);
+    let wrap = |chain: &[Header]| -> Vec<HashedHeader> {
+        chain.iter().cloned().map(HashedHeader::from).collect()
+    };
+    let hashed_weak = wrap(&weak);
+    let hashed_strong = wrap(&strong);
+
+    assert_eq!(HeaviestChainRule::best_chain(&[&weak, &strong]), &strong);
+    assert_eq!(
+        HeaviestChainRule::best_hashed_chain(&[&hashed_weak, &hashed_strong]),
+        &hashed_strong
+    );
+}
+
+#[test]
+fn bc_5_heaviest_then_longest_breaks_ties_by_length() {
+    let short = mined_chain(2, THRESHOLD, 0);
+
+    // A block whose hash is above the threshold adds length but no work
+    let tip = short.last().unwrap();
+    let no_work = (0..)
+        .map(|i| tip.child(hash(&[i]), i))
+        .find(|h| hash(h) > THRESHOLD)
+        .unwrap();
+    let long = [short.clone(), vec![no_work]].concat();
+
+    assert_eq!(chain_work(&short), chain_work(&long));
+    assert!(HeaviestThenLongest::first_chain_is_better(&long, &short));
+    assert!(!HeaviestThenLongest::first_chain_is_better(&short, &long));
+    assert_eq!(HeaviestThenLongest::best_chain(&[&short, &long]), &long);
+}
+
+#[test]
+fn bc_5_heaviest_then_longest_prefers_work_over_length() {
+    let short_strong = mined_chain(1, THRESHOLD, 0);
+    let mut long_weak: Vec<Header> = vec![];
+    for _ in 0..3 {
+        let parent = long_weak.last().cloned().unwrap_or_else(Header::genesis);
+        let weak = (0..)
+            .map(|i| parent.child(hash(&[i]), i))
+            .find(|h| hash(h) > THRESHOLD)
+            .unwrap();
+        long_weak.push(weak);
+    }
+    assert!(chain_work(&short_strong) > chain_work(&long_weak));
+
+    assert!(HeaviestThenLongest::first_chain_is_better(
+        &short_strong,
+        &long_weak
+    ));
+    assert!(!HeaviestThenLongest::first_chain_is_better(
+        &long_weak,
+        &short_strong
+    ));
+}
+
+#[test]
+fn bc_5_average_work_of_empty_chain() {
+    let chain = mined_chain(1, THRESHOLD, 0);
+
+    assert!(AverageWorkRule::first_chain_is_better(&chain, &[]));
+    assert!(!AverageWorkRule::first_chain_is_better(&[], &chain));
+}
+
+#[test]
+fn bc_5_finalized_tip_of_deep_chain() {
+    let g = Header::genesis();
+    let h1 = g.child(hash(&[1]), 1);
+    let h2 = h1.child(hash(&[2]), 2);
+    let h3 = h2.child(hash(&[3]), 3);
+    let chain = &[g.clone(), h1.clone(), h2.clone(), h3.clone()];
+
+    assert_eq!(finalized_tip(chain, 0), Some(&h3));
+    assert_eq!(finalized_tip(chain, 2), Some(&h1));
+    assert_eq!(finalized_tip(chain, 3), Some(&g));
+
+    assert!(is_final(chain, &g, 2));
+    assert!(is_final(chain, &h1, 2));
+    assert!(!is_final(chain, &h2, 2));
+    assert!(!is_final(chain, &h3, 2));
+}
+
+#[test]
+fn bc_5_finalized_tip_of_shallow_chain() {
+    let g = Header::genesis();
+    let h1 = g.child(hash(&[1]), 1);
+    let chain = &[g.clone(), h1];
+
+    assert_eq!(finalized_tip(chain, 2), None);
+    assert!(!is_final(chain, &g, 2));
+}
+
+#[test]
+fn bc_5_strict_extension() {
+    let g = Header::genesis();
+    let h1 = g.child(hash(&[1]), 1);
+    let h2 = h1.child(hash(&[2]), 2);
+
+    assert!(is_extension_of(&[g.clone(), h1.clone()], &[g, h1, h2]));
+}
+
+#[test]
+fn bc_5_chain_is_extension_of_itself() {
+    let g = Header::genesis();
+    let h1 = g.child(hash(&[1]), 1);
+    let chain = &[g, h1];
+
+    assert!(is_extension_of(chain, chain));
+}
+
+#[test]
+fn bc_5_fork_is_not_extension() {
+    let g = Header::genesis();
+    let h_a1 = g.child(hash(&[1]), 1);
+    let h_b1 = g.child(hash(&[2]), 2);
+    let h_b2 = h_b1.child(hash(&[3]), 3);
+    let base = &[g.clone(), h_a1];
+
+    assert!(!is_extension_of(base, &[g.clone(), h_b1, h_b2]));
+    // Nor is a shorter chain an extension
+    assert!(!is_extension_of(base, &[g]));
+}
+
+#[test]
+fn bc_5_one_block_reorg() {
+    let g = Header::genesis();
+    let h_a1 = g.child(hash(&[1]), 1);
+    let h_b1 = g.child(hash(&[2]), 2);
+
+    let (reverted, applied) = reorg(&[g.clone(), h_a1.clone()], &[g, h_b1.clone()]);
+    assert_eq!(reverted, vec![h_a1]);
+    assert_eq!(applied, vec![h_b1]);
+}
+
+#[test]
+fn bc_5_deep_reorg() {
+    let mut common = vec![Header::genesis()];
+    for i in 1..4 {
+        common.push(common.last().unwrap().child(hash(&[i]), i));
+    }
+    let fork_point = common.last().unwrap();
+    let h_a1 = fork_point.child(hash(&[10]), 10);
+    let h_a2 = h_a1.child(hash(&[11]), 11);
+    let h_a3 = h_a2.child(hash(&[12]), 12);
+    let h_b1 = fork_point.child(hash(&[20]), 20);
+    let h_b2 = h_b1.child(hash(&[21]), 21);
+
+    let old_chain = [
+        common.clone(),
+        vec![h_a1.clone(), h_a2.clone(), h_a3.clone()],
+    ]
+    .concat();
+    let new_chain = [common.clone(), vec![h_b1.clone(), h_b2.clone()]].concat();
+
+    let (reverted, applied) = reorg(&old_chain, &new_chain);
+    assert_eq!(reverted, vec![h_a3, h_a2, h_a1]);
+    assert_eq!(applied, vec![h_b1, h_b2]);
+
+    // Extending the chain reverts nothing
+    let (reverted, applied) = reorg(&common, &old_chain);
+    assert!(reverted.is_empty());
+    assert_eq!(applied, old_chain[common.len()..].to_vec());
+}
+
+/// A hasher that doubles the default hash, so that every header hashes to an even value.
+#[cfg(test)]
+#[derive(Default)]
+struct EvenHash;
+
+#[cfg(test)]
+impl Hasher for EvenHash {
+    fn hash<T: std::hash::Hash + ?Sized>(&self, t: &T) -> u64 {
+        DefaultHash.hash(t).wrapping_mul(2)
+    }
+}
+
+#[test]
+fn bc_5_most_even_blocks_with_custom_hasher() {
+    let g = Header::genesis();
+    let h_a1 = g.child(hash(&[1]), 1);
+    let h_a2 = h_a1.child(hash(&[2]), 2);
+    let h_b1 = g.child(hash(&[3]), 3);
+    let longer = &[g.clone(), h_a1, h_a2];
+    let shorter = &[g, h_b1];
+
+    // Every block counts when all hashes are even, so the longer chain wins
+    assert!(MostBlocksWithEvenHashBy::<EvenHash>::first_chain_is_better(
+        longer, shorter
+    ));
+    assert!(!MostBlocksWithEvenHashBy::<EvenHash>::first_chain_is_better(shorter, longer));
+    assert_eq!(
+        MostBlocksWithEvenHashBy::<EvenHash>::best_chain(&[shorter, longer]),
+        longer
+    );
+}
@@ -36,6 +36,8 @@ pub struct Header {
     /// Stores a cryptographic commitment, like a Merkle root or a hash to the complete
     /// post state.
     state_root: Hash,
+    /// The time at which the block was authored, in seconds since the unix epoch.
+    timestamp: u64,
     consensus_digest: u64,
 }
 
@@ -53,6 +55,7 @@ impl Header {
             height: 0,
             extrinsics_root: 0,
             state_root: genesis_state_root,
+            timestamp: 0,
             consensus_digest: 0,
         }
     }
@@ -60,14 +63,16 @@ impl Header {
     /// Create and return a valid child header.
     ///
     /// The state root is passed in similarly to how the complete state
-    /// was in the previous section.
-    fn child(&self, extrinsics_root: Hash, state_root: Hash) -> Self {
+    /// was in the previous section. The author also supplies the time at which
+    /// the block was authored.
+    fn child(&self, extrinsics_root: Hash, state_root: Hash, timestamp: u64) -> Self {
         for nonce in 0.. {
             let try_header = Header {
                 parent: hash(&self),
                 height: self.height + 1,
                 extrinsics_root,
                 state_root,
+                timestamp,
                 consensus_digest: nonce,
             };
 
@@ -112,6 +117,65 @@ impl Header {
     }
 }
 
+/// Calculate a Merkle root over the given extrinsics. This is a commitment to the complete
+/// ordered list of extrinsics.
+///
+/// Each extrinsic is hashed to form a leaf, and then pairs of nodes are hashed together
+/// level by level until a single root remains. When a level has an odd number of nodes, the
+/// last one is promoted to the next level as is. An empty body has a root of 0 by convention.
+///
+/// Bitcoin instead duplicates the last node, which means a body with a duplicated trailing
+/// transaction has the same root as the original (CVE-2012-2459). Promoting the node avoids
+/// that, and tagging leaves and inner nodes differently means a leaf can never pose as a subtree.
+pub fn merkle_root<T: std::hash::Hash>(extrinsics: &[T]) -> Hash {
+    const LEAF_TAG: u8 = 0;
+    const NODE_TAG: u8 = 1;
+
+    let mut level: Vec<Hash> = extrinsics.iter().map(|e| hash(&(LEAF_TAG, e))).collect();
+    if level.is_empty() {
+        return 0;
+    }
+
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => hash(&(NODE_TAG, left, right)),
+                [odd] => *odd,
+                _ => unreachable!("chunks of two have one or two nodes"),
+            })
+            .collect();
+    }
+
+    level[0]
+}
+
+/// The number of most recent ancestors whose timestamps are considered by `median_time_past`.
+const MEDIAN_TIME_SPAN: usize = 11;
+
+/// The median timestamp of the most recent `MEDIAN_TIME_SPAN` headers in the given chain of ancestors.
+///
+/// Like in Bitcoin, the median is used rather than the parent's timestamp so that a single author with
+/// a badly set clock cannot drag the chain's notion of time around. When fewer ancestors are available,
+/// as near genesis, the median of all of them is used. For an even number of ancestors the upper median
+/// is taken. An empty chain has a median time past of 0.
+fn median_time_past(ancestors: &[Header]) -> u64 {
+    let start = ancestors.len().saturating_sub(MEDIAN_TIME_SPAN);
+    let mut timestamps: Vec<u64> = ancestors[start..].iter().map(|h| h.timestamp).collect();
+    if timestamps.is_empty() {
+        return 0;
+    }
+
+    timestamps.sort_unstable();
+    timestamps[timestamps.len() / 2]
+}
+
+/// Check that the header's timestamp is strictly greater than the median time past of its ancestors.
+/// The ancestors should be ordered from oldest to newest, ending with the header's parent.
+fn validate_timestamp(ancestors: &[Header], header: &Header) -> bool {
+    ancestors.is_empty() || header.timestamp > median_time_past(ancestors)
+}
+
 /// A complete Block is a header and the extrinsics.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Block {
@@ -130,7 +194,8 @@ pub struct Block {
 /// These methods also differ from last time because you will need to
 /// calculate state roots to pass to the header-level methods.
 impl Block {
-    /// Returns a new valid genesis block. By convention this block has no extrinsics.
+    /// Returns a new valid genesis block. By convention this block has no extrinsics, so its
+    /// extrinsics root is the Merkle root of an empty body.
     pub fn genesis(genesis_state: &State) -> Self {
         Block {
             header: Header::genesis(hash(genesis_state)),
@@ -139,7 +204,7 @@ impl Block {
     }
 
     /// Create and return a valid child block.
-    pub fn child(&self, pre_state: &State, extrinsics: Vec<u64>) -> Self {
+    pub fn child(&self, pre_state: &State, extrinsics: Vec<u64>, timestamp: u64) -> Self {
         let mut new_state = pre_state.clone();
         for extrinsic in extrinsics.iter() {
             new_state.sum += extrinsic;
@@ -147,11 +212,24 @@ impl Block {
         }
 
         Block {
-            header: self.header.child(hash(&extrinsics), hash(&new_state)),
+            header: self
+                .header
+                .child(merkle_root(&extrinsics), hash(&new_state), timestamp),
             body: extrinsics,
         }
     }
 
+    /// Check that the header's extrinsics root really commits to the body of this block.
+    pub fn is_consistent(&self) -> bool {
+        self.header.extrinsics_root == merkle_root(&self.body)
+    }
+
+    /// Check that this block's header links to the given parent block. That is, it points
+    /// at the parent's hash and is exactly one block higher.
+    pub fn validate_against_parent(&self, parent: &Block) -> bool {
+        self.header.parent == hash(&parent.header) && self.header.height == parent.header.height + 1
+    }
+
     /// Verify that all the given blocks form a valid chain from this block to the tip.
     ///
     /// This time we need to validate the initial block itself by confirming that we
@@ -170,9 +248,8 @@ impl Block {
             state.product *= block.body.iter().product::<u64>();
 
             // check for invalid next block
-            if block.header.parent != hash(&prev_block.header)
-                || block.header.height != prev_block.header.height + 1
-                || block.header.extrinsics_root != hash(&block.body)
+            if !block.validate_against_parent(prev_block)
+                || !block.is_consistent()
                 || hash(&block.header) >= THRESHOLD
                 || block.header.state_root != hash(&state)
             {
@@ -200,7 +277,11 @@ fn build_invalid_child_block_with_valid_header(parent: &Header, pre_state: &Stat
     let extrinsics = vec![1, 2, 3, 4, 5];
 
     Block {
-        header: parent.child(hash(&extrinsics), hash(&State { sum: 1, product: 1 })),
+        header: parent.child(
+            merkle_root(&extrinsics),
+            hash(&State { sum: 1, product: 1 }),
+            parent.timestamp + 1,
+        ),
         body: extrinsics,
     }
 }
@@ -229,7 +310,7 @@ fn bc_6_genesis_block() {
 fn bc_6_child_block_empty() {
     let state = State { sum: 6, product: 9 };
     let b0 = Block::genesis(&state);
-    let b1 = b0.child(&state, vec![]);
+    let b1 = b0.child(&state, vec![], 10);
 
     assert_eq!(b1.header.height, 1);
     assert_eq!(b1.header.parent, hash(&b0.header));
@@ -246,7 +327,7 @@ fn bc_6_child_block_empty() {
 fn bc_6_child_block() {
     let state = State { sum: 6, product: 9 };
     let b0 = Block::genesis(&state);
-    let b1 = b0.child(&state, vec![1, 2, 3, 4, 5]);
+    let b1 = b0.child(&state, vec![1, 2, 3, 4, 5], 10);
 
     assert_eq!(b1.header.height, 1);
     assert_eq!(b1.header.parent, hash(&b0.header));
@@ -269,7 +350,7 @@ fn bc_6_child_header() {
         state_1.sum += extrinsic;
         state_1.product *= extrinsic;
     }
-    let h1 = g.child(hash(&extrinsics), hash(&state_1));
+    let h1 = g.child(hash(&extrinsics), hash(&state_1), 10);
 
     assert_eq!(h1.height, 1);
     assert_eq!(h1.parent, hash(&g));
@@ -283,7 +364,7 @@ fn bc_6_child_header() {
         state_2.product *= extrinsic;
     }
 
-    let h2 = h1.child(hash(&extrinsics), hash(&state_2));
+    let h2 = h1.child(hash(&extrinsics), hash(&state_2), 20);
 
     assert_eq!(h2.height, 2);
     assert_eq!(h2.parent, hash(&h1));
@@ -295,9 +376,9 @@ fn bc_6_child_header() {
 fn bc_6_verify_three_blocks() {
     let state_1 = State { sum: 6, product: 9 };
     let g = Block::genesis(&state_1);
-    let b1 = g.child(&state_1, vec![1]);
+    let b1 = g.child(&state_1, vec![1], 10);
     let state_2 = State { sum: 7, product: 9 };
-    let b2 = b1.child(&state_2, vec![2]);
+    let b2 = b1.child(&state_2, vec![2], 20);
     let chain = vec![g.clone(), b1, b2];
     assert!(g.verify_sub_chain(&state_1, &chain[1..]));
 }
@@ -311,6 +392,7 @@ fn bc_6_invalid_header_doesnt_check() {
         height: 100,
         extrinsics_root: 0,
         state_root: hash(&(State { sum: 0, product: 0 })),
+        timestamp: 0,
         consensus_digest: 0,
     };
 
@@ -321,7 +403,7 @@ fn bc_6_invalid_header_doesnt_check() {
 fn bc_6_invalid_block_state_doesnt_check() {
     let state = State { sum: 6, product: 9 };
     let b0 = Block::genesis(&state);
-    let mut b1 = b0.child(&state, vec![1, 2, 3]);
+    let mut b1 = b0.child(&state, vec![1, 2, 3], 10);
     b1.body = vec![];
 
     assert!(!b0.verify_sub_chain(&state, &[b1]));
@@ -331,7 +413,7 @@ fn bc_6_invalid_block_state_doesnt_check() {
 fn bc_6_block_with_invalid_header_doesnt_check() {
     let state = State { sum: 6, product: 9 };
     let b0 = Block::genesis(&state);
-    let mut b1 = b0.child(&state, vec![1, 2, 3]);
+    let mut b1 = b0.child(&state, vec![1, 2, 3], 10);
     b1.header = Header::genesis(hash(&state));
 
     assert!(!b0.verify_sub_chain(&state, &[b1]));
@@ -352,3 +434,96 @@ fn bc_6_student_invalid_block_really_is_invalid() {
     // Make sure that the block is not valid when executed.
     assert!(!gb.verify_sub_chain(&state, &[b1]));
 }
+
+#[test]
+fn bc_6_child_block_commits_to_merkle_root() {
+    let state = State { sum: 6, product: 9 };
+    let b0 = Block::genesis(&state);
+    assert_eq!(b0.header.extrinsics_root, merkle_root::<u64>(&[]));
+
+    let b1 = b0.child(&state, vec![1, 2, 3], 10);
+    assert_eq!(b1.header.extrinsics_root, merkle_root(&[1u64, 2, 3]));
+}
+
+#[test]
+fn bc_6_merkle_root_changes_with_body() {
+    let root = merkle_root(&[1u64, 2, 3, 4, 5]);
+
+    // Changing a single extrinsic changes the root
+    assert_ne!(root, merkle_root(&[1u64, 2, 3, 4, 6]));
+    // So does reordering them
+    assert_ne!(root, merkle_root(&[2u64, 1, 3, 4, 5]));
+}
+
+#[test]
+fn bc_6_merkle_root_commits_to_duplicated_last_extrinsic() {
+    assert_ne!(merkle_root(&[1u64, 2, 3]), merkle_root(&[1u64, 2, 3, 3]));
+    assert_ne!(merkle_root(&[1u64]), merkle_root(&[1u64, 1]));
+
+    // So a block with a duplicated trailing extrinsic is not consistent with the original header
+    let state = State { sum: 6, product: 9 };
+    let mut b1 = Block::genesis(&state).child(&state, vec![1, 2, 3], 10);
+    b1.body.push(3);
+    assert!(!b1.is_consistent());
+}
+
+#[test]
+fn bc_6_tampered_body_is_inconsistent() {
+    let state = State { sum: 6, product: 9 };
+    let b0 = Block::genesis(&state);
+    let mut b1 = b0.child(&state, vec![1, 2, 3], 10);
+    assert!(b1.is_consistent());
+
+    b1.body[1] = 20;
+    assert!(!b1.is_consistent());
+}
+
+#[test]
+fn bc_6_child_passes_parent_and_consistency_checks() {
+    let state = State { sum: 6, product: 9 };
+    let b0 = Block::genesis(&state);
+    let b1 = b0.child(&state, vec![1, 2, 3], 10);
+
+    assert!(b1.is_consistent());
+    assert!(b1.validate_against_parent(&b0));
+    assert!(!b0.validate_against_parent(&b1));
+
+    let b2 = b1.child(&state, vec![4], 20);
+    assert!(!b2.validate_against_parent(&b0));
+}
+
+#[cfg(test)]
+fn build_timed_chain(timestamps: &[u64]) -> Vec<Header> {
+    let state = State { sum: 0, product: 1 };
+    let mut chain = vec![Header::genesis(hash(&state))];
+    for timestamp in timestamps {
+        let parent = chain.last().unwrap();
+        chain.push(parent.child(0, hash(&state), *timestamp));
+    }
+    chain
+}
+
+#[test]
+fn bc_6_monotonic_time_chain_passes_median_time_past() {
+    let chain = build_timed_chain(&(1..=15).map(|t| t * 10).collect::<Vec<_>>());
+
+    for i in 1..chain.len() {
+        assert!(validate_timestamp(&chain[..i], &chain[i]));
+    }
+    // Only the last eleven timestamps, 50 through 150, are considered
+    assert_eq!(median_time_past(&chain), 100);
+}
+
+#[test]
+fn bc_6_backdated_block_fails_median_time_past() {
+    let chain = build_timed_chain(&[10, 20, 30, 40, 50]);
+    // Genesis is at time 0, so this is the upper median of six timestamps
+    assert_eq!(median_time_past(&chain), 30);
+
+    // Earlier than the parent, but still later than the median, is tolerated
+    let slightly_late = chain[5].child(0, 0, 35);
+    assert!(validate_timestamp(&chain, &slightly_late));
+
+    let backdated = chain[5].child(0, 0, 30);
+    assert!(!validate_timestamp(&chain, &backdated));
+}
@@ -8,6 +8,9 @@ mod p3_poa; // exercise: dictator is a special case of poa. Create dictator in t
 mod p4_even_only;
 mod p5_interleave;
 mod p6_forking;
+mod p7_finality_ref;
+mod p8_combinators;
+mod p9_chain_import;
 
 type Hash = u64;
 
@@ -20,13 +23,30 @@ type Hash = u64;
 /// which means they can operate entirely at the header level. They never need to touch
 /// the complete blocks.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header<Digest> {
-    parent: Hash,
-    height: u64,
-    state_root: Hash,
-    extrinsics_root: Hash,
-    consensus_digest: Digest,
+    pub(crate) parent: Hash,
+    pub(crate) height: u64,
+    pub(crate) state_root: Hash,
+    pub(crate) extrinsics_root: Hash,
+    /// The time at which the block was authored, as claimed by its author.
+    pub(crate) timestamp: u64,
+    pub(crate) consensus_digest: Digest,
 }
+/// The reasons a consensus engine may reject a header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsensusError {
+    /// The digest is not a kind this engine understands.
+    WrongDigestType,
+    /// The digest is a kind this engine understands, but not the kind it expects at the
+    /// header's height. This usually points to a misconfigured client.
+    WrongDigestForHeight { height: u64 },
+    /// An inner engine wrapped by a composite engine rejected the header.
+    InnerRejected,
+    /// The header violates one of this engine's own rules.
+    RuleViolated,
+}
+
 /// A Consensus Engine. Responsible for Sealing blocks and verifying their seals
 ///
 /// Consensus exists independently of execution logic, and therefore operates
@@ -43,8 +63,35 @@ pub trait Consensus {
     /// digest and the parent digest. For example, they may need to check that the
     /// slot number is increasing. Therefore the parent digest is also passed
     /// here. Other consensus engines will not need to use the parent digest at all.
+    ///
+    /// The genesis block does not require a seal, so every engine should consider a genesis
+    /// header valid regardless of its digest. See `is_genesis`.
     fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool;
 
+    /// Like `validate`, but reports why a header was rejected.
+    ///
+    /// The provided implementation simply wraps `validate` and reports any failure as a
+    /// `RuleViolated`. Composite engines that wrap other engines override this method so
+    /// callers can tell which part of the validation failed, and implement `validate` in
+    /// terms of it instead.
+    fn validate_detailed(
+        &self,
+        parent_digest: &Self::Digest,
+        header: &Header<Self::Digest>,
+    ) -> Result<(), ConsensusError> {
+        if self.validate(parent_digest, header) {
+            Ok(())
+        } else {
+            Err(ConsensusError::RuleViolated)
+        }
+    }
+
+    /// Whether the given header is the genesis header. Genesis headers are exempt from
+    /// consensus checks because there is nobody to seal them.
+    fn is_genesis(header: &Header<Self::Digest>) -> bool {
+        header.height == 0
+    }
+
     /// Takes a partial header that does not yet have a consensus digest attached. Returns
     /// a new header including the consensus digest that is valid according to the consensus rules.
     ///
@@ -88,6 +135,15 @@ pub trait Consensus {
         true
     }
 
+    /// The amount of work represented by this header. Fork choice rules can sum this over a chain
+    /// to compare competing forks without knowing which engine produced them.
+    ///
+    /// The provided implementation counts every block as one unit of work. Engines where some
+    /// blocks are harder to author than others should override it.
+    fn block_weight(&self, _header: &Header<Self::Digest>) -> u64 {
+        1
+    }
+
     /// A human-readable name for this engine. This may be used in user-facing
     /// programs error reporting. This is not in any way related to
     /// the correctness of the consensus logic.
@@ -96,6 +152,91 @@ pub trait Consensus {
     }
 }
 
+/// Validate an entire chain of headers according to the given consensus engine.
+///
+/// Each header is validated against the digest of the header before it, starting from the
+/// given genesis digest. Returns false at the first invalid header. An empty chain is
+/// vacuously valid.
+pub fn validate_chain<C: Consensus>(
+    engine: &C,
+    genesis_digest: &C::Digest,
+    headers: &[Header<C::Digest>],
+) -> bool {
+    let mut parent_digest = genesis_digest;
+    for header in headers {
+        if !engine.validate(parent_digest, header) {
+            return false;
+        }
+        parent_digest = &header.consensus_digest;
+    }
+    true
+}
+
+/// The total weight of a chain of headers according to the given engine's `block_weight`.
+pub fn chain_weight<C: Consensus>(engine: &C, headers: &[Header<C::Digest>]) -> u128 {
+    headers
+        .iter()
+        .map(|header| engine.block_weight(header) as u128)
+        .sum()
+}
+
+/// Seal each of the given partial headers in order, feeding the digest of each freshly sealed
+/// header forward as the parent digest of the next one. This saves sealing a chain block by block.
+///
+/// Returns `None` if any of the partial headers could not be sealed.
+pub fn build_sealed_chain<C: Consensus>(
+    engine: &C,
+    genesis_digest: C::Digest,
+    partials: Vec<Header<()>>,
+) -> Option<Vec<Header<C::Digest>>> {
+    let mut parent_digest = genesis_digest;
+    let mut chain = Vec::with_capacity(partials.len());
+    for partial_header in partials {
+        let header = engine.seal(&parent_digest, partial_header)?;
+        parent_digest = header.consensus_digest.clone();
+        chain.push(header);
+    }
+    Some(chain)
+}
+
+/// Find the first header in the chain whose extracted value, such as a slot number or a timestamp,
+/// is not strictly greater than its predecessor's. This is the per-header monotonicity check that
+/// slot-based and timestamped engines perform, generalized to a single scan over a whole chain.
+///
+/// Returns the index of the offending header, or `None` if the values are strictly increasing.
+pub fn first_regression<D>(chain: &[Header<D>], extract: impl Fn(&D) -> u64) -> Option<usize> {
+    chain
+        .windows(2)
+        .position(|pair| extract(&pair[1].consensus_digest) <= extract(&pair[0].consensus_digest))
+        .map(|i| i + 1)
+}
+
+/// Copy the given header, but with a different consensus digest. Composite engines use this to
+/// hand headers to the engines they wrap, and to wrap the headers those engines seal.
+pub(crate) fn with_digest<D, E>(header: &Header<D>, consensus_digest: E) -> Header<E> {
+    Header {
+        parent: header.parent,
+        height: header.height,
+        state_root: header.state_root,
+        extrinsics_root: header.extrinsics_root,
+        timestamp: header.timestamp,
+        consensus_digest,
+    }
+}
+
+/// A partial header at the given height, ready to be sealed in tests.
+#[cfg(test)]
+pub(crate) fn partial_header(height: u64) -> Header<()> {
+    Header {
+        parent: 123,
+        height,
+        state_root: 123,
+        extrinsics_root: 123,
+        timestamp: 0,
+        consensus_digest: (),
+    }
+}
+
 /// A trivial consensus engine that considers all blocks valid, and does not have
 /// a meaningful consensus digest.
 impl Consensus for () {
@@ -114,9 +255,50 @@ impl Consensus for () {
 
 /// A set of consensus authority accounts that can be used in
 /// identity-based consensus algorithms.
-#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Hash, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ConsensusAuthority {
     Alice,
     Bob,
     Charlie,
 }
+
+impl ConsensusAuthority {
+    /// Every authority, in their canonical order. This is the order round robin schemes rotate in.
+    pub fn all() -> &'static [ConsensusAuthority] {
+        &[
+            ConsensusAuthority::Alice,
+            ConsensusAuthority::Bob,
+            ConsensusAuthority::Charlie,
+        ]
+    }
+
+    /// The position of this authority in the canonical order.
+    pub fn index(&self) -> usize {
+        match self {
+            ConsensusAuthority::Alice => 0,
+            ConsensusAuthority::Bob => 1,
+            ConsensusAuthority::Charlie => 2,
+        }
+    }
+
+    /// The authority at the given position in the canonical order, if there is one.
+    pub fn from_index(index: usize) -> Option<ConsensusAuthority> {
+        Self::all().get(index).copied()
+    }
+}
+
+#[test]
+fn consensus_authority_index_round_trips() {
+    for (i, authority) in ConsensusAuthority::all().iter().enumerate() {
+        assert_eq!(authority.index(), i);
+        assert_eq!(
+            ConsensusAuthority::from_index(authority.index()),
+            Some(*authority)
+        );
+    }
+    assert_eq!(
+        ConsensusAuthority::from_index(ConsensusAuthority::all().len()),
+        None
+    );
+}
@@ -5,22 +5,233 @@
 //! generic consensus framework that we will use throughout the rest of the chapter.
 
 use super::{Consensus, Header};
-use crate::hash;
+use crate::{hash, hash256, DefaultHash, Hash256, Hasher};
+
+#[cfg(test)]
+use super::partial_header;
 
 /// A Proof of Work consensus engine. This is the same consensus logic that we
 /// implemented in the previous chapter. Here we simply re-implement it in the
 /// consensus framework that will be used throughout this chapter.
-pub struct Pow {
-    threshold: u64,
+///
+/// The hasher used to judge the work can be swapped out, which is mostly useful in tests.
+pub struct Pow<H: Hasher = DefaultHash> {
+    pub(super) threshold: u64,
+    pub(super) hasher: H,
 }
 
-impl Consensus for Pow {
+impl<H: Hasher> Consensus for Pow<H> {
     type Digest = u64;
 
     /// Check that the provided header's hash is below the required threshold.
+    /// The genesis header is exempt. This does not rely on the parent digest at all.
+    fn validate(&self, _: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        Self::is_genesis(header) || self.hasher.hash(header) < self.threshold
+    }
+
+    /// Mine a new PoW seal for the partial header provided.
     /// This does not rely on the parent digest at all.
+    fn seal(
+        &self,
+        parent_digest: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>> {
+        self.seal_from(parent_digest, partial_header, 0)
+    }
+
+    /// A header's weight is how far its hash falls below the threshold, so a lower hash
+    /// represents more work.
+    fn block_weight(&self, header: &Header<Self::Digest>) -> u64 {
+        self.threshold.saturating_sub(self.hasher.hash(header))
+    }
+}
+
+impl<H: Hasher> Pow<H> {
+    /// Mine a new PoW seal like `seal` does, and also report how many nonces were tried
+    /// before a valid one was found. Useful for analyzing the effect of the difficulty.
+    pub fn seal_counting(
+        &self,
+        parent_digest: &u64,
+        partial_header: Header<()>,
+    ) -> Option<(Header<u64>, u64)> {
+        self.seal_from(parent_digest, partial_header, 0)
+            .map(|header| {
+                let attempts = header.consensus_digest + 1;
+                (header, attempts)
+            })
+    }
+
+    /// Mine a new PoW seal like `seal` does, but start the nonce search at the given offset
+    /// instead of zero. Mining the same partial header from different offsets usually finds
+    /// different nonces, which is handy for building distinct chains in tests.
+    pub fn seal_from(
+        &self,
+        _: &u64,
+        partial_header: Header<()>,
+        start_nonce: u64,
+    ) -> Option<Header<u64>> {
+        let mut header: Header<u64> = Header {
+            parent: partial_header.parent,
+            height: partial_header.height,
+            state_root: partial_header.state_root,
+            extrinsics_root: partial_header.extrinsics_root,
+            timestamp: partial_header.timestamp,
+            consensus_digest: 0,
+        };
+
+        for nonce in start_nonce..u64::MAX {
+            header.consensus_digest = nonce;
+            if self.hasher.hash(&header) < self.threshold {
+                return Some(header);
+            }
+        }
+        None
+    }
+
+    /// Validate many already mined headers at once, for example when importing a batch of
+    /// headers from a peer.
+    ///
+    /// The result has one entry per header, in the same order, and each entry is exactly what
+    /// `validate` would return for that header. Headers are judged independently of each other,
+    /// so neither the order nor the links between them are checked. That independence is what
+    /// would allow the work to be parallelized later. For now the headers are checked
+    /// sequentially. The hasher can not be reused between headers because finishing it does
+    /// not reset its state.
+    pub fn validate_batch(&self, headers: &[Header<u64>]) -> Vec<bool> {
+        // PoW validation does not rely on the parent digest
+        headers.iter().map(|h| self.validate(&0, h)).collect()
+    }
+
+    /// A stricter form of validation that also checks the header is linked to the given parent.
+    ///
+    /// The regular `validate` method only sees the parent digest, so it cannot notice a header
+    /// whose parent pointer is wrong. Here the complete parent header is passed so that the
+    /// link can be checked in addition to the work proof. Chains are linked with the crate-wide
+    /// `hash` no matter which hasher judges the work.
+    pub fn validate_linked(&self, parent: &Header<u64>, header: &Header<u64>) -> bool {
+        header.parent == hash(parent) && self.validate(&parent.consensus_digest, header)
+    }
+
+    /// Mine a new PoW seal whose header hash has at least `zero_bits` leading zero bits. This is
+    /// an alternative to the threshold that is easier to reason about, since every extra bit
+    /// doubles the expected work. The engine's threshold is ignored.
+    pub fn seal_zero_bits(
+        &self,
+        _: &u64,
+        partial_header: Header<()>,
+        zero_bits: u32,
+    ) -> Option<Header<u64>> {
+        if zero_bits > u64::BITS {
+            return None;
+        }
+
+        let mut header: Header<u64> = Header {
+            parent: partial_header.parent,
+            height: partial_header.height,
+            state_root: partial_header.state_root,
+            extrinsics_root: partial_header.extrinsics_root,
+            timestamp: partial_header.timestamp,
+            consensus_digest: 0,
+        };
+
+        for nonce in 0..u64::MAX {
+            header.consensus_digest = nonce;
+            if self.hasher.hash(&header).leading_zeros() >= zero_bits {
+                return Some(header);
+            }
+        }
+        None
+    }
+
+    /// Check that the provided header's hash has at least `zero_bits` leading zero bits.
+    /// The genesis header is exempt, just as it is in `validate`.
+    pub fn validate_zero_bits(&self, header: &Header<u64>, zero_bits: u32) -> bool {
+        Self::is_genesis(header) || self.hasher.hash(header).leading_zeros() >= zero_bits
+    }
+}
+
+/// The digest of a PoW header that commits to the difficulty target it was mined against.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TargetedPowDigest {
+    /// The threshold the header's hash must fall below.
+    pub target: u64,
+    /// The nonce that makes the header's hash fall below the target.
+    pub nonce: u64,
+}
+
+/// A Proof of Work consensus engine whose headers carry the target they were mined against.
+///
+/// With plain `Pow` the threshold is only known to the engine, so a header can not be checked
+/// without out-of-band knowledge of the difficulty in force. Here the header says which target
+/// it claims to meet, and the engine checks both that the hash meets it and that it is the
+/// target expected at that height. This is what allows light clients to verify retargeting.
+pub struct TargetedPow {
+    /// The target in force from genesis on.
+    pub(super) initial_target: u64,
+    /// Difficulty changes as pairs of the height from which they apply and the new target,
+    /// sorted by height.
+    pub(super) retargets: Vec<(u64, u64)>,
+}
+
+impl TargetedPow {
+    /// The target a header at the given height must commit to.
+    pub fn expected_target(&self, height: u64) -> u64 {
+        self.retargets
+            .iter()
+            .rev()
+            .find(|(from, _)| *from <= height)
+            .map(|(_, target)| *target)
+            .unwrap_or(self.initial_target)
+    }
+}
+
+impl Consensus for TargetedPow {
+    type Digest = TargetedPowDigest;
+
+    /// Check that the header commits to the expected target and that its hash is below it.
+    /// The genesis header is exempt. This does not rely on the parent digest at all.
+    fn validate(&self, _: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        Self::is_genesis(header)
+            || (header.consensus_digest.target == self.expected_target(header.height)
+                && hash(header) < header.consensus_digest.target)
+    }
+
+    /// Mine a new PoW seal against the target expected at the header's height.
+    fn seal(&self, _: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
+        let target = self.expected_target(partial_header.height);
+        let mut header = Header {
+            parent: partial_header.parent,
+            height: partial_header.height,
+            state_root: partial_header.state_root,
+            extrinsics_root: partial_header.extrinsics_root,
+            timestamp: partial_header.timestamp,
+            consensus_digest: TargetedPowDigest { target, nonce: 0 },
+        };
+
+        for nonce in 0..u64::MAX {
+            header.consensus_digest.nonce = nonce;
+            if hash(&header) < target {
+                return Some(header);
+            }
+        }
+        None
+    }
+}
+
+/// A Proof of Work consensus engine that judges work by a 256-bit hash. The header's hash is
+/// compared byte by byte against a 32-byte target, which allows far finer grained difficulty
+/// than the u64 threshold of `Pow`.
+pub struct Pow256 {
+    pub(super) target: Hash256,
+}
+
+impl Consensus for Pow256 {
+    type Digest = u64;
+
+    /// Check that the provided header's 256-bit hash is below the target.
+    /// The genesis header is exempt. This does not rely on the parent digest at all.
     fn validate(&self, _: &Self::Digest, header: &Header<Self::Digest>) -> bool {
-        hash(header) < self.threshold
+        Self::is_genesis(header) || hash256(header) < self.target
     }
 
     /// Mine a new PoW seal for the partial header provided.
@@ -31,12 +242,13 @@ impl Consensus for Pow {
             height: partial_header.height,
             state_root: partial_header.state_root,
             extrinsics_root: partial_header.extrinsics_root,
+            timestamp: partial_header.timestamp,
             consensus_digest: 0,
         };
 
-        for nonce in 0.. {
+        for nonce in 0..u64::MAX {
             header.consensus_digest = nonce;
-            if hash(&header) < self.threshold {
+            if hash256(&header) < self.target {
                 return Some(header);
             }
         }
@@ -49,6 +261,7 @@ impl Consensus for Pow {
 pub fn moderate_difficulty_pow() -> Pow {
     Pow {
         threshold: u64::max_value() / 100,
+        hasher: DefaultHash,
     }
 }
 
@@ -57,5 +270,361 @@ pub fn moderate_difficulty_pow() -> Pow {
 pub fn trivial_always_valid_pow() -> Pow {
     Pow {
         threshold: u64::max_value(),
+        hasher: DefaultHash,
+    }
+}
+
+#[cfg(test)]
+fn mine_linked_chain(pow: &Pow, n: u64) -> Vec<Header<u64>> {
+    let mut chain = vec![Header {
+        parent: 0,
+        height: 0,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: 0,
+    }];
+    for i in 1..=n {
+        let parent = chain.last().unwrap();
+        let partial_header = Header {
+            parent: hash(parent),
+            height: i,
+            state_root: i,
+            extrinsics_root: i,
+            timestamp: 0,
+            consensus_digest: (),
+        };
+        chain.push(pow.seal(&parent.consensus_digest, partial_header).unwrap());
+    }
+    chain
+}
+
+#[test]
+fn pow_validate_linked_chain() {
+    let pow = moderate_difficulty_pow();
+    let chain = mine_linked_chain(&pow, 3);
+
+    for pair in chain.windows(2) {
+        assert!(pow.validate_linked(&pair[0], &pair[1]));
+    }
+}
+
+#[test]
+fn pow_validate_linked_rejects_broken_parent() {
+    let pow = trivial_always_valid_pow();
+    let chain = mine_linked_chain(&pow, 2);
+
+    let mut broken = chain[2].clone();
+    broken.parent = hash(&chain[0]);
+
+    assert!(pow.validate(&chain[1].consensus_digest, &broken));
+    assert!(!pow.validate_linked(&chain[1], &broken));
+}
+
+#[test]
+fn pow_seal_from_different_offsets() {
+    let pow = moderate_difficulty_pow();
+    let partial_header = Header {
+        parent: 123,
+        height: 1,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: (),
+    };
+
+    let first = pow.seal_from(&0, partial_header.clone(), 0).unwrap();
+    let second = pow
+        .seal_from(&0, partial_header.clone(), first.consensus_digest + 1)
+        .unwrap();
+
+    assert_ne!(first.consensus_digest, second.consensus_digest);
+    assert!(second.consensus_digest > first.consensus_digest);
+    assert!(pow.validate(&0, &first));
+    assert!(pow.validate(&0, &second));
+    assert_eq!(pow.seal(&0, partial_header), Some(first));
+}
+
+#[test]
+fn pow_seal_zero_bits() {
+    let pow = moderate_difficulty_pow();
+    let partial_header = Header {
+        parent: 123,
+        height: 1,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: (),
+    };
+
+    let sealed = pow.seal_zero_bits(&0, partial_header.clone(), 8).unwrap();
+    assert!(hash(&sealed).leading_zeros() >= 8);
+    assert!(pow.validate_zero_bits(&sealed, 8));
+
+    assert!(pow.seal_zero_bits(&0, partial_header, 65).is_none());
+}
+
+#[test]
+fn pow_validate_zero_bits_rejects_too_few_zeros() {
+    let pow = trivial_always_valid_pow();
+    let mut header = Header {
+        parent: 123,
+        height: 1,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: 0,
+    };
+    // Find a nonce whose hash has its top bit set, so it has no leading zeros at all
+    while hash(&header).leading_zeros() > 0 {
+        header.consensus_digest += 1;
+    }
+
+    assert!(pow.validate_zero_bits(&header, 0));
+    assert!(!pow.validate_zero_bits(&header, 1));
+}
+
+#[test]
+fn pow_genesis_not_checked() {
+    let pow = Pow {
+        threshold: 0,
+        hasher: DefaultHash,
+    };
+    let genesis = Header {
+        parent: 0,
+        height: 0,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: 12345,
+    };
+
+    assert!(pow.validate(&0, &genesis));
+}
+
+#[test]
+fn pow_block_weight_ranks_low_hash_higher() {
+    let pow = moderate_difficulty_pow();
+    let chain = mine_linked_chain(&pow, 5);
+    let (low, high) = (
+        chain[1..].iter().min_by_key(|h| hash(*h)).unwrap(),
+        chain[1..].iter().max_by_key(|h| hash(*h)).unwrap(),
+    );
+
+    assert!(pow.block_weight(low) > pow.block_weight(high));
+    assert_eq!(
+        super::chain_weight(&pow, &chain[1..]),
+        chain[1..]
+            .iter()
+            .map(|h| pow.block_weight(h) as u128)
+            .sum::<u128>()
+    );
+
+    // Engines without an override count every block once
+    let unit_header = Header {
+        parent: 0,
+        height: 1,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: (),
+    };
+    assert_eq!(super::chain_weight(&(), &vec![unit_header; 3]), 3);
+}
+
+#[test]
+fn pow_harder_threshold_needs_more_attempts() {
+    let partial_header = || Header {
+        parent: 0,
+        height: 1,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: (),
+    };
+
+    let (easy_header, easy_attempts) = trivial_always_valid_pow()
+        .seal_counting(&0, partial_header())
+        .unwrap();
+    let hard = Pow {
+        threshold: u64::MAX / 1000,
+        hasher: DefaultHash,
+    };
+    let (hard_header, hard_attempts) = hard.seal_counting(&0, partial_header()).unwrap();
+
+    assert_eq!(easy_attempts, 1);
+    assert_eq!(easy_header.consensus_digest, 0);
+    assert!(hard_attempts > easy_attempts);
+    assert_eq!(hard_attempts, hard_header.consensus_digest + 1);
+    assert_eq!(hard.seal(&0, partial_header()), Some(hard_header));
+}
+
+#[test]
+fn pow_validate_batch_matches_individual_validation() {
+    let pow = moderate_difficulty_pow();
+    let mut headers = mine_linked_chain(&pow, 4);
+
+    // Tamper with some of the headers so that their seals are (almost certainly) invalid
+    headers[1].state_root += 1;
+    headers[3].extrinsics_root += 1;
+
+    let batch = pow.validate_batch(&headers);
+    let individual: Vec<bool> = headers.iter().map(|h| pow.validate(&0, h)).collect();
+
+    assert_eq!(batch, individual);
+    assert!(batch.contains(&true));
+    assert!(batch.contains(&false));
+}
+
+#[cfg(test)]
+fn targeted_pow() -> TargetedPow {
+    TargetedPow {
+        initial_target: u64::MAX / 10,
+        retargets: vec![(3, u64::MAX / 100)],
+    }
+}
+
+#[test]
+fn targeted_pow_follows_retargets() {
+    let pow = targeted_pow();
+
+    assert_eq!(pow.expected_target(1), u64::MAX / 10);
+    assert_eq!(pow.expected_target(3), u64::MAX / 100);
+    assert_eq!(pow.expected_target(7), u64::MAX / 100);
+}
+
+#[test]
+fn targeted_pow_sealed_headers_validate() {
+    let pow = targeted_pow();
+    let parent_digest = TargetedPowDigest {
+        target: 0,
+        nonce: 0,
+    };
+
+    for height in 1..5 {
+        let header = pow.seal(&parent_digest, partial_header(height)).unwrap();
+        assert_eq!(header.consensus_digest.target, pow.expected_target(height));
+        assert!(pow.validate(&parent_digest, &header));
+    }
+}
+
+#[test]
+fn targeted_pow_rejects_wrong_committed_target() {
+    let pow = targeted_pow();
+    let parent_digest = TargetedPowDigest {
+        target: 0,
+        nonce: 0,
+    };
+
+    // Mined against the old, easier target after the retarget has taken effect
+    let easy = TargetedPow {
+        initial_target: u64::MAX / 10,
+        retargets: vec![],
+    };
+    let mut header = easy.seal(&parent_digest, partial_header(3)).unwrap();
+    assert!(!pow.validate(&parent_digest, &header));
+
+    // Claiming the right target does not help when the hash does not meet it
+    header.consensus_digest.target = pow.expected_target(3);
+    while hash(&header) < pow.expected_target(3) {
+        header.consensus_digest.nonce += 1;
+    }
+    assert!(!pow.validate(&parent_digest, &header));
+}
+
+/// A hasher that ignores its input, for pinning down PoW behavior in tests.
+#[cfg(test)]
+struct ConstantHash(u64);
+
+#[cfg(test)]
+impl Hasher for ConstantHash {
+    fn hash<T: std::hash::Hash + ?Sized>(&self, _: &T) -> u64 {
+        self.0
     }
 }
+
+#[test]
+fn pow_uses_injected_hasher() {
+    let header = Header {
+        parent: 0,
+        height: 1,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: 0,
+    };
+    let lucky = Pow {
+        threshold: 100,
+        hasher: ConstantHash(99),
+    };
+    let unlucky = Pow {
+        threshold: 100,
+        hasher: ConstantHash(100),
+    };
+
+    assert!(lucky.validate(&0, &header));
+    assert_eq!(lucky.block_weight(&header), 1);
+    assert!(!unlucky.validate(&0, &header));
+}
+
+#[test]
+fn pow_validate_linked_with_injected_hasher() {
+    let chain = mine_linked_chain(&trivial_always_valid_pow(), 2);
+    let lucky = Pow {
+        threshold: 100,
+        hasher: ConstantHash(99),
+    };
+
+    assert!(lucky.validate_linked(&chain[1], &chain[2]));
+    assert!(!lucky.validate_linked(&chain[0], &chain[2]));
+}
+
+#[test]
+fn pow256_seals_and_validates() {
+    let mut target = [0xff; 32];
+    target[0] = 0x01; // Roughly 1 in 128 hashes
+    let pow = Pow256 {
+        target: Hash256(target),
+    };
+    let partial_header = Header {
+        parent: 0,
+        height: 1,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: (),
+    };
+
+    let header = pow.seal(&0, partial_header).unwrap();
+    assert!(pow.validate(&0, &header));
+    assert!(hash256(&header).0[0] < 0x01);
+}
+
+#[test]
+fn pow256_distinguishes_targets_a_u64_can_not() {
+    let header = Header {
+        parent: 0,
+        height: 1,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: 0,
+    };
+    let header_hash = hash256(&header);
+
+    // Two targets that agree on their leading 64 bits, so no u64 threshold could tell them
+    // apart, but differ in the very last byte
+    let mut just_above = header_hash.0;
+    let i = just_above.iter().rposition(|b| *b < u8::MAX).unwrap();
+    just_above[i] += 1;
+    let strict = Pow256 {
+        target: header_hash,
+    };
+    let lenient = Pow256 {
+        target: Hash256(just_above),
+    };
+
+    assert_eq!(header_hash.0[..8], just_above[..8]);
+    assert!(!strict.validate(&0, &header));
+    assert!(lenient.validate(&0, &header));
+}
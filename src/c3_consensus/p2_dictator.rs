@@ -8,7 +8,10 @@
 //! require a crypto library which and overcoming its own learning curve, plus they distract from the
 //! underlying consensus-related logic. Instead, we just use the `ConsensusAuthority` enum from the module root.
 
-use super::{Consensus, ConsensusAuthority, Header};
+use super::{with_digest, Consensus, ConsensusAuthority, Header};
+
+#[cfg(test)]
+use super::partial_header;
 /// Dictator consensus is an identity-based consensus algorithm. It specifies a single dictator
 /// identity who is the only identity authorized to sign valid blocks. Any block signed by the
 /// dictator is valid (at the consensus level), and any block not signed by the dictator is invalid.
@@ -21,19 +24,285 @@ impl Consensus for DictatorConsensus {
 
     /// Check that the header is signed by the dictator
     fn validate(&self, _: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        if Self::is_genesis(header) {
+            return true;
+        }
+
         return header.consensus_digest == self.dictator;
     }
 
     /// Sign the given partial header by the dictator
     fn seal(&self, _: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
-        let signed_header: Header<ConsensusAuthority> = Header {
-            consensus_digest: self.dictator,
-            height: partial_header.height,
-            extrinsics_root: partial_header.extrinsics_root,
-            state_root: partial_header.state_root,
-            parent: partial_header.parent,
-        };
+        let signed_header: Header<ConsensusAuthority> = with_digest(&partial_header, self.dictator);
 
         Some(signed_header)
     }
 }
+
+/// A dictatorship with a succession plan. The heirs are ordered, and the first of them starts out as
+/// the active dictator. The active dictator may hand power to the next heir by flagging a block it
+/// signs, so that the chain does not halt forever when a dictator has to step down.
+struct FallbackDictator {
+    heirs: Vec<ConsensusAuthority>,
+}
+
+/// The digest for the `FallbackDictator`. Besides the signature, each header records which heir
+/// was the active dictator when it was signed, and whether that dictator passes power on to the
+/// next heir from the following block on.
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy)]
+struct SuccessionDigest {
+    signature: ConsensusAuthority,
+    dictator_index: usize,
+    transfer_power: bool,
+}
+
+impl FallbackDictator {
+    /// The index of the heir that must sign the header at the given height. The first block after
+    /// genesis is signed by the first heir. After that, the active dictator is tracked through the
+    /// parent's digest.
+    fn active_index(&self, parent_digest: &SuccessionDigest, height: u64) -> usize {
+        if height <= 1 {
+            0
+        } else if parent_digest.transfer_power {
+            parent_digest.dictator_index + 1
+        } else {
+            parent_digest.dictator_index
+        }
+    }
+
+    /// Seal the given partial header by the active dictator, optionally transferring power to the
+    /// next heir. Returns `None` if there is no active dictator, or no heir to transfer power to.
+    fn seal_with_succession(
+        &self,
+        parent_digest: &SuccessionDigest,
+        partial_header: Header<()>,
+        transfer_power: bool,
+    ) -> Option<Header<SuccessionDigest>> {
+        let dictator_index = self.active_index(parent_digest, partial_header.height);
+        let signature = *self.heirs.get(dictator_index)?;
+        if transfer_power && dictator_index + 1 >= self.heirs.len() {
+            return None;
+        }
+
+        Some(with_digest(
+            &partial_header,
+            SuccessionDigest {
+                signature,
+                dictator_index,
+                transfer_power,
+            },
+        ))
+    }
+}
+
+impl Consensus for FallbackDictator {
+    type Digest = SuccessionDigest;
+
+    /// Check that the header is signed by the active dictator, and that any transfer of power
+    /// names an heir that exists.
+    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        if Self::is_genesis(header) {
+            return true;
+        }
+
+        let digest = &header.consensus_digest;
+        let dictator_index = self.active_index(parent_digest, header.height);
+
+        digest.dictator_index == dictator_index
+            && self.heirs.get(dictator_index) == Some(&digest.signature)
+            && (!digest.transfer_power || dictator_index + 1 < self.heirs.len())
+    }
+
+    /// Sign the given partial header by the active dictator, without transferring power.
+    fn seal(
+        &self,
+        parent_digest: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>> {
+        self.seal_with_succession(parent_digest, partial_header, false)
+    }
+}
+
+/// A higher-order engine in which an inner engine decides validity, but a dictator may additionally
+/// veto any block by refusing to co-sign it. This models a chain that is decentralized in principle,
+/// but still has an operator with the final say.
+struct VetoedBy<Inner: Consensus> {
+    /// The inner consensus engine whose rules are enforced in addition to the co-signature.
+    inner: Inner,
+    /// The authority that has to co-sign every block.
+    dictator: ConsensusAuthority,
+}
+
+/// The digest for `VetoedBy`. It contains the inner engine's digest as well as the dictator's
+/// co-signature, which is missing when the dictator vetoed the block.
+#[derive(Hash, Debug, PartialEq, Eq, Clone)]
+struct VetoDigest<D> {
+    inner: D,
+    co_signature: Option<ConsensusAuthority>,
+}
+
+impl<Inner: Consensus> Consensus for VetoedBy<Inner> {
+    type Digest = VetoDigest<Inner::Digest>;
+
+    /// Check that the inner engine accepts the header and that the dictator co-signed it.
+    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        if Self::is_genesis(header) {
+            return true;
+        }
+
+        let inner_header = with_digest(header, header.consensus_digest.inner.clone());
+
+        self.inner.validate(&parent_digest.inner, &inner_header)
+            && header.consensus_digest.co_signature == Some(self.dictator)
+    }
+
+    /// Seal the header with the inner engine, and have the dictator co-sign it.
+    fn seal(
+        &self,
+        parent_digest: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>> {
+        let sealed = self.inner.seal(&parent_digest.inner, partial_header)?;
+
+        Some(with_digest(
+            &sealed,
+            VetoDigest {
+                inner: sealed.consensus_digest.clone(),
+                co_signature: Some(self.dictator),
+            },
+        ))
+    }
+}
+
+#[test]
+fn dictator_genesis_not_checked() {
+    let dictator = DictatorConsensus {
+        dictator: ConsensusAuthority::Alice,
+    };
+    let genesis = Header {
+        parent: 0,
+        height: 0,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: ConsensusAuthority::Charlie,
+    };
+
+    assert!(dictator.validate(&ConsensusAuthority::Alice, &genesis));
+}
+
+#[cfg(test)]
+fn genesis_succession_digest() -> SuccessionDigest {
+    SuccessionDigest {
+        signature: ConsensusAuthority::Alice,
+        dictator_index: 0,
+        transfer_power: false,
+    }
+}
+
+#[test]
+fn fallback_dictator_normal_blocks() {
+    let engine = FallbackDictator {
+        heirs: vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob],
+    };
+
+    let b1 = engine
+        .seal(&genesis_succession_digest(), partial_header(1))
+        .unwrap();
+    let b2 = engine
+        .seal(&b1.consensus_digest, partial_header(2))
+        .unwrap();
+
+    assert_eq!(b1.consensus_digest.signature, ConsensusAuthority::Alice);
+    assert_eq!(b2.consensus_digest.signature, ConsensusAuthority::Alice);
+    assert!(engine.validate(&genesis_succession_digest(), &b1));
+    assert!(engine.validate(&b1.consensus_digest, &b2));
+
+    // The heir may not sign while the first dictator is in power
+    let mut usurped = b2.clone();
+    usurped.consensus_digest.signature = ConsensusAuthority::Bob;
+    assert!(!engine.validate(&b1.consensus_digest, &usurped));
+}
+
+#[test]
+fn fallback_dictator_succession() {
+    let engine = FallbackDictator {
+        heirs: vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob],
+    };
+
+    let b1 = engine
+        .seal_with_succession(&genesis_succession_digest(), partial_header(1), true)
+        .unwrap();
+    let b2 = engine
+        .seal(&b1.consensus_digest, partial_header(2))
+        .unwrap();
+
+    assert!(engine.validate(&genesis_succession_digest(), &b1));
+    assert_eq!(b2.consensus_digest.signature, ConsensusAuthority::Bob);
+    assert!(engine.validate(&b1.consensus_digest, &b2));
+
+    // The old dictator has given up power
+    let mut stale = b2.clone();
+    stale.consensus_digest = SuccessionDigest {
+        signature: ConsensusAuthority::Alice,
+        dictator_index: 0,
+        transfer_power: false,
+    };
+    assert!(!engine.validate(&b1.consensus_digest, &stale));
+
+    // The last heir has nobody to transfer power to
+    assert!(engine
+        .seal_with_succession(&b2.consensus_digest, partial_header(3), true)
+        .is_none());
+}
+
+#[cfg(test)]
+fn vetoed_pow() -> VetoedBy<super::p1_pow::Pow> {
+    VetoedBy {
+        inner: super::p1_pow::moderate_difficulty_pow(),
+        dictator: ConsensusAuthority::Charlie,
+    }
+}
+
+#[test]
+fn vetoed_by_accepts_co_signed_block() {
+    let engine = vetoed_pow();
+    let parent_digest = VetoDigest {
+        inner: 0,
+        co_signature: None,
+    };
+
+    let header = engine.seal(&parent_digest, partial_header(1)).unwrap();
+
+    assert_eq!(
+        header.consensus_digest.co_signature,
+        Some(ConsensusAuthority::Charlie)
+    );
+    assert!(engine.validate(&parent_digest, &header));
+}
+
+#[test]
+fn vetoed_by_rejects_block_without_co_signature() {
+    let engine = vetoed_pow();
+    let parent_digest = VetoDigest {
+        inner: 0,
+        co_signature: None,
+    };
+
+    // Mined so that the inner engine accepts it, but never co-signed
+    let inner_header = engine.inner.seal(&0, partial_header(1)).unwrap();
+    let header = with_digest(
+        &inner_header,
+        VetoDigest {
+            inner: inner_header.consensus_digest,
+            co_signature: None,
+        },
+    );
+    assert!(engine.inner.validate(&0, &inner_header));
+    assert!(!engine.validate(&parent_digest, &header));
+
+    // Co-signed by somebody other than the dictator
+    let mut forged = header.clone();
+    forged.consensus_digest.co_signature = Some(ConsensusAuthority::Alice);
+    assert!(!engine.validate(&parent_digest, &forged));
+}
@@ -9,9 +9,15 @@
 //! Even when using the Proof of Stake configuration, the underlying consensus logic is identical to
 //! the proof of authority we are writing here.
 
+use super::p9_chain_import::HeaderForkChoice;
 use super::{Consensus, ConsensusAuthority, Header};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+#[cfg(test)]
+use super::partial_header;
 
 /// A Proof of Authority consensus engine. If any of the authorities have signed the block, it is valid.
+/// The genesis block does not require a seal.
 pub struct SimplePoa {
     pub authorities: Vec<ConsensusAuthority>,
 }
@@ -20,6 +26,10 @@ impl Consensus for SimplePoa {
     type Digest = ConsensusAuthority;
 
     fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        if Self::is_genesis(header) {
+            return true;
+        }
+
         return self.authorities.contains(&header.consensus_digest);
     }
 
@@ -28,7 +38,8 @@ impl Consensus for SimplePoa {
         parent_digest: &Self::Digest,
         partial_header: Header<()>,
     ) -> Option<Header<Self::Digest>> {
-        if self.authorities.is_empty() {
+        // Genesis block does not require a seal and we need at least one authority
+        if partial_header.height == 0 || self.authorities.is_empty() {
             return None;
         }
 
@@ -36,6 +47,7 @@ impl Consensus for SimplePoa {
             consensus_digest: self.authorities[0],
             height: partial_header.height,
             extrinsics_root: partial_header.extrinsics_root,
+            timestamp: partial_header.timestamp,
             state_root: partial_header.state_root,
             parent: partial_header.parent,
         };
@@ -47,18 +59,22 @@ impl Consensus for SimplePoa {
 /// A Proof of Authority consensus engine. Only one authority is valid at each block height.
 /// As ever, the genesis block does not require a seal. After that the authorities take turns
 /// in order.
-struct PoaRoundRobinByHeight {
-    authorities: Vec<ConsensusAuthority>,
+pub struct PoaRoundRobinByHeight {
+    pub authorities: Vec<ConsensusAuthority>,
 }
 
 impl Consensus for PoaRoundRobinByHeight {
     type Digest = ConsensusAuthority;
 
     fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
-        if header.height == 0 {
+        if Self::is_genesis(header) {
             return true;
         }
 
+        if self.authorities.is_empty() {
+            return false;
+        }
+
         let pos = (header.height - 1) as usize % self.authorities.len();
         return self.authorities[pos] == header.consensus_digest;
     }
@@ -68,8 +84,8 @@ impl Consensus for PoaRoundRobinByHeight {
         parent_digest: &Self::Digest,
         partial_header: Header<()>,
     ) -> Option<Header<Self::Digest>> {
-        // Genesis block does not require a seal
-        if partial_header.height == 0 {
+        // Genesis block does not require a seal and we need at least one authority
+        if partial_header.height == 0 || self.authorities.is_empty() {
             return None;
         }
 
@@ -78,6 +94,7 @@ impl Consensus for PoaRoundRobinByHeight {
             consensus_digest: self.authorities[pos],
             height: partial_header.height,
             extrinsics_root: partial_header.extrinsics_root,
+            timestamp: partial_header.timestamp,
             state_root: partial_header.state_root,
             parent: partial_header.parent,
         };
@@ -86,6 +103,132 @@ impl Consensus for PoaRoundRobinByHeight {
     }
 }
 
+impl PoaRoundRobinByHeight {
+    /// Count how many blocks each authority signed. The genesis header is not signed by anyone
+    /// and is skipped.
+    pub fn author_distribution(
+        headers: &[Header<ConsensusAuthority>],
+    ) -> BTreeMap<ConsensusAuthority, usize> {
+        let mut distribution = BTreeMap::new();
+        for header in headers.iter().filter(|h| h.height > 0) {
+            *distribution.entry(header.consensus_digest).or_insert(0) += 1;
+        }
+        distribution
+    }
+
+    /// Whether the signers of the given headers look like a perfect round robin, in which the
+    /// block counts of any two authorities differ by at most one. Only authorities that signed
+    /// at least one block are considered, so an authority that was skipped entirely has to be
+    /// detected by comparing the distribution to the authority set.
+    pub fn is_fair(headers: &[Header<ConsensusAuthority>]) -> bool {
+        let distribution = Self::author_distribution(headers);
+        match (distribution.values().min(), distribution.values().max()) {
+            (Some(min), Some(max)) => max - min <= 1,
+            _ => true,
+        }
+    }
+}
+
+/// A Proof of Authority consensus engine in which each authority carries a weight, such as its stake.
+/// As with `SimplePoa`, a block is valid if any of the authorities has signed it. The weights do not
+/// affect validity, but they let fork choice prefer chains signed by heavier authorities.
+pub struct WeightedPoa {
+    pub authorities: Vec<(ConsensusAuthority, u64)>,
+}
+
+impl WeightedPoa {
+    /// The weight of the given authority, or zero if it is not one of the authorities.
+    pub fn author_weight(&self, authority: ConsensusAuthority) -> u64 {
+        self.authorities
+            .iter()
+            .find(|(a, _)| *a == authority)
+            .map(|(_, weight)| *weight)
+            .unwrap_or(0)
+    }
+}
+
+impl Consensus for WeightedPoa {
+    type Digest = ConsensusAuthority;
+
+    fn validate(&self, _: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        Self::is_genesis(header)
+            || self
+                .authorities
+                .iter()
+                .any(|(a, _)| *a == header.consensus_digest)
+    }
+
+    /// Seal with the heaviest authority.
+    fn seal(&self, _: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
+        // Genesis block does not require a seal
+        if partial_header.height == 0 {
+            return None;
+        }
+
+        let (signature, _) = self.authorities.iter().max_by_key(|(_, weight)| *weight)?;
+
+        Some(Header {
+            consensus_digest: *signature,
+            height: partial_header.height,
+            extrinsics_root: partial_header.extrinsics_root,
+            timestamp: partial_header.timestamp,
+            state_root: partial_header.state_root,
+            parent: partial_header.parent,
+        })
+    }
+
+    /// A header weighs as much as the authority that signed it.
+    fn block_weight(&self, header: &Header<Self::Digest>) -> u64 {
+        self.author_weight(header.consensus_digest)
+    }
+}
+
+/// The best chain is the one whose signers carry the most accumulated authority weight. A short chain
+/// signed by heavy authorities beats a longer one signed by light authorities.
+pub struct MostAuthorityWeightRule;
+
+impl HeaderForkChoice<WeightedPoa> for MostAuthorityWeightRule {
+    fn first_chain_is_better(
+        engine: &WeightedPoa,
+        chain_1: &[Header<ConsensusAuthority>],
+        chain_2: &[Header<ConsensusAuthority>],
+    ) -> bool {
+        let weight = |chain: &[Header<ConsensusAuthority>]| -> u128 {
+            chain
+                .iter()
+                .map(|h| engine.author_weight(h.consensus_digest) as u128)
+                .sum()
+        };
+
+        weight(chain_1) > weight(chain_2)
+    }
+}
+
+/// The number of distinct authorities that signed the last `k` headers of the chain.
+fn recent_author_diversity(chain: &[Header<ConsensusAuthority>], k: usize) -> usize {
+    chain
+        .iter()
+        .rev()
+        .take(k)
+        .map(|h| h.consensus_digest)
+        .collect::<BTreeSet<_>>()
+        .len()
+}
+
+/// Pick the chain whose last `k` blocks were produced by the most distinct authorities. This
+/// discourages a single authority from dominating block production. Ties are broken in favor of
+/// the longer chain, and after that the first such chain. Returns `None` when there are no chains.
+pub fn most_diverse_chain(
+    chains: &[Vec<Header<ConsensusAuthority>>],
+    k: usize,
+) -> Option<&[Header<ConsensusAuthority>]> {
+    chains
+        .iter()
+        .rev()
+        .max_by_key(|chain| (recent_author_diversity(chain, k), chain.len()))
+        .map(|chain| chain.as_slice())
+}
+
 /// Both of the previous PoA schemes have the weakness that a single dishonest authority can corrupt the chain.
 /// * When allowing any authority to sign, the single corrupt authority can sign blocks with invalid transitions
 ///   with no way to throttle them.
@@ -94,8 +237,16 @@ impl Consensus for PoaRoundRobinByHeight {
 ///
 /// A common PoA scheme that works around these weaknesses is to divide time into slots, and then do a round robin
 /// by slot instead of by height
+///
+/// Slots may be skipped when an authority is offline, but not by an unbounded amount. Without a bound,
+/// an authority could claim an arbitrarily high slot that happens to map to itself and jump the queue.
+/// Worse, because slots must be strictly increasing, such a block would push every honest authority's
+/// future slots out of reach, stalling the chain. Bounding the skip keeps a dishonest authority from
+/// hurting liveness while still tolerating a few missed slots.
 struct PoaRoundRobinBySlot {
     authorities: Vec<ConsensusAuthority>,
+    /// The maximum number of slots a block may advance beyond its parent's slot.
+    max_slot_skip: u64,
 }
 
 /// A digest used for PoaRoundRobinBySlot. The digest contains the slot number as well as the signature.
@@ -107,11 +258,81 @@ struct SlotDigest {
     signature: ConsensusAuthority,
 }
 
+/// Proof that an authority equivocated by signing two distinct headers for the same slot. This is
+/// the data a governance or staking module needs to penalize the authority.
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct SlashRecord {
+    authority: ConsensusAuthority,
+    slot: u64,
+    evidence: (Header<SlotDigest>, Header<SlotDigest>),
+}
+
+impl PoaRoundRobinBySlot {
+    /// Scan a set of observed headers for equivocation. An authority equivocates when it signs
+    /// more than one distinct header for the same slot. Returns the slot and the offending
+    /// authority for the first equivocation found. This is the evidence needed for slashing.
+    pub fn detect_equivocation(
+        headers: &[Header<SlotDigest>],
+    ) -> Option<(u64, ConsensusAuthority)> {
+        Self::slash_records(headers)
+            .first()
+            .map(|record| (record.slot, record.authority))
+    }
+
+    /// Scan a set of observed headers for equivocation, and produce a slash record for every
+    /// slot in which an authority signed more than one distinct header. The evidence is the first
+    /// two distinct headers observed for that slot. Records are in the order the equivocations
+    /// were observed.
+    pub fn slash_records(headers: &[Header<SlotDigest>]) -> Vec<SlashRecord> {
+        let mut seen: HashMap<(u64, ConsensusAuthority), &Header<SlotDigest>> = HashMap::new();
+        let mut reported: HashSet<(u64, ConsensusAuthority)> = HashSet::new();
+        let mut records = Vec::new();
+
+        for header in headers {
+            let key = (
+                header.consensus_digest.slot,
+                header.consensus_digest.signature,
+            );
+            match seen.get(&key) {
+                Some(previous) if *previous != header && reported.insert(key) => {
+                    records.push(SlashRecord {
+                        authority: key.1,
+                        slot: key.0,
+                        evidence: ((*previous).clone(), header.clone()),
+                    });
+                }
+                Some(_) => {}
+                None => {
+                    seen.insert(key, header);
+                }
+            }
+        }
+
+        records
+    }
+
+    /// The number of slots skipped between consecutive blocks of the chain. Every slot without a block
+    /// is a slot in which an authority was offline or failed to author, so this quantifies how much the
+    /// chain's liveness has degraded.
+    pub fn missed_slots(headers: &[Header<SlotDigest>]) -> u64 {
+        headers
+            .windows(2)
+            .map(|pair| {
+                pair[1]
+                    .consensus_digest
+                    .slot
+                    .saturating_sub(pair[0].consensus_digest.slot)
+                    .saturating_sub(1)
+            })
+            .sum()
+    }
+}
+
 impl Consensus for PoaRoundRobinBySlot {
     type Digest = SlotDigest;
 
     fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
-        if header.height == 0 {
+        if Self::is_genesis(header) {
             return true;
         }
 
@@ -129,7 +350,9 @@ impl Consensus for PoaRoundRobinBySlot {
         let expected_authority = self.authorities[pos];
 
         return expected_authority == header.consensus_digest.signature
-            && header.consensus_digest.slot > parent_digest.slot;
+            && header.consensus_digest.slot > parent_digest.slot
+            && header.consensus_digest.slot
+                <= parent_digest.slot.saturating_add(self.max_slot_skip);
     }
 
     fn seal(
@@ -152,6 +375,7 @@ impl Consensus for PoaRoundRobinBySlot {
             consensus_digest: slot_digest,
             height: partial_header.height,
             extrinsics_root: partial_header.extrinsics_root,
+            timestamp: partial_header.timestamp,
             state_root: partial_header.state_root,
             parent: partial_header.parent,
         };
@@ -160,6 +384,259 @@ impl Consensus for PoaRoundRobinBySlot {
     }
 }
 
+/// A fork choice rule for slot based PoA that measures chain quality by how few slots were missed.
+/// The chain with the fewest missed slots per block is better, regardless of its length. When
+/// both chains are equally gapless, the longer one is better.
+pub struct FewestMissedSlotsRule;
+
+impl HeaderForkChoice<PoaRoundRobinBySlot> for FewestMissedSlotsRule {
+    fn first_chain_is_better(
+        _: &PoaRoundRobinBySlot,
+        chain_1: &[Header<SlotDigest>],
+        chain_2: &[Header<SlotDigest>],
+    ) -> bool {
+        let missed_1 = PoaRoundRobinBySlot::missed_slots(chain_1) as u128;
+        let missed_2 = PoaRoundRobinBySlot::missed_slots(chain_2) as u128;
+        let len_1 = chain_1.len() as u128;
+        let len_2 = chain_2.len() as u128;
+
+        // Compare missed_1 / len_1 with missed_2 / len_2 without dividing
+        match (missed_1 * len_2).cmp(&(missed_2 * len_1)) {
+            std::cmp::Ordering::Less => true,
+            std::cmp::Ordering::Greater => false,
+            std::cmp::Ordering::Equal => len_1 > len_2,
+        }
+    }
+}
+
+/// Slot based engines know ahead of time which authority is allowed to author each slot. Exposing
+/// that schedule lets authorities prepare for their turn and lets observers tell a missed slot
+/// from a dishonest one.
+pub trait SlotSchedule {
+    /// The authority expected to author the given slot, if any.
+    fn slot_leader(&self, slot: u64) -> Option<ConsensusAuthority>;
+
+    /// The deterministic leader schedule for the `epoch_len` slots starting at `epoch_start_slot`.
+    fn leader_schedule(&self, epoch_start_slot: u64, epoch_len: u64) -> Vec<ConsensusAuthority> {
+        (epoch_start_slot..epoch_start_slot.saturating_add(epoch_len))
+            .filter_map(|slot| self.slot_leader(slot))
+            .collect()
+    }
+}
+
+impl SlotSchedule for PoaRoundRobinBySlot {
+    fn slot_leader(&self, slot: u64) -> Option<ConsensusAuthority> {
+        if self.authorities.is_empty() {
+            return None;
+        }
+
+        let pos = slot.checked_sub(1)? as usize % self.authorities.len();
+        Some(self.authorities[pos])
+    }
+}
+
+/// A proof of stake flavoured variant of the slot round robin. Each authority is assigned a number
+/// of consecutive slots per rotation equal to its stake, so an authority with twice the stake
+/// authors twice as many blocks.
+struct PoaStakeWeightedBySlot {
+    /// The authorities together with their stake.
+    stakes: Vec<(ConsensusAuthority, u64)>,
+}
+
+impl SlotSchedule for PoaStakeWeightedBySlot {
+    fn slot_leader(&self, slot: u64) -> Option<ConsensusAuthority> {
+        let total_stake: u64 = self.stakes.iter().map(|(_, stake)| stake).sum();
+        if total_stake == 0 {
+            return None;
+        }
+
+        let mut position = slot.checked_sub(1)? % total_stake;
+        for (authority, stake) in &self.stakes {
+            if position < *stake {
+                return Some(*authority);
+            }
+            position -= stake;
+        }
+
+        None
+    }
+}
+
+impl Consensus for PoaStakeWeightedBySlot {
+    type Digest = SlotDigest;
+
+    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        if Self::is_genesis(header) {
+            return true;
+        }
+
+        header.consensus_digest.slot > parent_digest.slot
+            && self.slot_leader(header.consensus_digest.slot)
+                == Some(header.consensus_digest.signature)
+    }
+
+    fn seal(
+        &self,
+        parent_digest: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>> {
+        // Genesis block does not require a seal
+        if partial_header.height == 0 {
+            return None;
+        }
+
+        let slot = parent_digest.slot + 1;
+        let signature = self.slot_leader(slot)?;
+
+        Some(Header {
+            consensus_digest: SlotDigest { slot, signature },
+            height: partial_header.height,
+            extrinsics_root: partial_header.extrinsics_root,
+            timestamp: partial_header.timestamp,
+            state_root: partial_header.state_root,
+            parent: partial_header.parent,
+        })
+    }
+}
+
+/// Real PoA chains rotate their authority set over time. This trait lets the PoA engines above be
+/// rebuilt with a different authority set so they can be used by `WithAuthorityChanges`.
+pub trait AuthoritySet: Sized {
+    /// The authorities this engine currently accepts.
+    fn authorities(&self) -> &[ConsensusAuthority];
+
+    /// A copy of this engine using the given authorities instead.
+    fn with_authorities(&self, authorities: Vec<ConsensusAuthority>) -> Self;
+}
+
+impl AuthoritySet for SimplePoa {
+    fn authorities(&self) -> &[ConsensusAuthority] {
+        &self.authorities
+    }
+
+    fn with_authorities(&self, authorities: Vec<ConsensusAuthority>) -> Self {
+        SimplePoa { authorities }
+    }
+}
+
+impl AuthoritySet for PoaRoundRobinByHeight {
+    fn authorities(&self) -> &[ConsensusAuthority] {
+        &self.authorities
+    }
+
+    fn with_authorities(&self, authorities: Vec<ConsensusAuthority>) -> Self {
+        PoaRoundRobinByHeight { authorities }
+    }
+}
+
+/// A digest for PoA chains whose authority set can change. Besides the signature, each header records
+/// the authority set it was signed under, and may propose a new set that takes effect from the next block.
+#[derive(Hash, Debug, PartialEq, Eq, Clone)]
+pub struct AuthorityChangeDigest {
+    signature: ConsensusAuthority,
+    authorities: Vec<ConsensusAuthority>,
+    authority_change: Option<Vec<ConsensusAuthority>>,
+}
+
+impl AuthorityChangeDigest {
+    /// The authority set that the child of this header must be signed under.
+    fn next_authorities(&self) -> &[ConsensusAuthority] {
+        self.authority_change.as_ref().unwrap_or(&self.authorities)
+    }
+}
+
+/// A higher-order PoA engine that allows the inner engine's authority set to change over time.
+///
+/// The inner engine's own authorities are the set used for the first block after genesis. A block
+/// proposing a change is still validated against the old set, and the new set applies from its child on.
+pub struct WithAuthorityChanges<Inner: AuthoritySet> {
+    inner: Inner,
+}
+
+impl<Inner> WithAuthorityChanges<Inner>
+where
+    Inner: AuthoritySet + Consensus<Digest = ConsensusAuthority>,
+{
+    /// The authority set that a header at the given height must be signed under.
+    fn active_authorities(
+        &self,
+        parent_digest: &AuthorityChangeDigest,
+        height: u64,
+    ) -> Vec<ConsensusAuthority> {
+        if height <= 1 {
+            self.inner.authorities().to_vec()
+        } else {
+            parent_digest.next_authorities().to_vec()
+        }
+    }
+
+    /// Seal the given partial header, optionally proposing a new authority set for the following blocks.
+    pub fn seal_with_authority_change(
+        &self,
+        parent_digest: &AuthorityChangeDigest,
+        partial_header: Header<()>,
+        authority_change: Option<Vec<ConsensusAuthority>>,
+    ) -> Option<Header<AuthorityChangeDigest>> {
+        let authorities = self.active_authorities(parent_digest, partial_header.height);
+        let sealed = self
+            .inner
+            .with_authorities(authorities.clone())
+            .seal(&parent_digest.signature, partial_header)?;
+
+        Some(Header {
+            consensus_digest: AuthorityChangeDigest {
+                signature: sealed.consensus_digest,
+                authorities,
+                authority_change,
+            },
+            height: sealed.height,
+            extrinsics_root: sealed.extrinsics_root,
+            timestamp: sealed.timestamp,
+            state_root: sealed.state_root,
+            parent: sealed.parent,
+        })
+    }
+}
+
+impl<Inner> Consensus for WithAuthorityChanges<Inner>
+where
+    Inner: AuthoritySet + Consensus<Digest = ConsensusAuthority>,
+{
+    type Digest = AuthorityChangeDigest;
+
+    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        if Self::is_genesis(header) {
+            return true;
+        }
+
+        let authorities = self.active_authorities(parent_digest, header.height);
+        if header.consensus_digest.authorities != authorities {
+            return false;
+        }
+
+        let inner_header = Header {
+            consensus_digest: header.consensus_digest.signature,
+            height: header.height,
+            extrinsics_root: header.extrinsics_root,
+            timestamp: header.timestamp,
+            state_root: header.state_root,
+            parent: header.parent,
+        };
+
+        self.inner
+            .with_authorities(authorities)
+            .validate(&parent_digest.signature, &inner_header)
+    }
+
+    fn seal(
+        &self,
+        parent_digest: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>> {
+        self.seal_with_authority_change(parent_digest, partial_header, None)
+    }
+}
+
 #[cfg(test)]
 
 // Helper function to create a Header
@@ -170,6 +647,7 @@ fn create_header(digest: ConsensusAuthority, height: u64) -> Header<ConsensusAut
         parent: 123,
         state_root: 123,
         extrinsics_root: 123,
+        timestamp: 0,
     }
 }
 
@@ -198,6 +676,7 @@ fn simple_poa_seal() {
         parent: 123,
         state_root: 123,
         extrinsics_root: 123,
+        timestamp: 0,
     };
 
     if let Some(sealed_header) = poa.seal(&ConsensusAuthority::Alice, partial_header) {
@@ -207,6 +686,28 @@ fn simple_poa_seal() {
     }
 }
 
+#[test]
+fn simple_poa_does_not_seal_genesis() {
+    let poa = SimplePoa {
+        authorities: vec![ConsensusAuthority::Alice],
+    };
+
+    let genesis_partial_header = Header::<()> {
+        consensus_digest: (),
+        height: 0,
+        parent: 123,
+        state_root: 123,
+        extrinsics_root: 123,
+        timestamp: 0,
+    };
+
+    assert!(
+        poa.seal(&ConsensusAuthority::Alice, genesis_partial_header)
+            .is_none(),
+        "Genesis block should not be sealed"
+    );
+}
+
 #[test]
 fn poa_round_robin_validate() {
     let poa = PoaRoundRobinByHeight {
@@ -252,10 +753,12 @@ fn poa_round_robin_seal() {
         parent: 123,
         state_root: 123,
         extrinsics_root: 123,
+        timestamp: 0,
     };
     let partial_header_2 = Header::<()> {
         height: 2,
         extrinsics_root: 123,
+        timestamp: 0,
         state_root: 123,
         parent: 123,
         consensus_digest: (),
@@ -263,6 +766,7 @@ fn poa_round_robin_seal() {
     let partial_header_3 = Header::<()> {
         height: 3,
         extrinsics_root: 123,
+        timestamp: 0,
         state_root: 123,
         parent: 123,
         consensus_digest: (),
@@ -308,6 +812,7 @@ fn poa_round_robin_seal() {
         parent: 123,
         state_root: 123,
         extrinsics_root: 123,
+        timestamp: 0,
     };
     assert!(
         poa.seal(&ConsensusAuthority::Alice, genesis_partial_header)
@@ -315,3 +820,664 @@ fn poa_round_robin_seal() {
         "Genesis block should not be sealed"
     );
 }
+
+#[cfg(test)]
+fn create_slot_header(slot: u64, signature: ConsensusAuthority, height: u64) -> Header<SlotDigest> {
+    Header {
+        consensus_digest: SlotDigest { slot, signature },
+        height,
+        parent: 123,
+        state_root: 123,
+        extrinsics_root: 123,
+        timestamp: 0,
+    }
+}
+
+#[test]
+fn poa_round_robin_by_slot_skipped_slot_valid() {
+    let poa = PoaRoundRobinBySlot {
+        authorities: vec![
+            ConsensusAuthority::Alice,
+            ConsensusAuthority::Bob,
+            ConsensusAuthority::Charlie,
+        ],
+        max_slot_skip: 3,
+    };
+    let parent_digest = SlotDigest {
+        slot: 1,
+        signature: ConsensusAuthority::Alice,
+    };
+
+    // Bob missed slot 2, so Charlie authors in slot 3
+    let header = create_slot_header(3, ConsensusAuthority::Charlie, 2);
+    assert!(poa.validate(&parent_digest, &header));
+}
+
+#[test]
+fn poa_round_robin_by_slot_excessive_skip_invalid() {
+    let poa = PoaRoundRobinBySlot {
+        authorities: vec![
+            ConsensusAuthority::Alice,
+            ConsensusAuthority::Bob,
+            ConsensusAuthority::Charlie,
+        ],
+        max_slot_skip: 3,
+    };
+    let parent_digest = SlotDigest {
+        slot: 1,
+        signature: ConsensusAuthority::Alice,
+    };
+
+    // Slot 5 is Bob's slot, but it is too far ahead of the parent
+    let header = create_slot_header(5, ConsensusAuthority::Bob, 2);
+    assert!(!poa.validate(&parent_digest, &header));
+}
+
+#[cfg(test)]
+fn genesis_change_digest() -> AuthorityChangeDigest {
+    AuthorityChangeDigest {
+        signature: ConsensusAuthority::Alice,
+        authorities: vec![],
+        authority_change: None,
+    }
+}
+
+#[test]
+fn simple_poa_authority_change_adds_authority() {
+    let poa = WithAuthorityChanges {
+        inner: SimplePoa {
+            authorities: vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob],
+        },
+    };
+    let genesis = genesis_change_digest();
+    let all = vec![
+        ConsensusAuthority::Alice,
+        ConsensusAuthority::Bob,
+        ConsensusAuthority::Charlie,
+    ];
+
+    let b1 = poa
+        .seal_with_authority_change(&genesis, partial_header(1), Some(all.clone()))
+        .unwrap();
+    assert!(poa.validate(&genesis, &b1));
+
+    // The block proposing the change is still checked against the old set
+    let mut b1_by_charlie = b1.clone();
+    b1_by_charlie.consensus_digest.signature = ConsensusAuthority::Charlie;
+    assert!(!poa.validate(&genesis, &b1_by_charlie));
+
+    // From the next block on, Charlie may sign
+    let mut b2 = poa.seal(&b1.consensus_digest, partial_header(2)).unwrap();
+    assert_eq!(b2.consensus_digest.authorities, all);
+    b2.consensus_digest.signature = ConsensusAuthority::Charlie;
+    assert!(poa.validate(&b1.consensus_digest, &b2));
+}
+
+#[test]
+fn simple_poa_authority_change_removes_authority() {
+    let poa = WithAuthorityChanges {
+        inner: SimplePoa {
+            authorities: vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob],
+        },
+    };
+    let genesis = genesis_change_digest();
+
+    let b1 = poa
+        .seal_with_authority_change(
+            &genesis,
+            partial_header(1),
+            Some(vec![ConsensusAuthority::Bob]),
+        )
+        .unwrap();
+    assert_eq!(b1.consensus_digest.signature, ConsensusAuthority::Alice);
+    assert!(poa.validate(&genesis, &b1));
+
+    let b2 = poa.seal(&b1.consensus_digest, partial_header(2)).unwrap();
+    assert_eq!(b2.consensus_digest.signature, ConsensusAuthority::Bob);
+    assert!(poa.validate(&b1.consensus_digest, &b2));
+
+    // Alice is no longer an authority, and the change persists past the next block
+    let b3 = poa.seal(&b2.consensus_digest, partial_header(3)).unwrap();
+    let mut b3_by_alice = b3.clone();
+    b3_by_alice.consensus_digest.signature = ConsensusAuthority::Alice;
+    assert!(poa.validate(&b2.consensus_digest, &b3));
+    assert!(!poa.validate(&b2.consensus_digest, &b3_by_alice));
+}
+
+#[test]
+fn round_robin_authority_change() {
+    let poa = WithAuthorityChanges {
+        inner: PoaRoundRobinByHeight {
+            authorities: vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob],
+        },
+    };
+    let genesis = genesis_change_digest();
+
+    let b1 = poa.seal(&genesis, partial_header(1)).unwrap();
+    let b2 = poa
+        .seal_with_authority_change(
+            &b1.consensus_digest,
+            partial_header(2),
+            Some(vec![ConsensusAuthority::Charlie]),
+        )
+        .unwrap();
+    let b3 = poa.seal(&b2.consensus_digest, partial_header(3)).unwrap();
+
+    assert_eq!(b1.consensus_digest.signature, ConsensusAuthority::Alice);
+    assert_eq!(b2.consensus_digest.signature, ConsensusAuthority::Bob);
+    assert_eq!(b3.consensus_digest.signature, ConsensusAuthority::Charlie);
+    assert!(poa.validate(&genesis, &b1));
+    assert!(poa.validate(&b1.consensus_digest, &b2));
+    assert!(poa.validate(&b2.consensus_digest, &b3));
+
+    // A header that claims the old set after the change is rejected
+    let mut stale = b3.clone();
+    stale.consensus_digest.authorities = vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob];
+    stale.consensus_digest.signature = ConsensusAuthority::Alice;
+    assert!(!poa.validate(&b2.consensus_digest, &stale));
+}
+
+#[test]
+fn poa_engines_genesis_not_checked() {
+    let authorities = vec![ConsensusAuthority::Alice];
+    let genesis = create_header(ConsensusAuthority::Charlie, 0);
+
+    let simple = SimplePoa {
+        authorities: authorities.clone(),
+    };
+    assert!(simple.validate(&ConsensusAuthority::Alice, &genesis));
+
+    let by_height = PoaRoundRobinByHeight {
+        authorities: authorities.clone(),
+    };
+    assert!(by_height.validate(&ConsensusAuthority::Alice, &genesis));
+
+    let by_slot = PoaRoundRobinBySlot {
+        authorities,
+        max_slot_skip: 1,
+    };
+    let slot_genesis = create_slot_header(0, ConsensusAuthority::Charlie, 0);
+    let parent_digest = SlotDigest {
+        slot: 100,
+        signature: ConsensusAuthority::Bob,
+    };
+    assert!(by_slot.validate(&parent_digest, &slot_genesis));
+}
+
+#[test]
+fn poa_round_robin_by_slot_no_equivocation() {
+    let headers = vec![
+        create_slot_header(1, ConsensusAuthority::Alice, 1),
+        create_slot_header(2, ConsensusAuthority::Bob, 2),
+        // The same header observed twice is not an equivocation
+        create_slot_header(2, ConsensusAuthority::Bob, 2),
+        create_slot_header(4, ConsensusAuthority::Alice, 3),
+    ];
+
+    assert_eq!(PoaRoundRobinBySlot::detect_equivocation(&headers), None);
+}
+
+#[test]
+fn poa_round_robin_by_slot_detects_equivocation() {
+    let mut fork = create_slot_header(2, ConsensusAuthority::Bob, 2);
+    fork.state_root = 456;
+    let headers = vec![
+        create_slot_header(1, ConsensusAuthority::Alice, 1),
+        create_slot_header(2, ConsensusAuthority::Bob, 2),
+        fork,
+    ];
+
+    assert_eq!(
+        PoaRoundRobinBySlot::detect_equivocation(&headers),
+        Some((2, ConsensusAuthority::Bob))
+    );
+}
+
+#[test]
+fn poa_round_robin_by_slot_honest_headers_are_not_slashed() {
+    let headers = vec![
+        create_slot_header(1, ConsensusAuthority::Alice, 1),
+        create_slot_header(2, ConsensusAuthority::Bob, 2),
+        create_slot_header(2, ConsensusAuthority::Bob, 2),
+        create_slot_header(4, ConsensusAuthority::Alice, 3),
+    ];
+
+    assert!(PoaRoundRobinBySlot::slash_records(&headers).is_empty());
+}
+
+#[test]
+fn poa_round_robin_by_slot_double_signed_slot_is_slashed_once() {
+    let honest = create_slot_header(2, ConsensusAuthority::Bob, 2);
+    let mut fork = honest.clone();
+    fork.state_root = 456;
+    let mut another_fork = honest.clone();
+    another_fork.state_root = 789;
+    let headers = vec![
+        create_slot_header(1, ConsensusAuthority::Alice, 1),
+        honest.clone(),
+        fork.clone(),
+        another_fork,
+        create_slot_header(3, ConsensusAuthority::Charlie, 3),
+    ];
+
+    assert_eq!(
+        PoaRoundRobinBySlot::slash_records(&headers),
+        vec![SlashRecord {
+            authority: ConsensusAuthority::Bob,
+            slot: 2,
+            evidence: (honest, fork),
+        }]
+    );
+}
+
+#[test]
+fn slot_chain_strictly_increasing_has_no_regression() {
+    let chain = vec![
+        create_slot_header(1, ConsensusAuthority::Alice, 1),
+        create_slot_header(2, ConsensusAuthority::Bob, 2),
+        create_slot_header(4, ConsensusAuthority::Bob, 3),
+    ];
+
+    assert_eq!(
+        super::first_regression(&chain, |d: &SlotDigest| d.slot),
+        None
+    );
+}
+
+#[test]
+fn slot_chain_regression_detected() {
+    let chain = vec![
+        create_slot_header(1, ConsensusAuthority::Alice, 1),
+        create_slot_header(3, ConsensusAuthority::Charlie, 2),
+        create_slot_header(3, ConsensusAuthority::Charlie, 3),
+        create_slot_header(2, ConsensusAuthority::Bob, 4),
+    ];
+
+    assert_eq!(
+        super::first_regression(&chain, |d: &SlotDigest| d.slot),
+        Some(2)
+    );
+}
+
+#[test]
+fn validate_chain_round_robin() {
+    let poa = PoaRoundRobinByHeight {
+        authorities: vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob],
+    };
+    let chain = vec![
+        create_header(ConsensusAuthority::Alice, 1),
+        create_header(ConsensusAuthority::Bob, 2),
+        create_header(ConsensusAuthority::Alice, 3),
+        create_header(ConsensusAuthority::Bob, 4),
+    ];
+
+    assert!(super::validate_chain(
+        &poa,
+        &ConsensusAuthority::Alice,
+        &chain
+    ));
+    assert!(super::validate_chain(&poa, &ConsensusAuthority::Alice, &[]));
+}
+
+#[test]
+fn validate_chain_bad_block_in_middle() {
+    let poa = PoaRoundRobinByHeight {
+        authorities: vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob],
+    };
+    let chain = vec![
+        create_header(ConsensusAuthority::Alice, 1),
+        create_header(ConsensusAuthority::Bob, 2),
+        create_header(ConsensusAuthority::Bob, 3),
+        create_header(ConsensusAuthority::Bob, 4),
+    ];
+
+    assert!(!super::validate_chain(
+        &poa,
+        &ConsensusAuthority::Alice,
+        &chain
+    ));
+}
+
+#[test]
+fn build_sealed_round_robin_chain() {
+    let poa = PoaRoundRobinByHeight {
+        authorities: vec![
+            ConsensusAuthority::Alice,
+            ConsensusAuthority::Bob,
+            ConsensusAuthority::Charlie,
+        ],
+    };
+    let partials = (1..=5).map(partial_header).collect();
+
+    let chain = super::build_sealed_chain(&poa, ConsensusAuthority::Alice, partials).unwrap();
+
+    assert_eq!(chain.len(), 5);
+    assert_eq!(chain[3].consensus_digest, ConsensusAuthority::Alice);
+    assert!(super::validate_chain(
+        &poa,
+        &ConsensusAuthority::Alice,
+        &chain
+    ));
+
+    // Genesis cannot be sealed, so the whole chain fails to build
+    let partials = (0..=5).map(partial_header).collect();
+    assert!(super::build_sealed_chain(&poa, ConsensusAuthority::Alice, partials).is_none());
+}
+
+#[test]
+fn round_robin_slot_leader_schedule_is_cyclic() {
+    use ConsensusAuthority::*;
+    let poa = PoaRoundRobinBySlot {
+        authorities: vec![Alice, Bob, Charlie],
+        max_slot_skip: 3,
+    };
+
+    assert_eq!(
+        poa.leader_schedule(1, 7),
+        vec![Alice, Bob, Charlie, Alice, Bob, Charlie, Alice]
+    );
+    assert_eq!(poa.leader_schedule(5, 3), vec![Bob, Charlie, Alice]);
+}
+
+#[test]
+fn stake_weighted_leader_schedule_is_proportional() {
+    use ConsensusAuthority::*;
+    let pos = PoaStakeWeightedBySlot {
+        stakes: vec![(Alice, 2), (Bob, 1)],
+    };
+
+    let schedule = pos.leader_schedule(1, 6);
+    assert_eq!(schedule, vec![Alice, Alice, Bob, Alice, Alice, Bob]);
+    assert_eq!(schedule.iter().filter(|a| **a == Alice).count(), 4);
+    assert_eq!(schedule.iter().filter(|a| **a == Bob).count(), 2);
+}
+
+#[test]
+fn stake_weighted_seal_follows_schedule() {
+    use ConsensusAuthority::*;
+    let pos = PoaStakeWeightedBySlot {
+        stakes: vec![(Alice, 2), (Bob, 1)],
+    };
+    let genesis_digest = SlotDigest {
+        slot: 0,
+        signature: Alice,
+    };
+
+    let partials = (1..=3).map(partial_header).collect();
+    let chain = super::build_sealed_chain(&pos, genesis_digest, partials).unwrap();
+    let signers: Vec<_> = chain.iter().map(|h| h.consensus_digest.signature).collect();
+    assert_eq!(signers, pos.leader_schedule(1, 3));
+    assert!(super::validate_chain(&pos, &genesis_digest, &chain));
+
+    // Bob may not author Alice's slot
+    assert!(!pos.validate(&genesis_digest, &create_slot_header(1, Bob, 1)));
+}
+
+#[test]
+fn weighted_poa_heavy_chain_beats_longer_light_chain() {
+    let poa = WeightedPoa {
+        authorities: vec![
+            (ConsensusAuthority::Alice, 10),
+            (ConsensusAuthority::Bob, 1),
+            (ConsensusAuthority::Charlie, 1),
+        ],
+    };
+
+    let heavy = vec![
+        create_header(ConsensusAuthority::Alice, 1),
+        create_header(ConsensusAuthority::Alice, 2),
+    ];
+    let light = vec![
+        create_header(ConsensusAuthority::Bob, 1),
+        create_header(ConsensusAuthority::Charlie, 2),
+        create_header(ConsensusAuthority::Bob, 3),
+        create_header(ConsensusAuthority::Charlie, 4),
+    ];
+
+    assert!(heavy
+        .iter()
+        .chain(&light)
+        .all(|h| poa.validate(&ConsensusAuthority::Alice, h)));
+    assert_eq!(poa.author_weight(ConsensusAuthority::Alice), 10);
+    assert_eq!(poa.author_weight(ConsensusAuthority::Bob), 1);
+
+    assert!(MostAuthorityWeightRule::first_chain_is_better(
+        &poa, &heavy, &light
+    ));
+    assert!(!MostAuthorityWeightRule::first_chain_is_better(
+        &poa, &light, &heavy
+    ));
+}
+
+#[test]
+fn most_diverse_chain_beats_longer_single_author_chain() {
+    let single_author: Vec<_> = (1..=6)
+        .map(|height| create_header(ConsensusAuthority::Alice, height))
+        .collect();
+    let diverse = vec![
+        create_header(ConsensusAuthority::Alice, 1),
+        create_header(ConsensusAuthority::Bob, 2),
+        create_header(ConsensusAuthority::Charlie, 3),
+    ];
+
+    let chains = vec![single_author, diverse.clone()];
+    assert_eq!(most_diverse_chain(&chains, 3), Some(diverse.as_slice()));
+}
+
+#[test]
+fn most_diverse_chain_only_counts_last_k_blocks() {
+    let diverse_then_single = vec![
+        create_header(ConsensusAuthority::Bob, 1),
+        create_header(ConsensusAuthority::Charlie, 2),
+        create_header(ConsensusAuthority::Alice, 3),
+        create_header(ConsensusAuthority::Alice, 4),
+    ];
+    let alternating = vec![
+        create_header(ConsensusAuthority::Alice, 1),
+        create_header(ConsensusAuthority::Bob, 2),
+    ];
+
+    let chains = vec![diverse_then_single, alternating.clone()];
+    assert_eq!(most_diverse_chain(&chains, 2), Some(alternating.as_slice()));
+}
+
+#[test]
+fn most_diverse_chain_breaks_ties_by_length() {
+    let short = vec![
+        create_header(ConsensusAuthority::Alice, 1),
+        create_header(ConsensusAuthority::Bob, 2),
+    ];
+    let long = vec![
+        create_header(ConsensusAuthority::Alice, 1),
+        create_header(ConsensusAuthority::Alice, 2),
+        create_header(ConsensusAuthority::Bob, 3),
+    ];
+
+    let chains = vec![short, long.clone()];
+    assert_eq!(most_diverse_chain(&chains, 2), Some(long.as_slice()));
+    assert_eq!(most_diverse_chain(&[], 2), None);
+}
+
+#[test]
+fn weighted_poa_seals_with_heaviest_authority() {
+    let poa = WeightedPoa {
+        authorities: vec![(ConsensusAuthority::Alice, 1), (ConsensusAuthority::Bob, 5)],
+    };
+    let partial_header = Header::<()> {
+        consensus_digest: (),
+        height: 1,
+        parent: 123,
+        state_root: 123,
+        extrinsics_root: 123,
+        timestamp: 0,
+    };
+
+    let sealed = poa
+        .seal(&ConsensusAuthority::Alice, partial_header)
+        .unwrap();
+    assert_eq!(sealed.consensus_digest, ConsensusAuthority::Bob);
+    assert_eq!(poa.author_weight(ConsensusAuthority::Charlie), 0);
+    assert!(!poa.validate(
+        &ConsensusAuthority::Alice,
+        &create_header(ConsensusAuthority::Charlie, 1)
+    ));
+}
+
+#[test]
+fn poa_round_robin_clean_chain_is_fair() {
+    let authorities = [
+        ConsensusAuthority::Alice,
+        ConsensusAuthority::Bob,
+        ConsensusAuthority::Charlie,
+    ];
+    let headers: Vec<_> = (0..=7)
+        .map(|height| create_header(authorities[height % 3], height as u64 + 1))
+        .collect();
+
+    let distribution = PoaRoundRobinByHeight::author_distribution(&headers);
+    assert_eq!(distribution[&ConsensusAuthority::Alice], 3);
+    assert_eq!(distribution[&ConsensusAuthority::Bob], 3);
+    assert_eq!(distribution[&ConsensusAuthority::Charlie], 2);
+    assert!(PoaRoundRobinByHeight::is_fair(&headers));
+}
+
+#[test]
+fn poa_round_robin_out_of_turn_signer_is_unfair() {
+    let headers = vec![
+        create_header(ConsensusAuthority::Alice, 0),
+        create_header(ConsensusAuthority::Alice, 1),
+        create_header(ConsensusAuthority::Bob, 2),
+        create_header(ConsensusAuthority::Alice, 3),
+        create_header(ConsensusAuthority::Alice, 4),
+    ];
+
+    let distribution = PoaRoundRobinByHeight::author_distribution(&headers);
+    assert_eq!(distribution[&ConsensusAuthority::Alice], 3);
+    assert_eq!(distribution[&ConsensusAuthority::Bob], 1);
+    assert!(!PoaRoundRobinByHeight::is_fair(&headers));
+}
+
+#[test]
+fn simple_poa_without_authorities() {
+    let poa = SimplePoa {
+        authorities: vec![],
+    };
+
+    assert!(poa
+        .seal(&ConsensusAuthority::Alice, partial_header(1))
+        .is_none());
+    assert!(!poa.validate(
+        &ConsensusAuthority::Alice,
+        &create_header(ConsensusAuthority::Alice, 1)
+    ));
+}
+
+#[test]
+fn poa_round_robin_without_authorities() {
+    let poa = PoaRoundRobinByHeight {
+        authorities: vec![],
+    };
+
+    assert!(poa
+        .seal(&ConsensusAuthority::Alice, partial_header(1))
+        .is_none());
+    assert!(!poa.validate(
+        &ConsensusAuthority::Alice,
+        &create_header(ConsensusAuthority::Alice, 1)
+    ));
+}
+
+#[test]
+fn poa_round_robin_by_slot_without_authorities() {
+    let poa = PoaRoundRobinBySlot {
+        authorities: vec![],
+        max_slot_skip: 3,
+    };
+    let parent_digest = SlotDigest {
+        slot: 0,
+        signature: ConsensusAuthority::Alice,
+    };
+    let header = Header {
+        consensus_digest: SlotDigest {
+            slot: 1,
+            signature: ConsensusAuthority::Alice,
+        },
+        height: 1,
+        parent: 123,
+        state_root: 123,
+        extrinsics_root: 123,
+        timestamp: 0,
+    };
+
+    assert!(poa.seal(&parent_digest, partial_header(1)).is_none());
+    assert!(!poa.validate(&parent_digest, &header));
+}
+
+#[cfg(test)]
+fn slot_chain(slots: &[u64]) -> Vec<Header<SlotDigest>> {
+    slots
+        .iter()
+        .enumerate()
+        .map(|(height, slot)| Header {
+            consensus_digest: SlotDigest {
+                slot: *slot,
+                signature: ConsensusAuthority::Alice,
+            },
+            height: height as u64,
+            parent: 123,
+            state_root: 123,
+            extrinsics_root: 123,
+            timestamp: 0,
+        })
+        .collect()
+}
+
+#[test]
+fn poa_round_robin_by_slot_no_missed_slots() {
+    let chain = slot_chain(&[0, 1, 2, 3, 4]);
+
+    assert_eq!(PoaRoundRobinBySlot::missed_slots(&chain), 0);
+    assert_eq!(PoaRoundRobinBySlot::missed_slots(&chain[..1]), 0);
+}
+
+#[test]
+fn poa_round_robin_by_slot_counts_skipped_slots() {
+    // Slots 2, 3 and 6 have no block
+    let chain = slot_chain(&[0, 1, 4, 5, 7]);
+
+    assert_eq!(PoaRoundRobinBySlot::missed_slots(&chain), 3);
+}
+
+#[test]
+fn fewest_missed_slots_rule_prefers_gapless_chain() {
+    let poa = PoaRoundRobinBySlot {
+        authorities: vec![ConsensusAuthority::Alice],
+        max_slot_skip: 3,
+    };
+    let gapless = slot_chain(&[0, 1, 2, 3]);
+    let gappy = slot_chain(&[0, 1, 3, 5, 7, 8]);
+
+    assert!(FewestMissedSlotsRule::first_chain_is_better(
+        &poa, &gapless, &gappy
+    ));
+    assert!(!FewestMissedSlotsRule::first_chain_is_better(
+        &poa, &gappy, &gapless
+    ));
+}
+
+#[test]
+fn fewest_missed_slots_rule_breaks_ties_by_length() {
+    let poa = PoaRoundRobinBySlot {
+        authorities: vec![ConsensusAuthority::Alice],
+        max_slot_skip: 3,
+    };
+    let short = slot_chain(&[0, 1, 2]);
+    let long = slot_chain(&[0, 1, 2, 3, 4]);
+
+    assert!(FewestMissedSlotsRule::first_chain_is_better(
+        &poa, &long, &short
+    ));
+    assert!(!FewestMissedSlotsRule::first_chain_is_better(
+        &poa, &short, &long
+    ));
+}
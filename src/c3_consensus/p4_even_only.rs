@@ -5,32 +5,68 @@
 use crate::hash;
 use std::marker::PhantomData;
 
-use super::{p1_pow::moderate_difficulty_pow, Consensus, Header};
+use super::{p1_pow::moderate_difficulty_pow, Consensus, ConsensusError, Header};
 
-/// A Consensus engine that requires the state root to be even for the header to be valid.
-/// Wraps an inner consensus engine whose rules will also be enforced.
-struct EvenOnly<Inner: Consensus> {
-    /// The inner consensus engine that will be used in addition to the even-only requirement.
+/// A Consensus engine that requires the state root to be divisible by `N` for the header to be valid.
+/// Wraps an inner consensus engine whose rules will also be enforced. The genesis block is exempt
+/// from the divisibility rule.
+///
+/// No state root is divisible by zero, so with `N = 0` every header except genesis is rejected.
+struct DivisibleBy<const N: u64, Inner: Consensus> {
+    /// The inner consensus engine that will be used in addition to the divisibility requirement.
     inner: Inner,
 }
 
-impl<Inner: Consensus> Consensus for EvenOnly<Inner> {
+/// A Consensus engine that requires the state root to be even for the header to be valid.
+type EvenOnly<Inner> = DivisibleBy<2, Inner>;
+
+impl<const N: u64, Inner: Consensus> DivisibleBy<N, Inner> {
+    fn root_allowed(state_root: u64) -> bool {
+        N != 0 && state_root.is_multiple_of(N)
+    }
+}
+
+impl<const N: u64, Inner: Consensus> Consensus for DivisibleBy<N, Inner> {
     type Digest = Inner::Digest;
 
     fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        self.validate_detailed(parent_digest, header).is_ok()
+    }
+
+    fn validate_detailed(
+        &self,
+        parent_digest: &Self::Digest,
+        header: &Header<Self::Digest>,
+    ) -> Result<(), ConsensusError> {
+        if Self::is_genesis(header) {
+            return Ok(());
+        }
+
         if !self.inner.validate(parent_digest, header) {
-            return false;
+            return Err(ConsensusError::InnerRejected);
         }
 
-        return header.state_root % 2 == 0;
+        if !Self::root_allowed(header.state_root) {
+            return Err(ConsensusError::RuleViolated);
+        }
+
+        Ok(())
     }
 
+    /// Seal the header with the inner engine as long as the state root is divisible by `N`.
+    ///
+    /// The genesis block is exempt from the divisibility rule, just as it is in `validate`.
+    /// Whether genesis gets sealed at all is left entirely to the inner engine.
     fn seal(
         &self,
         parent_digest: &Self::Digest,
         partial_header: Header<()>,
     ) -> Option<Header<Self::Digest>> {
-        if partial_header.state_root % 2 != 0 {
+        if partial_header.height == 0 {
+            return self.inner.seal(parent_digest, partial_header);
+        }
+
+        if !Self::root_allowed(partial_header.state_root) {
             return None;
         }
 
@@ -38,6 +74,110 @@ impl<Inner: Consensus> Consensus for EvenOnly<Inner> {
     }
 }
 
+/// A Consensus engine that requires each block's state root to be strictly greater than its parent's.
+/// Wraps an inner consensus engine whose rules will also be enforced. The genesis block has no parent
+/// and is therefore exempt.
+///
+/// Unlike `EvenOnly`, this rule cannot be checked from the parent digest alone, so rather than implementing
+/// `Consensus` directly the engine is driven with the complete parent header.
+struct MonotonicStateRoot<Inner: Consensus> {
+    /// The inner consensus engine that will be used in addition to the monotonic root requirement.
+    inner: Inner,
+}
+
+impl<Inner: Consensus> MonotonicStateRoot<Inner> {
+    /// Validate the header against its complete parent header.
+    fn validate_linked(
+        &self,
+        parent: &Header<Inner::Digest>,
+        header: &Header<Inner::Digest>,
+    ) -> Result<(), ConsensusError> {
+        if Inner::is_genesis(header) {
+            return Ok(());
+        }
+
+        if !self.inner.validate(&parent.consensus_digest, header) {
+            return Err(ConsensusError::InnerRejected);
+        }
+
+        if header.state_root <= parent.state_root {
+            return Err(ConsensusError::RuleViolated);
+        }
+
+        Ok(())
+    }
+
+    /// Seal the header with the inner engine as long as its state root exceeds the parent's.
+    /// Genesis sealing is left entirely to the inner engine.
+    fn seal_linked(
+        &self,
+        parent: &Header<Inner::Digest>,
+        partial_header: Header<()>,
+    ) -> Option<Header<Inner::Digest>> {
+        if partial_header.height != 0 && partial_header.state_root <= parent.state_root {
+            return None;
+        }
+
+        self.inner.seal(&parent.consensus_digest, partial_header)
+    }
+}
+
+/// A Consensus engine that bounds how far a block's timestamp may be ahead of its parent's. This bounds
+/// how stale a chain can get before the next block arrives. Wraps an inner consensus engine whose rules
+/// will also be enforced. The genesis block has no parent and is therefore exempt.
+///
+/// Like `MonotonicStateRoot`, this rule needs the complete parent header, so the engine is driven with
+/// the parent header rather than implementing `Consensus` directly.
+struct MaxGap<Inner: Consensus> {
+    /// The inner consensus engine that will be used in addition to the gap requirement.
+    inner: Inner,
+    /// The largest amount by which a block's timestamp may exceed its parent's.
+    max_gap: u64,
+}
+
+impl<Inner: Consensus> MaxGap<Inner> {
+    fn gap_allowed(&self, parent_timestamp: u64, timestamp: u64) -> bool {
+        timestamp.saturating_sub(parent_timestamp) <= self.max_gap
+    }
+
+    /// Validate the header against its complete parent header.
+    fn validate_linked(
+        &self,
+        parent: &Header<Inner::Digest>,
+        header: &Header<Inner::Digest>,
+    ) -> Result<(), ConsensusError> {
+        if Inner::is_genesis(header) {
+            return Ok(());
+        }
+
+        if !self.inner.validate(&parent.consensus_digest, header) {
+            return Err(ConsensusError::InnerRejected);
+        }
+
+        if !self.gap_allowed(parent.timestamp, header.timestamp) {
+            return Err(ConsensusError::RuleViolated);
+        }
+
+        Ok(())
+    }
+
+    /// Seal the header with the inner engine as long as its timestamp is within the gap of the
+    /// parent's. Genesis sealing is left entirely to the inner engine.
+    fn seal_linked(
+        &self,
+        parent: &Header<Inner::Digest>,
+        partial_header: Header<()>,
+    ) -> Option<Header<Inner::Digest>> {
+        if partial_header.height != 0
+            && !self.gap_allowed(parent.timestamp, partial_header.timestamp)
+        {
+            return None;
+        }
+
+        self.inner.seal(&parent.consensus_digest, partial_header)
+    }
+}
+
 /// Using the moderate difficulty PoW algorithm you created in section 1 of this chapter as the inner engine,
 /// create a PoW chain that is valid according to the inner consensus engine, but is not valid according to
 /// this engine because the state roots are not all even.
@@ -51,6 +191,7 @@ fn almost_valid_but_not_all_even() -> Vec<Header<u64>> {
         height: 123,
         state_root: 123,
         extrinsics_root: 123,
+        timestamp: 0,
         consensus_digest: (),
     };
 
@@ -62,6 +203,7 @@ fn almost_valid_but_not_all_even() -> Vec<Header<u64>> {
             height: headers.last().unwrap().height + 1,
             state_root: i,
             extrinsics_root: i,
+            timestamp: 0,
             consensus_digest: (),
         };
 
@@ -99,3 +241,289 @@ fn test_almost_valid_but_not_all_even() {
         assert!(is_valid_pow);
     }
 }
+
+#[test]
+fn even_only_genesis_not_checked() {
+    let even_only = EvenOnly {
+        inner: moderate_difficulty_pow(),
+    };
+    let genesis = Header {
+        parent: 0,
+        height: 0,
+        state_root: 1,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: 12345,
+    };
+
+    assert!(even_only.validate(&0, &genesis));
+}
+
+#[test]
+fn even_only_reports_rule_violation() {
+    let even_only = EvenOnly {
+        inner: super::p1_pow::trivial_always_valid_pow(),
+    };
+    let header = Header {
+        parent: 0,
+        height: 1,
+        state_root: 3,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: 0,
+    };
+
+    assert_eq!(
+        even_only.validate_detailed(&0, &header),
+        Err(ConsensusError::RuleViolated)
+    );
+    assert!(!even_only.validate(&0, &header));
+}
+
+#[test]
+fn even_only_reports_inner_rejection() {
+    let even_only = EvenOnly {
+        inner: moderate_difficulty_pow(),
+    };
+    let mut header = Header {
+        parent: 0,
+        height: 1,
+        state_root: 2,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: 0,
+    };
+    while even_only.inner.validate(&0, &header) {
+        header.consensus_digest += 1;
+    }
+
+    assert_eq!(
+        even_only.validate_detailed(&0, &header),
+        Err(ConsensusError::InnerRejected)
+    );
+}
+
+#[test]
+fn even_only_genesis_seal_defers_to_inner() {
+    let odd_genesis = || Header {
+        parent: 0,
+        height: 0,
+        state_root: 1,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: (),
+    };
+
+    // The inner PoA engine does not seal genesis at all
+    let even_poa = EvenOnly {
+        inner: super::p3_poa::PoaRoundRobinByHeight {
+            authorities: vec![super::ConsensusAuthority::Alice],
+        },
+    };
+    assert!(even_poa
+        .seal(&super::ConsensusAuthority::Alice, odd_genesis())
+        .is_none());
+
+    // The inner PoW engine does, and the odd root does not stop it
+    let even_pow = EvenOnly {
+        inner: moderate_difficulty_pow(),
+    };
+    let sealed = even_pow.seal(&0, odd_genesis()).unwrap();
+    assert_eq!(sealed.state_root, 1);
+    assert!(even_pow.validate(&0, &sealed));
+}
+
+#[cfg(test)]
+fn pow_chain_with_roots(roots: &[u64]) -> Vec<Header<u64>> {
+    let pow = super::p1_pow::trivial_always_valid_pow();
+    let mut chain = vec![Header {
+        parent: 0,
+        height: 0,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: 0,
+    }];
+    for (i, root) in roots.iter().enumerate() {
+        let parent = chain.last().unwrap();
+        let partial_header = Header {
+            parent: hash(parent),
+            height: i as u64 + 1,
+            state_root: *root,
+            extrinsics_root: 0,
+            timestamp: 0,
+            consensus_digest: (),
+        };
+        chain.push(pow.seal(&parent.consensus_digest, partial_header).unwrap());
+    }
+    chain
+}
+
+#[test]
+fn monotonic_root_accepts_increasing_chain() {
+    let monotonic = MonotonicStateRoot {
+        inner: super::p1_pow::trivial_always_valid_pow(),
+    };
+    let chain = pow_chain_with_roots(&[1, 5, 6, 20]);
+
+    for pair in chain.windows(2) {
+        assert_eq!(monotonic.validate_linked(&pair[0], &pair[1]), Ok(()));
+    }
+}
+
+#[test]
+fn monotonic_root_rejects_dip() {
+    let monotonic = MonotonicStateRoot {
+        inner: super::p1_pow::trivial_always_valid_pow(),
+    };
+    let chain = pow_chain_with_roots(&[1, 5, 4, 20]);
+
+    assert_eq!(
+        monotonic.validate_linked(&chain[2], &chain[3]),
+        Err(ConsensusError::RuleViolated)
+    );
+    // An equal root is not strictly greater either
+    let flat = pow_chain_with_roots(&[3, 3]);
+    assert!(monotonic.validate_linked(&flat[1], &flat[2]).is_err());
+}
+
+#[test]
+fn monotonic_root_genesis_and_sealing() {
+    let monotonic = MonotonicStateRoot {
+        inner: moderate_difficulty_pow(),
+    };
+    let chain = pow_chain_with_roots(&[7]);
+
+    // Genesis has no parent constraint
+    assert_eq!(monotonic.validate_linked(&chain[0], &chain[0]), Ok(()));
+
+    let partial = |state_root| Header {
+        parent: hash(&chain[1]),
+        height: 2,
+        state_root,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: (),
+    };
+    assert!(monotonic.seal_linked(&chain[1], partial(7)).is_none());
+
+    let sealed = monotonic.seal_linked(&chain[1], partial(8)).unwrap();
+    assert_eq!(monotonic.validate_linked(&chain[1], &sealed), Ok(()));
+}
+
+#[cfg(test)]
+fn pow_chain_with_timestamps(timestamps: &[u64]) -> Vec<Header<u64>> {
+    let pow = super::p1_pow::trivial_always_valid_pow();
+    let mut chain = pow_chain_with_roots(&[]);
+    for (i, timestamp) in timestamps.iter().enumerate() {
+        let parent = chain.last().unwrap();
+        let partial_header = Header {
+            parent: hash(parent),
+            height: i as u64 + 1,
+            state_root: 0,
+            extrinsics_root: 0,
+            timestamp: *timestamp,
+            consensus_digest: (),
+        };
+        chain.push(pow.seal(&parent.consensus_digest, partial_header).unwrap());
+    }
+    chain
+}
+
+#[test]
+fn max_gap_accepts_block_within_gap() {
+    let max_gap = MaxGap {
+        inner: super::p1_pow::trivial_always_valid_pow(),
+        max_gap: 10,
+    };
+    let chain = pow_chain_with_timestamps(&[5, 15, 16]);
+
+    for pair in chain.windows(2) {
+        assert_eq!(max_gap.validate_linked(&pair[0], &pair[1]), Ok(()));
+    }
+}
+
+#[test]
+fn max_gap_rejects_block_too_far_ahead() {
+    let max_gap = MaxGap {
+        inner: super::p1_pow::trivial_always_valid_pow(),
+        max_gap: 10,
+    };
+    let chain = pow_chain_with_timestamps(&[5, 16]);
+
+    assert_eq!(
+        max_gap.validate_linked(&chain[1], &chain[2]),
+        Err(ConsensusError::RuleViolated)
+    );
+
+    let partial = |timestamp| Header {
+        parent: hash(&chain[1]),
+        height: 2,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp,
+        consensus_digest: (),
+    };
+    assert!(max_gap.seal_linked(&chain[1], partial(16)).is_none());
+
+    let sealed = max_gap.seal_linked(&chain[1], partial(15)).unwrap();
+    assert_eq!(max_gap.validate_linked(&chain[1], &sealed), Ok(()));
+}
+
+#[test]
+fn divisible_by_three() {
+    let engine: DivisibleBy<3, _> = DivisibleBy {
+        inner: moderate_difficulty_pow(),
+    };
+    let partial = |state_root| Header {
+        parent: 0,
+        height: 1,
+        state_root,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: (),
+    };
+
+    for root in [3, 6, 9] {
+        let header = engine.seal(&0, partial(root)).unwrap();
+        assert!(engine.validate(&0, &header));
+    }
+    for root in [1, 2, 4] {
+        assert!(engine.seal(&0, partial(root)).is_none());
+    }
+
+    // A mined header with a root that is not a multiple of three
+    let header = engine.inner.seal(&0, partial(4)).unwrap();
+    assert_eq!(
+        engine.validate_detailed(&0, &header),
+        Err(ConsensusError::RuleViolated)
+    );
+
+    // A multiple of three does not excuse missing work
+    let mut header = engine.seal(&0, partial(6)).unwrap();
+    while engine.inner.validate(&0, &header) {
+        header.consensus_digest += 1;
+    }
+    assert_eq!(
+        engine.validate_detailed(&0, &header),
+        Err(ConsensusError::InnerRejected)
+    );
+}
+
+#[test]
+fn divisible_by_zero_rejects_everything_but_genesis() {
+    let engine: DivisibleBy<0, _> = DivisibleBy {
+        inner: super::p1_pow::trivial_always_valid_pow(),
+    };
+    let header = |height| Header {
+        parent: 0,
+        height,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: 0,
+    };
+
+    assert!(engine.validate(&0, &header(0)));
+    assert!(!engine.validate(&0, &header(1)));
+}
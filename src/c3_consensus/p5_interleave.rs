@@ -8,7 +8,15 @@
 /// Odd blocks are PoW
 /// Even blocks are PoA
 ///
-use super::{p1_pow::Pow, p3_poa::SimplePoa, Consensus, ConsensusAuthority, Header};
+use super::{
+    p1_pow::Pow, p3_poa::SimplePoa, with_digest, Consensus, ConsensusAuthority, ConsensusError,
+    Header,
+};
+use crate::hash;
+
+#[cfg(test)]
+use super::partial_header;
+
 struct AlternatingPowPoa {
     pow: Pow,
     poa: SimplePoa,
@@ -27,6 +35,16 @@ impl AlternatingPowPoa {
     pub fn new(pow: Pow, poa: SimplePoa) -> Self {
         AlternatingPowPoa { pow, poa }
     }
+
+    /// The kind of consensus that applies at the given height. Even heights are PoA and odd
+    /// heights are PoW.
+    fn expected_kind(height: u64) -> ConsensusKind {
+        if height.is_multiple_of(2) {
+            ConsensusKind::Poa
+        } else {
+            ConsensusKind::Pow
+        }
+    }
 }
 
 impl From<u64> for PowOrPoaDigest {
@@ -67,74 +85,445 @@ impl Consensus for AlternatingPowPoa {
     type Digest = PowOrPoaDigest;
 
     fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
-        if header.height % 2 == 0 {
-            // PoA
+        self.validate_detailed(parent_digest, header).is_ok()
+    }
+
+    fn validate_detailed(
+        &self,
+        _parent_digest: &Self::Digest,
+        header: &Header<Self::Digest>,
+    ) -> Result<(), ConsensusError> {
+        if Self::is_genesis(header) {
+            return Ok(());
+        }
+
+        let wrong_digest = ConsensusError::WrongDigestForHeight {
+            height: header.height,
+        };
+
+        let inner_valid = if Self::expected_kind(header.height) == ConsensusKind::Poa {
             let consensus_digest_result: Result<ConsensusAuthority, _> =
                 header.consensus_digest.try_into();
 
             if consensus_digest_result.is_err() {
-                return false;
+                return Err(wrong_digest);
             }
 
-            let poa_header = Header {
-                parent: header.parent,
-                height: header.height,
-                state_root: header.state_root,
-                extrinsics_root: header.extrinsics_root,
-                consensus_digest: consensus_digest_result.unwrap(),
-            };
+            let poa_header = with_digest(header, consensus_digest_result.unwrap());
 
             self.poa.validate(&ConsensusAuthority::Alice, &poa_header) // parent digest is not used in SimplePoA
         } else {
-            // PoW
             let consensus_digest_result: Result<u64, _> = header.consensus_digest.try_into();
 
             if consensus_digest_result.is_err() {
-                return false;
+                return Err(wrong_digest);
             }
 
-            let pow_header: Header<u64> = Header {
-                parent: header.parent,
-                height: header.height,
-                state_root: header.state_root,
-                extrinsics_root: header.extrinsics_root,
-                consensus_digest: consensus_digest_result.unwrap(),
-            };
+            let pow_header: Header<u64> = with_digest(header, consensus_digest_result.unwrap());
             self.pow.validate(&0, &pow_header) // parent digest is not used in PoW
+        };
+
+        if inner_valid {
+            Ok(())
+        } else {
+            Err(ConsensusError::InnerRejected)
         }
     }
 
     fn seal(
         &self,
-        parent_digest: &Self::Digest,
+        _parent_digest: &Self::Digest,
         partial_header: Header<()>,
     ) -> Option<Header<Self::Digest>> {
-        if partial_header.height % 2 == 0 {
-            // PoA
-
-            let sealed_header = self
-                .poa
-                .seal(&ConsensusAuthority::Alice, partial_header)
-                .unwrap();
-
-            Some(Header {
-                parent: sealed_header.parent,
-                height: sealed_header.height,
-                state_root: sealed_header.state_root,
-                extrinsics_root: sealed_header.extrinsics_root,
-                consensus_digest: PowOrPoaDigest::Poa(sealed_header.consensus_digest),
-            })
+        match Self::expected_kind(partial_header.height) {
+            ConsensusKind::Poa => {
+                let sealed_header = self.poa.seal(&ConsensusAuthority::Alice, partial_header)?;
+
+                Some(with_digest(
+                    &sealed_header,
+                    PowOrPoaDigest::Poa(sealed_header.consensus_digest),
+                ))
+            }
+            ConsensusKind::Pow => {
+                let sealed_header = self.pow.seal(&0, partial_header)?;
+
+                Some(with_digest(
+                    &sealed_header,
+                    PowOrPoaDigest::Pow(sealed_header.consensus_digest),
+                ))
+            }
+        }
+    }
+}
+
+/// The kinds of sub-engine that a `PatternedConsensus` can cycle through.
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy)]
+enum ConsensusKind {
+    Pow,
+    Poa,
+}
+
+/// A generalization of the alternating engine. Rather than hard-coding odd and even heights,
+/// it cycles through an arbitrary repeating pattern of sub-engines. For example, the pattern
+/// PoW, PoW, PoA means two PoW blocks are followed by one PoA block, over and over.
+///
+/// The kind of a block is `pattern[height % pattern.len()]`.
+struct PatternedConsensus {
+    pattern: Vec<ConsensusKind>,
+    pow: Pow,
+    poa: SimplePoa,
+}
+
+impl PatternedConsensus {
+    /// The kind of consensus that applies at the given height, if the pattern is not empty.
+    fn kind_at(&self, height: u64) -> Option<ConsensusKind> {
+        if self.pattern.is_empty() {
+            return None;
+        }
+        Some(self.pattern[(height % self.pattern.len() as u64) as usize])
+    }
+}
+
+impl Consensus for PatternedConsensus {
+    type Digest = PowOrPoaDigest;
+
+    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        self.validate_detailed(parent_digest, header).is_ok()
+    }
+
+    fn validate_detailed(
+        &self,
+        _: &Self::Digest,
+        header: &Header<Self::Digest>,
+    ) -> Result<(), ConsensusError> {
+        if Self::is_genesis(header) {
+            return Ok(());
+        }
+
+        let inner_valid = match (self.kind_at(header.height), header.consensus_digest) {
+            (Some(ConsensusKind::Pow), PowOrPoaDigest::Pow(nonce)) => {
+                let pow_header = with_digest(header, nonce);
+                self.pow.validate(&0, &pow_header) // parent digest is not used in PoW
+            }
+            (Some(ConsensusKind::Poa), PowOrPoaDigest::Poa(authority)) => {
+                let poa_header = with_digest(header, authority);
+                self.poa.validate(&ConsensusAuthority::Alice, &poa_header) // parent digest is not used in SimplePoA
+            }
+            // With an empty pattern there is no kind of digest this engine understands
+            (None, _) => return Err(ConsensusError::WrongDigestType),
+            // The digest is the wrong kind for this height
+            _ => {
+                return Err(ConsensusError::WrongDigestForHeight {
+                    height: header.height,
+                })
+            }
+        };
+
+        if inner_valid {
+            Ok(())
         } else {
-            // PoW
-            let sealed_header = self.pow.seal(&0, partial_header).unwrap();
-
-            Some(Header {
-                parent: sealed_header.parent,
-                height: sealed_header.height,
-                state_root: sealed_header.state_root,
-                extrinsics_root: sealed_header.extrinsics_root,
-                consensus_digest: PowOrPoaDigest::Pow(sealed_header.consensus_digest),
-            })
+            Err(ConsensusError::InnerRejected)
+        }
+    }
+
+    fn seal(&self, _: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
+        match self.kind_at(partial_header.height)? {
+            ConsensusKind::Pow => {
+                let sealed_header = self.pow.seal(&0, partial_header)?;
+                Some(with_digest(
+                    &sealed_header,
+                    PowOrPoaDigest::Pow(sealed_header.consensus_digest),
+                ))
+            }
+            ConsensusKind::Poa => {
+                let sealed_header = self.poa.seal(&ConsensusAuthority::Alice, partial_header)?;
+                Some(with_digest(
+                    &sealed_header,
+                    PowOrPoaDigest::Poa(sealed_header.consensus_digest),
+                ))
+            }
+        }
+    }
+}
+
+/// Rather than alternating, this engine requires every block to carry BOTH a valid authority
+/// signature and enough proof of work. This is similar to merged mining with a permissioned check.
+struct CombinedPowPoa {
+    pow: Pow,
+    poa: SimplePoa,
+}
+
+/// The digest for the combined engine contains both the work proof and the signature.
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy)]
+struct PowAndPoaDigest {
+    nonce: u64,
+    signature: ConsensusAuthority,
+}
+
+impl Consensus for CombinedPowPoa {
+    type Digest = PowAndPoaDigest;
+
+    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        if Self::is_genesis(header) {
+            return true;
+        }
+
+        let poa_header = with_digest(header, header.consensus_digest.signature);
+
+        // The work covers the entire header, including the signature
+        self.poa.validate(&parent_digest.signature, &poa_header)
+            && hash(header) < self.pow.threshold
+    }
+
+    /// Sign the header first, and then mine it so that the work also covers the signature.
+    fn seal(
+        &self,
+        parent_digest: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>> {
+        let signed_header = self.poa.seal(&parent_digest.signature, partial_header)?;
+
+        let mut header = with_digest(
+            &signed_header,
+            PowAndPoaDigest {
+                nonce: 0,
+                signature: signed_header.consensus_digest,
+            },
+        );
+
+        for nonce in 0.. {
+            header.consensus_digest.nonce = nonce;
+            if hash(&header) < self.pow.threshold {
+                return Some(header);
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn alternating_genesis_not_checked() {
+    let engine = AlternatingPowPoa::new(
+        super::p1_pow::moderate_difficulty_pow(),
+        SimplePoa {
+            authorities: vec![ConsensusAuthority::Alice],
+        },
+    );
+    // Genesis is an even height, but carries a PoW digest
+    let genesis = Header {
+        parent: 0,
+        height: 0,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: PowOrPoaDigest::Pow(12345),
+    };
+
+    assert!(engine.validate(&PowOrPoaDigest::Pow(0), &genesis));
+}
+
+#[test]
+fn combined_pow_poa_seal_and_validate() {
+    let engine = CombinedPowPoa {
+        pow: super::p1_pow::moderate_difficulty_pow(),
+        poa: SimplePoa {
+            authorities: vec![ConsensusAuthority::Alice],
+        },
+    };
+    let parent_digest = PowAndPoaDigest {
+        nonce: 0,
+        signature: ConsensusAuthority::Alice,
+    };
+
+    let header = engine.seal(&parent_digest, partial_header(1)).unwrap();
+
+    assert_eq!(header.consensus_digest.signature, ConsensusAuthority::Alice);
+    assert!(engine.validate(&parent_digest, &header));
+}
+
+#[test]
+fn combined_pow_poa_fails_only_pow() {
+    let engine = CombinedPowPoa {
+        pow: super::p1_pow::moderate_difficulty_pow(),
+        poa: SimplePoa {
+            authorities: vec![ConsensusAuthority::Alice],
+        },
+    };
+    let parent_digest = PowAndPoaDigest {
+        nonce: 0,
+        signature: ConsensusAuthority::Alice,
+    };
+
+    // Correctly signed, but with a nonce that does not meet the threshold
+    let mut header = engine.seal(&parent_digest, partial_header(1)).unwrap();
+    while hash(&header) < engine.pow.threshold {
+        header.consensus_digest.nonce += 1;
+    }
+
+    assert!(!engine.validate(&parent_digest, &header));
+}
+
+#[test]
+fn combined_pow_poa_fails_only_signature() {
+    let engine = CombinedPowPoa {
+        pow: super::p1_pow::trivial_always_valid_pow(),
+        poa: SimplePoa {
+            authorities: vec![ConsensusAuthority::Alice],
+        },
+    };
+    let parent_digest = PowAndPoaDigest {
+        nonce: 0,
+        signature: ConsensusAuthority::Alice,
+    };
+
+    // Enough work (any nonce is enough), but signed by a non-authority
+    let mut header = engine.seal(&parent_digest, partial_header(1)).unwrap();
+    header.consensus_digest.signature = ConsensusAuthority::Bob;
+
+    assert!(!engine.validate(&parent_digest, &header));
+}
+
+#[test]
+fn patterned_consensus_pow_pow_poa() {
+    let engine = PatternedConsensus {
+        pattern: vec![ConsensusKind::Pow, ConsensusKind::Pow, ConsensusKind::Poa],
+        pow: super::p1_pow::moderate_difficulty_pow(),
+        poa: SimplePoa {
+            authorities: vec![ConsensusAuthority::Alice],
+        },
+    };
+    let parent_digest = PowOrPoaDigest::Pow(0);
+
+    for height in 1..=6 {
+        let header = engine.seal(&parent_digest, partial_header(height)).unwrap();
+        assert!(engine.validate(&parent_digest, &header));
+
+        match height % 3 {
+            2 => assert_eq!(
+                header.consensus_digest,
+                PowOrPoaDigest::Poa(ConsensusAuthority::Alice)
+            ),
+            _ => assert!(matches!(header.consensus_digest, PowOrPoaDigest::Pow(_))),
         }
     }
 }
+
+#[test]
+fn patterned_consensus_rejects_wrong_kind() {
+    let engine = PatternedConsensus {
+        pattern: vec![ConsensusKind::Pow, ConsensusKind::Pow, ConsensusKind::Poa],
+        pow: super::p1_pow::trivial_always_valid_pow(),
+        poa: SimplePoa {
+            authorities: vec![ConsensusAuthority::Alice],
+        },
+    };
+    let parent_digest = PowOrPoaDigest::Pow(0);
+
+    // Height 1 must be PoW, and height 2 must be PoA
+    let mut pow_header = engine.seal(&parent_digest, partial_header(1)).unwrap();
+    pow_header.consensus_digest = PowOrPoaDigest::Poa(ConsensusAuthority::Alice);
+    let mut poa_header = engine.seal(&parent_digest, partial_header(2)).unwrap();
+    poa_header.consensus_digest = PowOrPoaDigest::Pow(0);
+
+    assert!(!engine.validate(&parent_digest, &pow_header));
+    assert!(!engine.validate(&parent_digest, &poa_header));
+    assert_eq!(
+        engine.validate_detailed(&parent_digest, &pow_header),
+        Err(ConsensusError::WrongDigestForHeight { height: 1 })
+    );
+    assert_eq!(
+        engine.validate_detailed(&parent_digest, &poa_header),
+        Err(ConsensusError::WrongDigestForHeight { height: 2 })
+    );
+}
+
+#[test]
+fn patterned_consensus_with_empty_pattern_reports_wrong_digest_type() {
+    let engine = PatternedConsensus {
+        pattern: vec![],
+        pow: super::p1_pow::trivial_always_valid_pow(),
+        poa: SimplePoa {
+            authorities: vec![ConsensusAuthority::Alice],
+        },
+    };
+    let parent_digest = PowOrPoaDigest::Pow(0);
+    let header = with_digest(&partial_header(1), PowOrPoaDigest::Pow(0));
+
+    assert!(engine.seal(&parent_digest, partial_header(1)).is_none());
+    assert_eq!(
+        engine.validate_detailed(&parent_digest, &header),
+        Err(ConsensusError::WrongDigestType)
+    );
+}
+
+#[test]
+fn alternating_reports_wrong_digest_type() {
+    let engine = AlternatingPowPoa::new(
+        super::p1_pow::trivial_always_valid_pow(),
+        SimplePoa {
+            authorities: vec![ConsensusAuthority::Alice],
+        },
+    );
+    let parent_digest = PowOrPoaDigest::Pow(0);
+
+    // Height 1 must be PoW
+    let mut header = engine.seal(&parent_digest, partial_header(1)).unwrap();
+    header.consensus_digest = PowOrPoaDigest::Poa(ConsensusAuthority::Alice);
+    assert_eq!(
+        engine.validate_detailed(&parent_digest, &header),
+        Err(ConsensusError::WrongDigestForHeight { height: 1 })
+    );
+
+    // Height 2 must be PoA by one of the authorities
+    let mut header = engine.seal(&parent_digest, partial_header(2)).unwrap();
+    header.consensus_digest = PowOrPoaDigest::Poa(ConsensusAuthority::Bob);
+    assert_eq!(
+        engine.validate_detailed(&parent_digest, &header),
+        Err(ConsensusError::InnerRejected)
+    );
+}
+
+#[test]
+fn alternating_expected_kind() {
+    assert_eq!(AlternatingPowPoa::expected_kind(1), ConsensusKind::Pow);
+    assert_eq!(AlternatingPowPoa::expected_kind(2), ConsensusKind::Poa);
+}
+
+#[test]
+fn alternating_reports_pow_digest_at_even_height() {
+    let engine = AlternatingPowPoa::new(
+        super::p1_pow::trivial_always_valid_pow(),
+        SimplePoa {
+            authorities: vec![ConsensusAuthority::Alice],
+        },
+    );
+    let parent_digest = PowOrPoaDigest::Pow(0);
+
+    let mut header = engine.seal(&parent_digest, partial_header(4)).unwrap();
+    header.consensus_digest = PowOrPoaDigest::Pow(0);
+
+    assert!(!engine.validate(&parent_digest, &header));
+    assert_eq!(
+        engine.validate_detailed(&parent_digest, &header),
+        Err(ConsensusError::WrongDigestForHeight { height: 4 })
+    );
+}
+
+#[test]
+fn alternating_seal_without_authorities_is_none() {
+    let engine = AlternatingPowPoa::new(
+        super::p1_pow::trivial_always_valid_pow(),
+        SimplePoa {
+            authorities: vec![],
+        },
+    );
+
+    assert_eq!(
+        engine.seal(&PowOrPoaDigest::Pow(0), partial_header(2)),
+        None
+    );
+    // PoW heights do not depend on the authorities
+    assert!(engine
+        .seal(&PowOrPoaDigest::Pow(0), partial_header(1))
+        .is_some());
+}
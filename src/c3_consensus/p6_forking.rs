@@ -8,7 +8,10 @@
 
 use std::marker::PhantomData;
 
-use super::{Consensus, ConsensusAuthority, Header};
+use super::{with_digest, Consensus, ConsensusAuthority, Header};
+
+#[cfg(test)]
+use super::partial_header;
 
 /// A Higher-order consensus engine that represents a change from one set of consensus rules (Before) to
 /// another set (After) at a specific block height
@@ -84,3 +87,164 @@ fn pow_to_poa(
 ) -> impl Consensus {
     todo!("Exercise 6")
 }
+
+/// The digest of a `HeightSwitch` engine. Headers below the fork height carry the digest of the
+/// engine before the fork, and headers at or above it carry the digest of the engine after it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SwitchDigest<BeforeDigest, AfterDigest> {
+    Before(BeforeDigest),
+    After(AfterDigest),
+}
+
+/// A hard fork from one consensus engine to an entirely different one, like Ethereum's merge from
+/// PoW to PoA. Unlike `Forked`, the two engines do not need to share a digest type. Instead the
+/// combined `SwitchDigest` records which engine sealed each header.
+pub struct HeightSwitch<Before: Consensus, After: Consensus> {
+    /// The engine for headers below the fork height
+    pub before: Before,
+    /// The engine for headers at or above the fork height
+    pub after: After,
+    /// The first block height at which the after engine applies
+    pub fork_height: u64,
+    /// The digest the after engine uses as the parent of the first header after the fork. The
+    /// real parent was sealed by the before engine, so its digest means nothing to the after engine.
+    pub fork_parent_digest: After::Digest,
+}
+
+impl<Before: Consensus, After: Consensus> HeightSwitch<Before, After> {
+    /// The parent digest to hand to the after engine, if the given parent digest makes sense there.
+    fn after_parent_digest(
+        &self,
+        parent_digest: &SwitchDigest<Before::Digest, After::Digest>,
+        height: u64,
+    ) -> Option<After::Digest> {
+        match parent_digest {
+            SwitchDigest::After(digest) => Some(digest.clone()),
+            SwitchDigest::Before(_) if height == self.fork_height => {
+                Some(self.fork_parent_digest.clone())
+            }
+            SwitchDigest::Before(_) => None,
+        }
+    }
+}
+
+impl<Before: Consensus, After: Consensus> Consensus for HeightSwitch<Before, After> {
+    type Digest = SwitchDigest<Before::Digest, After::Digest>;
+
+    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        if Self::is_genesis(header) {
+            return true;
+        }
+
+        if header.height < self.fork_height {
+            match (parent_digest, &header.consensus_digest) {
+                (SwitchDigest::Before(parent), SwitchDigest::Before(digest)) => self
+                    .before
+                    .validate(parent, &with_digest(header, digest.clone())),
+                _ => false,
+            }
+        } else {
+            let (Some(parent), SwitchDigest::After(digest)) = (
+                self.after_parent_digest(parent_digest, header.height),
+                &header.consensus_digest,
+            ) else {
+                return false;
+            };
+            self.after
+                .validate(&parent, &with_digest(header, digest.clone()))
+        }
+    }
+
+    fn seal(
+        &self,
+        parent_digest: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>> {
+        if partial_header.height < self.fork_height {
+            let SwitchDigest::Before(parent) = parent_digest else {
+                return None;
+            };
+            let sealed_header = self.before.seal(parent, partial_header)?;
+            let digest = SwitchDigest::Before(sealed_header.consensus_digest.clone());
+            Some(with_digest(&sealed_header, digest))
+        } else {
+            let parent = self.after_parent_digest(parent_digest, partial_header.height)?;
+            let sealed_header = self.after.seal(&parent, partial_header)?;
+            let digest = SwitchDigest::After(sealed_header.consensus_digest.clone());
+            Some(with_digest(&sealed_header, digest))
+        }
+    }
+}
+
+#[cfg(test)]
+fn pow_to_alice(fork_height: u64) -> HeightSwitch<super::p1_pow::Pow, super::p3_poa::SimplePoa> {
+    HeightSwitch {
+        before: super::p1_pow::moderate_difficulty_pow(),
+        after: super::p3_poa::SimplePoa {
+            authorities: vec![ConsensusAuthority::Alice],
+        },
+        fork_height,
+        fork_parent_digest: ConsensusAuthority::Alice,
+    }
+}
+
+#[test]
+fn height_switch_seals_and_validates_across_fork() {
+    let engine = pow_to_alice(3);
+
+    let mut parent_digest = SwitchDigest::Before(0);
+    for height in 1..=5 {
+        let header = engine.seal(&parent_digest, partial_header(height)).unwrap();
+        assert!(engine.validate(&parent_digest, &header));
+
+        if height < 3 {
+            assert!(matches!(header.consensus_digest, SwitchDigest::Before(_)));
+        } else {
+            assert_eq!(
+                header.consensus_digest,
+                SwitchDigest::After(ConsensusAuthority::Alice)
+            );
+        }
+        parent_digest = header.consensus_digest;
+    }
+}
+
+#[test]
+fn height_switch_rejects_wrong_engine_for_height() {
+    let engine = pow_to_alice(3);
+
+    // Just before the fork, a signature is not enough
+    let mut header = engine
+        .seal(&SwitchDigest::Before(0), partial_header(2))
+        .unwrap();
+    header.consensus_digest = SwitchDigest::After(ConsensusAuthority::Alice);
+    assert!(!engine.validate(&SwitchDigest::Before(0), &header));
+
+    // At the fork, proof of work is no longer accepted
+    let mut header = engine
+        .seal(&SwitchDigest::Before(0), partial_header(3))
+        .unwrap();
+    let pow_header = engine.before.seal(&0, partial_header(3)).unwrap();
+    header.consensus_digest = SwitchDigest::Before(pow_header.consensus_digest);
+    assert!(!engine.validate(&SwitchDigest::Before(0), &header));
+
+    // And after the fork, only the authorities may sign
+    let mut header = engine
+        .seal(
+            &SwitchDigest::After(ConsensusAuthority::Alice),
+            partial_header(4),
+        )
+        .unwrap();
+    header.consensus_digest = SwitchDigest::After(ConsensusAuthority::Bob);
+    assert!(!engine.validate(&SwitchDigest::After(ConsensusAuthority::Alice), &header));
+}
+
+#[test]
+fn height_switch_rejects_pre_fork_parent_after_fork() {
+    let engine = pow_to_alice(3);
+
+    // Only the first header after the fork may have a parent sealed by the old engine
+    assert!(engine
+        .seal(&SwitchDigest::Before(0), partial_header(4))
+        .is_none());
+}
@@ -0,0 +1,230 @@
+//! Consensus engines decide which blocks are valid, and finality decides which blocks will never be
+//! reverted. Here we couple the two by requiring every block to reference a finalized block, and
+//! never an older one than its parent referenced. Block production therefore can not lose sight of
+//! finality, while blocks that were valid when they were authored stay valid as finality moves on.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::{with_digest, Consensus, Header};
+use crate::hash;
+
+#[cfg(test)]
+use super::partial_header;
+
+type Hash = u64;
+
+/// A shared handle to the finalized block hashes, in the order they were finalized. Clones of the
+/// handle all observe the same blocks, so a client can keep finalizing blocks while a consensus
+/// engine holds a copy.
+#[derive(Clone, Debug)]
+pub struct Finality {
+    finalized: Rc<RefCell<Vec<Hash>>>,
+}
+
+impl Finality {
+    /// Create a new handle starting with the given finalized block, typically genesis.
+    pub fn new(finalized: Hash) -> Self {
+        Finality {
+            finalized: Rc::new(RefCell::new(vec![finalized])),
+        }
+    }
+
+    /// Note that a new block has been finalized.
+    pub fn finalize(&self, finalized: Hash) {
+        self.finalized.borrow_mut().push(finalized);
+    }
+
+    /// The hash of the most recent finalized block.
+    pub fn latest_finalized(&self) -> Hash {
+        *self
+            .finalized
+            .borrow()
+            .last()
+            .expect("finality starts with a finalized block")
+    }
+
+    /// How many blocks were finalized before the given one, or `None` if it is not finalized.
+    pub fn position(&self, block: Hash) -> Option<usize> {
+        self.finalized.borrow().iter().position(|b| *b == block)
+    }
+}
+
+/// The digest for `RequiresFinalityRef`. It contains the inner engine's digest as well as the
+/// hash of the finalized block that the author saw when creating the block.
+#[derive(Hash, Debug, PartialEq, Eq, Clone)]
+pub struct FinalityRefDigest<D> {
+    finalized_ref: Hash,
+    inner: D,
+}
+
+/// A higher-order consensus engine that requires each block to reference a finalized block at
+/// least as recent as the one its parent referenced, in addition to the inner engine's rules.
+/// Authors reference the most recent finalized block they know of.
+///
+/// The finalized reference sits alongside the inner digest, so on its own it would not be covered
+/// by the inner seal, and anybody relaying the block could swap it. To prevent that, the header the
+/// inner engine seals has an extrinsics root that also commits to the reference.
+pub struct RequiresFinalityRef<Inner: Consensus> {
+    inner: Inner,
+    finality: Finality,
+}
+
+/// The header that the inner engine seals on behalf of `RequiresFinalityRef`. It is the given
+/// header with the inner digest, and an extrinsics root that also commits to the finalized reference.
+fn inner_header<D, E>(header: &Header<D>, finalized_ref: Hash, inner: E) -> Header<E> {
+    Header {
+        extrinsics_root: hash(&(header.extrinsics_root, finalized_ref)),
+        ..with_digest(header, inner)
+    }
+}
+
+impl<Inner: Consensus> Consensus for RequiresFinalityRef<Inner> {
+    type Digest = FinalityRefDigest<Inner::Digest>;
+
+    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        if Self::is_genesis(header) {
+            return true;
+        }
+
+        // Unknown references, and references older than the parent's, are rejected
+        let finalized_ref = header.consensus_digest.finalized_ref;
+        match (
+            self.finality.position(parent_digest.finalized_ref),
+            self.finality.position(finalized_ref),
+        ) {
+            (Some(parent_position), Some(position)) if position >= parent_position => {}
+            _ => return false,
+        }
+
+        let inner_header =
+            inner_header(header, finalized_ref, header.consensus_digest.inner.clone());
+        self.inner.validate(&parent_digest.inner, &inner_header)
+    }
+
+    fn seal(
+        &self,
+        parent_digest: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>> {
+        let finalized_ref = self.finality.latest_finalized();
+        let sealed_header = self.inner.seal(
+            &parent_digest.inner,
+            inner_header(&partial_header, finalized_ref, ()),
+        )?;
+
+        // The block itself keeps the real extrinsics root
+        Some(Header {
+            extrinsics_root: partial_header.extrinsics_root,
+            ..with_digest(
+                &sealed_header,
+                FinalityRefDigest {
+                    finalized_ref,
+                    inner: sealed_header.consensus_digest.clone(),
+                },
+            )
+        })
+    }
+}
+
+#[test]
+fn finality_ref_to_latest_finalized_is_valid() {
+    let finality = Finality::new(42);
+    let engine = RequiresFinalityRef {
+        inner: super::p1_pow::moderate_difficulty_pow(),
+        finality: finality.clone(),
+    };
+    let parent_digest = FinalityRefDigest {
+        finalized_ref: 42,
+        inner: 0,
+    };
+
+    let header = engine.seal(&parent_digest, partial_header(1)).unwrap();
+
+    assert_eq!(header.consensus_digest.finalized_ref, 42);
+    assert!(engine.validate(&parent_digest, &header));
+}
+
+#[cfg(test)]
+fn finality_ref_engine(finality: &Finality) -> RequiresFinalityRef<super::p1_pow::Pow> {
+    RequiresFinalityRef {
+        inner: super::p1_pow::moderate_difficulty_pow(),
+        finality: finality.clone(),
+    }
+}
+
+#[test]
+fn finality_ref_older_than_parent_is_invalid() {
+    let finality = Finality::new(42);
+    let engine = finality_ref_engine(&finality);
+    let header = engine
+        .seal(
+            &FinalityRefDigest {
+                finalized_ref: 42,
+                inner: 0,
+            },
+            partial_header(2),
+        )
+        .unwrap();
+
+    // Finality moves on, and the parent already references the newer block
+    finality.finalize(43);
+    let parent_digest = FinalityRefDigest {
+        finalized_ref: 43,
+        inner: 0,
+    };
+
+    assert!(!engine.validate(&parent_digest, &header));
+}
+
+#[test]
+fn finality_ref_to_unknown_hash_is_invalid() {
+    let finality = Finality::new(42);
+    let engine = finality_ref_engine(&finality);
+    let parent_digest = FinalityRefDigest {
+        finalized_ref: 42,
+        inner: 0,
+    };
+    let mut header = engine.seal(&parent_digest, partial_header(1)).unwrap();
+    header.consensus_digest.finalized_ref = 7;
+
+    assert!(!engine.validate(&parent_digest, &header));
+}
+
+#[test]
+fn finality_ref_old_blocks_stay_valid_as_finality_moves_on() {
+    let finality = Finality::new(42);
+    let engine = finality_ref_engine(&finality);
+    let genesis_digest = FinalityRefDigest {
+        finalized_ref: 42,
+        inner: 0,
+    };
+    let header = engine.seal(&genesis_digest, partial_header(1)).unwrap();
+
+    finality.finalize(43);
+
+    // The old block can still be re-validated, and new blocks reference the newer finalized block
+    assert!(engine.validate(&genesis_digest, &header));
+    let child = engine
+        .seal(&header.consensus_digest, partial_header(2))
+        .unwrap();
+    assert_eq!(child.consensus_digest.finalized_ref, 43);
+    assert!(engine.validate(&header.consensus_digest, &child));
+}
+
+#[test]
+fn finality_ref_is_covered_by_the_inner_seal() {
+    let finality = Finality::new(42);
+    let engine = finality_ref_engine(&finality);
+    finality.finalize(43);
+    let parent_digest = FinalityRefDigest {
+        finalized_ref: 42,
+        inner: 0,
+    };
+    let mut header = engine.seal(&parent_digest, partial_header(1)).unwrap();
+    assert_eq!(header.extrinsics_root, partial_header(1).extrinsics_root);
+
+    // Both references are acceptable on their own, but swapping them invalidates the work
+    header.consensus_digest.finalized_ref = 42;
+    assert!(!engine.validate(&parent_digest, &header));
+}
@@ -0,0 +1,186 @@
+//! Rather than writing a new higher-order engine for every combination of rules, we can build
+//! general combinators. Here we combine two engines that share a digest type so that a header must
+//! satisfy both of them, or at least one of them. `EvenOnly` from earlier in the chapter is then just
+//! the conjunction of an inner engine with an engine that checks the state root.
+
+use super::{Consensus, ConsensusError, Header};
+
+#[cfg(test)]
+use super::{partial_header, with_digest};
+
+/// A Consensus engine that accepts a header only when both inner engines accept it.
+pub struct AndConsensus<A, B> {
+    /// The first engine. It is also responsible for sealing.
+    pub first: A,
+    /// The second engine.
+    pub second: B,
+}
+
+impl<D, A, B> Consensus for AndConsensus<A, B>
+where
+    D: Clone + core::fmt::Debug + Eq + PartialEq + std::hash::Hash,
+    A: Consensus<Digest = D>,
+    B: Consensus<Digest = D>,
+{
+    type Digest = D;
+
+    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        self.validate_detailed(parent_digest, header).is_ok()
+    }
+
+    fn validate_detailed(
+        &self,
+        parent_digest: &Self::Digest,
+        header: &Header<Self::Digest>,
+    ) -> Result<(), ConsensusError> {
+        if self.first.validate(parent_digest, header) && self.second.validate(parent_digest, header)
+        {
+            Ok(())
+        } else {
+            Err(ConsensusError::InnerRejected)
+        }
+    }
+
+    /// Seal with the first engine. The sealed header is only returned if the second engine
+    /// accepts it too, because otherwise it would not be valid according to this engine.
+    fn seal(
+        &self,
+        parent_digest: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>> {
+        self.first
+            .seal(parent_digest, partial_header)
+            .filter(|header| self.second.validate(parent_digest, header))
+    }
+}
+
+/// A Consensus engine that accepts a header when at least one of the inner engines accepts it.
+pub struct OrConsensus<A, B> {
+    /// The first engine. It is tried first when sealing.
+    pub first: A,
+    /// The second engine.
+    pub second: B,
+}
+
+impl<D, A, B> Consensus for OrConsensus<A, B>
+where
+    D: Clone + core::fmt::Debug + Eq + PartialEq + std::hash::Hash,
+    A: Consensus<Digest = D>,
+    B: Consensus<Digest = D>,
+{
+    type Digest = D;
+
+    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        self.validate_detailed(parent_digest, header).is_ok()
+    }
+
+    fn validate_detailed(
+        &self,
+        parent_digest: &Self::Digest,
+        header: &Header<Self::Digest>,
+    ) -> Result<(), ConsensusError> {
+        if self.first.validate(parent_digest, header) || self.second.validate(parent_digest, header)
+        {
+            Ok(())
+        } else {
+            Err(ConsensusError::InnerRejected)
+        }
+    }
+
+    /// Seal with the first engine, falling back to the second if the first cannot seal.
+    fn seal(
+        &self,
+        parent_digest: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>> {
+        self.first
+            .seal(parent_digest, partial_header.clone())
+            .or_else(|| self.second.seal(parent_digest, partial_header))
+    }
+}
+
+/// A toy engine with a `u64` digest that only cares about the parity of the state root.
+/// Combined with PoW through `AndConsensus`, this behaves like `EvenOnly<Pow>`.
+#[cfg(test)]
+struct EvenRoot;
+
+#[cfg(test)]
+impl Consensus for EvenRoot {
+    type Digest = u64;
+
+    fn validate(&self, _: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        Self::is_genesis(header) || header.state_root.is_multiple_of(2)
+    }
+
+    fn seal(&self, _: &Self::Digest, partial_header: Header<()>) -> Option<Header<Self::Digest>> {
+        if partial_header.height != 0 && !partial_header.state_root.is_multiple_of(2) {
+            return None;
+        }
+
+        Some(with_digest(&partial_header, 0))
+    }
+}
+
+#[cfg(test)]
+fn partial_header_with_root(state_root: u64) -> Header<()> {
+    Header {
+        state_root,
+        ..partial_header(1)
+    }
+}
+
+#[test]
+fn and_pow_even_root_requires_both() {
+    let engine = AndConsensus {
+        first: super::p1_pow::moderate_difficulty_pow(),
+        second: EvenRoot,
+    };
+
+    let sealed = engine.seal(&0, partial_header_with_root(2)).unwrap();
+    assert!(engine.validate(&0, &sealed));
+
+    // Valid work but an odd state root
+    let pow = super::p1_pow::moderate_difficulty_pow();
+    let odd = pow.seal(&0, partial_header_with_root(3)).unwrap();
+    assert!(pow.validate(&0, &odd));
+    assert_eq!(
+        engine.validate_detailed(&0, &odd),
+        Err(ConsensusError::InnerRejected)
+    );
+    assert!(engine.seal(&0, partial_header_with_root(3)).is_none());
+
+    // Even state root but no valid work
+    let mut lazy = sealed.clone();
+    while pow.validate(&0, &lazy) {
+        lazy.consensus_digest += 1;
+    }
+    assert!(!engine.validate(&0, &lazy));
+}
+
+#[test]
+fn or_pow_even_root_requires_either() {
+    let engine = OrConsensus {
+        first: EvenRoot,
+        second: super::p1_pow::moderate_difficulty_pow(),
+    };
+    let pow = super::p1_pow::moderate_difficulty_pow();
+
+    // The parity engine cannot seal an odd root, so sealing falls back to PoW
+    let odd = engine.seal(&0, partial_header_with_root(3)).unwrap();
+    assert!(pow.validate(&0, &odd));
+    assert!(engine.validate(&0, &odd));
+
+    // An even root needs no work at all
+    let mut even = engine.seal(&0, partial_header_with_root(4)).unwrap();
+    while pow.validate(&0, &even) {
+        even.consensus_digest += 1;
+    }
+    assert!(engine.validate(&0, &even));
+
+    // Odd root without work satisfies neither
+    let mut neither = odd;
+    while pow.validate(&0, &neither) {
+        neither.consensus_digest += 1;
+    }
+    assert!(!engine.validate(&0, &neither));
+}
@@ -0,0 +1,293 @@
+//! So far we have validated chains that were handed to us complete and in order. A real client
+//! receives headers one at a time from the network, sometimes out of order, and sometimes on
+//! competing forks. Here we build a small import pipeline that validates each header with a
+//! consensus engine, keeps track of every fork, and follows the best one according to a fork
+//! choice rule.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use super::{chain_weight, Consensus, Header};
+use crate::hash;
+
+type Hash = u64;
+
+/// A fork choice rule over the headers of a consensus engine.
+///
+/// This mirrors the fork choice rules from the previous chapter, but works with the generic headers
+/// of this chapter. The engine is passed along so that rules can ask it how much work a header represents.
+pub trait HeaderForkChoice<C: Consensus> {
+    /// Compare two chains, and return whether the first one is better.
+    fn first_chain_is_better(
+        engine: &C,
+        chain_1: &[Header<C::Digest>],
+        chain_2: &[Header<C::Digest>],
+    ) -> bool;
+}
+
+/// The "best" chain is simply the longest chain.
+pub struct LongestHeaderChain;
+
+impl<C: Consensus> HeaderForkChoice<C> for LongestHeaderChain {
+    fn first_chain_is_better(
+        _: &C,
+        chain_1: &[Header<C::Digest>],
+        chain_2: &[Header<C::Digest>],
+    ) -> bool {
+        chain_1.len() > chain_2.len()
+    }
+}
+
+/// The "best" chain is the one with the most total weight according to the consensus engine.
+pub struct HeaviestHeaderChain;
+
+impl<C: Consensus> HeaderForkChoice<C> for HeaviestHeaderChain {
+    fn first_chain_is_better(
+        engine: &C,
+        chain_1: &[Header<C::Digest>],
+        chain_2: &[Header<C::Digest>],
+    ) -> bool {
+        chain_weight(engine, chain_1) > chain_weight(engine, chain_2)
+    }
+}
+
+/// What happened when a header was handed to the importer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportResult {
+    /// The header was valid and is now part of the known block tree.
+    Imported,
+    /// The header's parent is not known yet. It has been buffered and will be imported
+    /// as soon as the parent arrives.
+    Orphaned,
+    /// The header was already known.
+    AlreadyKnown,
+    /// The header does not extend its parent validly.
+    Invalid,
+}
+
+/// Imports headers into a tree rooted at genesis and tracks the best chain through it.
+pub struct ChainImporter<C: Consensus, F: HeaderForkChoice<C>> {
+    engine: C,
+    /// Every header connected to genesis, by hash.
+    known: HashMap<Hash, Header<C::Digest>>,
+    /// Headers whose parent has not arrived yet, grouped by the missing parent's hash.
+    orphans: HashMap<Hash, Vec<Header<C::Digest>>>,
+    /// The hash of the tip of the best chain.
+    best_tip: Hash,
+    fork_choice: PhantomData<F>,
+}
+
+impl<C: Consensus, F: HeaderForkChoice<C>> ChainImporter<C, F> {
+    /// Create a new importer that knows only the given genesis header.
+    pub fn new(engine: C, genesis: Header<C::Digest>) -> Self {
+        let best_tip = hash(&genesis);
+        ChainImporter {
+            engine,
+            known: HashMap::from([(best_tip, genesis)]),
+            orphans: HashMap::new(),
+            best_tip,
+            fork_choice: PhantomData,
+        }
+    }
+
+    /// The tip of the current best chain.
+    pub fn best_tip(&self) -> &Header<C::Digest> {
+        &self.known[&self.best_tip]
+    }
+
+    /// The number of headers waiting for their parent to arrive.
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.values().map(Vec::len).sum()
+    }
+
+    /// Import a single header.
+    ///
+    /// If the header's parent is known, the header is validated and added to the tree, and any
+    /// orphans that were waiting on it are imported in turn. Otherwise the header is buffered.
+    /// Orphans waiting on a header that turns out to be invalid can never connect, so they are
+    /// dropped along with their own orphans.
+    pub fn import(&mut self, header: Header<C::Digest>) -> ImportResult {
+        let header_hash = hash(&header);
+        if self.known.contains_key(&header_hash) {
+            return ImportResult::AlreadyKnown;
+        }
+
+        if !self.known.contains_key(&header.parent) {
+            self.orphans.entry(header.parent).or_default().push(header);
+            return ImportResult::Orphaned;
+        }
+
+        let result = self.import_connected(header);
+
+        // Connect any orphans that were waiting on a header we just imported, and evict the ones
+        // that were waiting on a header we rejected
+        let mut ready = vec![(header_hash, result == ImportResult::Imported)];
+        while let Some((parent_hash, parent_imported)) = ready.pop() {
+            for orphan in self.orphans.remove(&parent_hash).unwrap_or_default() {
+                let orphan_hash = hash(&orphan);
+                let imported =
+                    parent_imported && self.import_connected(orphan) == ImportResult::Imported;
+                ready.push((orphan_hash, imported));
+            }
+        }
+
+        result
+    }
+
+    /// Import a header whose parent is already known.
+    fn import_connected(&mut self, header: Header<C::Digest>) -> ImportResult {
+        let Some(parent) = self.known.get(&header.parent) else {
+            return ImportResult::Invalid;
+        };
+        if header.height != parent.height + 1
+            || !self.engine.validate(&parent.consensus_digest, &header)
+        {
+            return ImportResult::Invalid;
+        }
+
+        let header_hash = hash(&header);
+        self.known.insert(header_hash, header);
+
+        let candidate = self.chain_to(header_hash);
+        let best = self.chain_to(self.best_tip);
+        if F::first_chain_is_better(&self.engine, &candidate, &best) {
+            self.best_tip = header_hash;
+        }
+
+        ImportResult::Imported
+    }
+
+    /// The chain of known headers from genesis up to and including the given header.
+    fn chain_to(&self, tip: Hash) -> Vec<Header<C::Digest>> {
+        let mut chain = Vec::new();
+        let mut current = self.known.get(&tip);
+        while let Some(header) = current {
+            chain.push(header.clone());
+            if header.height == 0 {
+                break;
+            }
+            current = self.known.get(&header.parent);
+        }
+        chain.reverse();
+        chain
+    }
+}
+
+#[cfg(test)]
+fn pow_chain(
+    pow: &super::p1_pow::Pow,
+    parent: &Header<u64>,
+    n: u64,
+    fork_seed: u64,
+) -> Vec<Header<u64>> {
+    let mut chain = vec![parent.clone()];
+    for _ in 0..n {
+        let parent = chain.last().unwrap();
+        let partial_header = Header {
+            parent: hash(parent),
+            height: parent.height + 1,
+            state_root: fork_seed,
+            extrinsics_root: 0,
+            timestamp: 0,
+            consensus_digest: (),
+        };
+        chain.push(pow.seal(&parent.consensus_digest, partial_header).unwrap());
+    }
+    chain.split_off(1)
+}
+
+#[cfg(test)]
+fn genesis() -> Header<u64> {
+    Header {
+        parent: 0,
+        height: 0,
+        state_root: 0,
+        extrinsics_root: 0,
+        timestamp: 0,
+        consensus_digest: 0,
+    }
+}
+
+#[test]
+fn import_in_order() {
+    let pow = super::p1_pow::moderate_difficulty_pow();
+    let chain = pow_chain(&pow, &genesis(), 3, 0);
+    let mut importer: ChainImporter<_, LongestHeaderChain> = ChainImporter::new(pow, genesis());
+
+    for header in chain.iter() {
+        assert_eq!(importer.import(header.clone()), ImportResult::Imported);
+    }
+
+    assert_eq!(importer.best_tip(), &chain[2]);
+    assert_eq!(
+        importer.import(chain[1].clone()),
+        ImportResult::AlreadyKnown
+    );
+}
+
+#[test]
+fn import_out_of_order_connects_orphans() {
+    let pow = super::p1_pow::moderate_difficulty_pow();
+    let chain = pow_chain(&pow, &genesis(), 4, 0);
+    let mut importer: ChainImporter<_, LongestHeaderChain> = ChainImporter::new(pow, genesis());
+
+    assert_eq!(importer.import(chain[3].clone()), ImportResult::Orphaned);
+    assert_eq!(importer.import(chain[1].clone()), ImportResult::Orphaned);
+    assert_eq!(importer.import(chain[2].clone()), ImportResult::Orphaned);
+    assert_eq!(importer.orphan_count(), 3);
+    assert_eq!(importer.best_tip(), &genesis());
+
+    // Filling the gap connects every buffered header
+    assert_eq!(importer.import(chain[0].clone()), ImportResult::Imported);
+    assert_eq!(importer.orphan_count(), 0);
+    assert_eq!(importer.best_tip(), &chain[3]);
+}
+
+#[test]
+fn import_switches_to_better_fork() {
+    let pow = super::p1_pow::moderate_difficulty_pow();
+    let short_fork = pow_chain(&pow, &genesis(), 2, 1);
+    let long_fork = pow_chain(&pow, &genesis(), 3, 2);
+    let mut importer: ChainImporter<_, LongestHeaderChain> = ChainImporter::new(pow, genesis());
+
+    for header in short_fork.iter().chain(long_fork[..2].iter()) {
+        importer.import(header.clone());
+    }
+    // Ties keep the chain we saw first
+    assert_eq!(importer.best_tip(), &short_fork[1]);
+
+    importer.import(long_fork[2].clone());
+    assert_eq!(importer.best_tip(), &long_fork[2]);
+}
+
+#[test]
+fn import_rejects_invalid_header() {
+    let pow = super::p1_pow::moderate_difficulty_pow();
+    let chain = pow_chain(&pow, &genesis(), 1, 0);
+    let mut importer: ChainImporter<_, HeaviestHeaderChain> = ChainImporter::new(pow, genesis());
+
+    let mut bad = chain[0].clone();
+    while importer.engine.validate(&0, &bad) {
+        bad.consensus_digest += 1;
+    }
+
+    assert_eq!(importer.import(bad), ImportResult::Invalid);
+    assert_eq!(importer.best_tip(), &genesis());
+}
+
+#[test]
+fn import_evicts_orphan_of_invalid_parent() {
+    let pow = super::p1_pow::moderate_difficulty_pow();
+    let chain = pow_chain(&pow, &genesis(), 1, 0);
+    let mut bad = chain[0].clone();
+    while pow.validate(&0, &bad) {
+        bad.consensus_digest += 1;
+    }
+    let child = pow_chain(&pow, &bad, 1, 0);
+    let mut importer: ChainImporter<_, LongestHeaderChain> = ChainImporter::new(pow, genesis());
+
+    assert_eq!(importer.import(child[0].clone()), ImportResult::Orphaned);
+    assert_eq!(importer.import(bad), ImportResult::Invalid);
+    assert_eq!(importer.orphan_count(), 0);
+    assert_eq!(importer.best_tip(), &genesis());
+}
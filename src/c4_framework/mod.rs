@@ -10,8 +10,15 @@
 ///
 /// Let's refactor our blockchain to take advantage of these two abstractions
 /// In doing so, we create a blockchain framework
+#[cfg(feature = "typed-body")]
+use crate::c1_state_machine::p5_digital_cash::{CashTransaction, DigitalCashSystem};
 use crate::c1_state_machine::StateMachine;
+#[cfg(feature = "typed-body")]
+use crate::c1_state_machine::User;
+#[cfg(feature = "typed-body")]
+use crate::c3_consensus::ConsensusAuthority;
 use crate::c3_consensus::{Consensus, Header};
+use crate::hash;
 type Hash = u64;
 
 impl<Digest> Header<Digest> {
@@ -36,6 +43,14 @@ impl<Digest> Header<Digest> {
     }
 }
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "C::Digest: serde::Serialize, SM::Transition: serde::Serialize",
+        deserialize = "C::Digest: serde::Deserialize<'de>, SM::Transition: serde::Deserialize<'de>"
+    ))
+)]
 struct Block<C: Consensus, SM: StateMachine> {
     header: Header<C::Digest>,
     body: Vec<SM::Transition>,
@@ -67,7 +82,492 @@ fn create_empty_chain<C: Consensus, SM: StateMachine>(
     todo!("Exercise 8")
 }
 
+/// Calculate the extrinsics root that commits to the given block body.
+fn extrinsics_root<T: std::hash::Hash>(extrinsics: &[T]) -> Hash {
+    hash(&extrinsics)
+}
+
+/// Package a selection of cash transactions, for example from a mempool, into a block body.
+///
+/// Only the body and the extrinsics root are filled in. The block author is still responsible
+/// for linking the header to its parent, committing the state root, and sealing it.
+#[cfg(feature = "typed-body")]
+impl<C> From<Vec<CashTransaction>> for Block<C, DigitalCashSystem>
+where
+    C: Consensus,
+    C::Digest: Default,
+{
+    fn from(body: Vec<CashTransaction>) -> Self {
+        Block {
+            header: Header {
+                parent: 0,
+                height: 0,
+                state_root: 0,
+                extrinsics_root: extrinsics_root(&body),
+                timestamp: 0,
+                consensus_digest: C::Digest::default(),
+            },
+            body,
+        }
+    }
+}
+
+/// Extract the transactions back out of a block body.
+#[cfg(feature = "typed-body")]
+impl<C: Consensus> From<Block<C, DigitalCashSystem>> for Vec<CashTransaction> {
+    fn from(block: Block<C, DigitalCashSystem>) -> Self {
+        block.body
+    }
+}
+
+/// Execute a block body by applying each of its transitions in order, starting from the pre-state.
+///
+/// This is the link between the two halves of the crate: the blockchain orders the transitions,
+/// and the state machine gives them meaning.
+fn execute_block<SM: StateMachine>(pre_state: &SM::State, body: &[SM::Transition]) -> SM::State
+where
+    SM::State: Clone,
+{
+    SM::apply_all(pre_state, body)
+}
+
+/// Execute the block's body on top of the pre-state and check that the resulting state hashes
+/// to the state root declared in the block's header.
+///
+/// Returns the post-state if it matches, or `None` if the header commits to a different state.
+fn execute_and_check_block<C: Consensus, SM: StateMachine>(
+    pre_state: &SM::State,
+    block: &Block<C, SM>,
+) -> Option<SM::State>
+where
+    SM::State: Clone + std::hash::Hash,
+{
+    let post_state = execute_block::<SM>(pre_state, &block.body);
+    (hash(&post_state) == block.header.state_root).then_some(post_state)
+}
+
+/// The amount of new currency minted to the author of every block.
+#[cfg(feature = "typed-body")]
+const BLOCK_SUBSIDY: u64 = 50;
+
+/// In identity-based consensus, the block author is the authority that signed the block.
+/// Each authority collects its rewards as the user of the same name.
+#[cfg(feature = "typed-body")]
+impl From<ConsensusAuthority> for User {
+    fn from(authority: ConsensusAuthority) -> Self {
+        match authority {
+            ConsensusAuthority::Alice => User::Alice,
+            ConsensusAuthority::Bob => User::Bob,
+            ConsensusAuthority::Charlie => User::Charlie,
+        }
+    }
+}
+
+/// Build the coinbase transaction that rewards the block author with the subsidy plus the fees
+/// collected from the rest of the block. By convention it is the first transaction in the body.
+#[cfg(feature = "typed-body")]
+fn coinbase(author: User, fees: u64) -> CashTransaction {
+    CashTransaction::Mint {
+        minter: author,
+        amount: BLOCK_SUBSIDY.saturating_add(fees),
+    }
+}
+
+/// Blocks of the digital cash system link consensus to the currency by rewarding their author.
+///
+/// The author is not part of the header. In PoA it is the signing authority, which converts into a
+/// `User`. In PoW anybody can mine, so the miner nominates the `User` it wants to be paid as.
+#[cfg(feature = "typed-body")]
+impl<C: Consensus> Block<C, DigitalCashSystem> {
+    /// The fees collected by this block. Any value spent by a transfer but not received by anybody
+    /// is no longer destroyed, but paid to the block author instead.
+    ///
+    /// Fees are claimed before the transfers are executed, so a body of invalid transfers can claim
+    /// more than fits. Returns `None` in that case.
+    fn fees(&self) -> Option<u64> {
+        self.body
+            .iter()
+            .skip(1)
+            .try_fold(0u64, |total, tx| total.checked_add(tx.fee()))
+    }
+
+    /// Check that the block begins with a coinbase paying the given author exactly the subsidy
+    /// plus the collected fees. Blocks whose fees overflow are rejected.
+    fn validate_coinbase(&self, author: User) -> bool {
+        match self.fees() {
+            Some(fees) if BLOCK_SUBSIDY.checked_add(fees).is_some() => {
+                self.body.first() == Some(&coinbase(author, fees))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Replay the given blocks starting from the genesis state and check that the state root
+/// declared in each header matches the hash of the state after executing that block's body.
+///
+/// This is the state-validity half of what a full node does when verifying a chain. The
+/// consensus half is handled by the consensus engine.
+///
+/// Returns the final state if every block checks out, or the index of the first block whose
+/// state root does not match.
+fn verify_state_roots<C: Consensus, SM: StateMachine>(
+    genesis_state: &SM::State,
+    blocks: &[Block<C, SM>],
+) -> Result<SM::State, usize>
+where
+    SM::State: Clone + std::hash::Hash,
+{
+    let mut state = genesis_state.clone();
+
+    for (i, block) in blocks.iter().enumerate() {
+        state = execute_and_check_block(&state, block).ok_or(i)?;
+    }
+
+    Ok(state)
+}
+
+/// A higher-order consensus engine that limits how many extrinsics a block may contain.
+///
+/// A header alone does not reveal the size of the body, so at the header level this engine simply
+/// defers to the inner engine. The size limit is enforced by `validate_block`, which sees the
+/// complete block.
+struct BoundedBlockSize<Inner: Consensus> {
+    /// The inner consensus engine whose rules will also be enforced.
+    inner: Inner,
+    /// The maximum number of extrinsics allowed in a block body.
+    max_extrinsics: usize,
+}
+
+impl<Inner: Consensus> BoundedBlockSize<Inner> {
+    /// Validate a complete block. The header must be valid according to the inner engine, and the
+    /// body must not contain more than `max_extrinsics` extrinsics.
+    fn validate_block<SM: StateMachine>(
+        &self,
+        parent_digest: &Inner::Digest,
+        block: &Block<Self, SM>,
+    ) -> bool {
+        block.body.len() <= self.max_extrinsics && self.validate(parent_digest, &block.header)
+    }
+}
+
+impl<Inner: Consensus> Consensus for BoundedBlockSize<Inner> {
+    type Digest = Inner::Digest;
+
+    fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
+        self.inner.validate(parent_digest, header)
+    }
+
+    fn seal(
+        &self,
+        parent_digest: &Self::Digest,
+        partial_header: Header<()>,
+    ) -> Option<Header<Self::Digest>> {
+        self.inner.seal(parent_digest, partial_header)
+    }
+}
+
 //TODO tests
 
 //TODO maybe this shouldn't be a whole chapter. Maybe it is the first
 // section in the chapter on building a client
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::c1_state_machine::p5_digital_cash::{
+        Bill, CashTransaction, DigitalCashSystem, State,
+    };
+    use crate::c1_state_machine::User;
+
+    /// Build a chain of blocks that each mint some cash, with honestly calculated state roots.
+    fn build_cash_chain(n: u64) -> Vec<Block<(), DigitalCashSystem>> {
+        let mut state = State::new();
+        let mut blocks = vec![Block {
+            header: Header {
+                parent: 0,
+                height: 0,
+                state_root: hash(&state),
+                extrinsics_root: 0,
+                timestamp: 0,
+                consensus_digest: (),
+            },
+            body: vec![],
+        }];
+
+        for height in 1..=n {
+            let body = vec![CashTransaction::Mint {
+                minter: User::Alice,
+                amount: height * 10,
+            }];
+            for transition in body.iter() {
+                state = DigitalCashSystem::next_state(&state, transition);
+            }
+            blocks.push(Block {
+                header: Header {
+                    parent: hash(&blocks.last().unwrap().header),
+                    height,
+                    state_root: hash(&state),
+                    extrinsics_root: 0,
+                    timestamp: 0,
+                    consensus_digest: (),
+                },
+                body,
+            });
+        }
+
+        blocks
+    }
+
+    /// A block on top of genesis that mints the given number of times.
+    fn block_with_mints(n: u64) -> Block<BoundedBlockSize<()>, DigitalCashSystem> {
+        let body: Vec<CashTransaction> = (1..=n)
+            .map(|amount| CashTransaction::Mint {
+                minter: User::Alice,
+                amount,
+            })
+            .collect();
+
+        Block {
+            header: Header {
+                parent: 0,
+                height: 1,
+                state_root: 0,
+                extrinsics_root: extrinsics_root(&body),
+                timestamp: 0,
+                consensus_digest: (),
+            },
+            body,
+        }
+    }
+
+    #[test]
+    fn bounded_block_size_accepts_body_at_limit() {
+        let engine = BoundedBlockSize {
+            inner: (),
+            max_extrinsics: 3,
+        };
+
+        assert!(engine.validate_block(&(), &block_with_mints(0)));
+        assert!(engine.validate_block(&(), &block_with_mints(3)));
+    }
+
+    #[test]
+    fn bounded_block_size_rejects_body_over_limit() {
+        let engine = BoundedBlockSize {
+            inner: (),
+            max_extrinsics: 3,
+        };
+        let block = block_with_mints(4);
+
+        assert!(!engine.validate_block(&(), &block));
+        // The header alone is still fine
+        assert!(engine.validate(&(), &block.header));
+    }
+
+    #[test]
+    fn verify_state_roots_valid_cash_chain() {
+        let blocks = build_cash_chain(4);
+
+        let final_state = verify_state_roots(&State::new(), &blocks).unwrap();
+        assert_eq!(hash(&final_state), blocks[4].header.state_root);
+    }
+
+    #[test]
+    fn verify_state_roots_tampered_middle_block() {
+        let mut blocks = build_cash_chain(4);
+        blocks[2].header.state_root = 42;
+
+        assert_eq!(verify_state_roots(&State::new(), &blocks), Err(2));
+    }
+
+    #[test]
+    fn execute_block_of_cash_transfers() {
+        let pre_state = State::from([Bill::new(User::Alice, 50, 0)]);
+        let body = vec![
+            CashTransaction::Transfer {
+                spends: vec![Bill::new(User::Alice, 50, 0)],
+                receives: vec![Bill::new(User::Bob, 30, 1), Bill::new(User::Alice, 20, 2)],
+            },
+            CashTransaction::Transfer {
+                spends: vec![Bill::new(User::Bob, 30, 1)],
+                receives: vec![Bill::new(User::Charlie, 30, 3)],
+            },
+        ];
+
+        let post_state = execute_block::<DigitalCashSystem>(&pre_state, &body);
+        let mut expected = State::from([
+            Bill::new(User::Alice, 20, 2),
+            Bill::new(User::Charlie, 30, 3),
+        ]);
+        expected.set_serial(4);
+        assert_eq!(post_state, expected);
+
+        let mut block: Block<(), DigitalCashSystem> = Block {
+            header: Header {
+                parent: 0,
+                height: 1,
+                state_root: hash(&expected),
+                extrinsics_root: extrinsics_root(&body),
+                timestamp: 0,
+                consensus_digest: (),
+            },
+            body,
+        };
+        assert_eq!(execute_and_check_block(&pre_state, &block), Some(expected));
+
+        block.header.state_root = hash(&pre_state);
+        assert_eq!(execute_and_check_block(&pre_state, &block), None);
+    }
+
+    #[cfg(feature = "typed-body")]
+    #[test]
+    fn coinbase_rewards_poa_author() {
+        use crate::c3_consensus::ConsensusAuthority;
+
+        let author: User = ConsensusAuthority::Bob.into();
+        let pre_state = State::from([Bill::new(User::Alice, 50, 0)]);
+        let body = vec![
+            coinbase(author, 5),
+            CashTransaction::Transfer {
+                spends: vec![Bill::new(User::Alice, 50, 0)],
+                receives: vec![Bill::new(User::Charlie, 45, 1)],
+            },
+        ];
+        let block: Block<(), DigitalCashSystem> = body.into();
+
+        assert!(block.validate_coinbase(User::Bob));
+        assert!(!block.validate_coinbase(User::Alice));
+
+        let post_state = execute_block::<DigitalCashSystem>(&pre_state, &block.body);
+        assert_eq!(
+            post_state.balance(User::Bob),
+            pre_state.balance(User::Bob) + BLOCK_SUBSIDY + 5
+        );
+    }
+
+    #[cfg(feature = "typed-body")]
+    #[test]
+    fn coinbase_with_overflowing_fees_is_rejected() {
+        let miner = User::Dave;
+        let fake_transfer = |serial| CashTransaction::Transfer {
+            spends: vec![Bill::new(User::Alice, u64::MAX, serial)],
+            receives: vec![],
+        };
+        let block: Block<(), DigitalCashSystem> = vec![
+            coinbase(miner, u64::MAX),
+            fake_transfer(0),
+            fake_transfer(1),
+        ]
+        .into();
+
+        assert_eq!(block.fees(), None);
+        assert!(!block.validate_coinbase(miner));
+    }
+
+    #[cfg(feature = "typed-body")]
+    #[test]
+    fn coinbase_must_match_subsidy() {
+        let miner = User::Dave;
+        let greedy: Block<(), DigitalCashSystem> = vec![coinbase(miner, 1)].into();
+        assert!(!greedy.validate_coinbase(miner));
+
+        let honest: Block<(), DigitalCashSystem> = vec![coinbase(miner, 0)].into();
+        assert!(honest.validate_coinbase(miner));
+
+        let post_state = execute_block::<DigitalCashSystem>(&State::new(), &honest.body);
+        assert_eq!(post_state.balance(miner), BLOCK_SUBSIDY);
+
+        let missing: Block<(), DigitalCashSystem> = Vec::new().into();
+        assert!(!missing.validate_coinbase(miner));
+    }
+
+    #[cfg(feature = "typed-body")]
+    #[test]
+    fn transactions_into_block_body() {
+        let txs = vec![
+            CashTransaction::Mint {
+                minter: User::Alice,
+                amount: 10,
+            },
+            CashTransaction::Mint {
+                minter: User::Bob,
+                amount: 20,
+            },
+            CashTransaction::Mint {
+                minter: User::Charlie,
+                amount: 30,
+            },
+        ];
+
+        let block: Block<(), DigitalCashSystem> = txs.clone().into();
+        assert_eq!(block.header.extrinsics_root, hash(&txs));
+        assert_eq!(block.body, txs);
+
+        let extracted: Vec<CashTransaction> = block.into();
+        assert_eq!(extracted, txs);
+    }
+
+    #[cfg(feature = "typed-body")]
+    #[test]
+    fn empty_transactions_into_block_body() {
+        let block: Block<(), DigitalCashSystem> = Vec::new().into();
+
+        assert!(block.body.is_empty());
+        assert_eq!(
+            block.header.extrinsics_root,
+            hash(&Vec::<CashTransaction>::new())
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn pow_header_json_round_trip() {
+        let header: Header<u64> = Header {
+            parent: 1,
+            height: 2,
+            state_root: 3,
+            extrinsics_root: 4,
+            timestamp: 0,
+            consensus_digest: 12345,
+        };
+
+        let json = serde_json::to_string(&header).unwrap();
+        assert_eq!(serde_json::from_str::<Header<u64>>(&json).unwrap(), header);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn poa_header_json_round_trip() {
+        use crate::c3_consensus::ConsensusAuthority;
+
+        let header = Header {
+            parent: 1,
+            height: 2,
+            state_root: 3,
+            extrinsics_root: 4,
+            timestamp: 0,
+            consensus_digest: ConsensusAuthority::Bob,
+        };
+
+        let json = serde_json::to_string(&header).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Header<ConsensusAuthority>>(&json).unwrap(),
+            header
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn cash_block_json_round_trip() {
+        let blocks = build_cash_chain(2);
+
+        let json = serde_json::to_string(&blocks).unwrap();
+        let decoded: Vec<Block<(), DigitalCashSystem>> = serde_json::from_str(&json).unwrap();
+        for (decoded, block) in decoded.iter().zip(blocks.iter()) {
+            assert_eq!(decoded.header, block.header);
+            assert_eq!(decoded.body, block.body);
+        }
+        assert_eq!(decoded.len(), blocks.len());
+    }
+}
@@ -1,14 +1,47 @@
 use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use std::hash::{Hash, Hasher as _};
 
 mod c1_state_machine;
 mod c2_blockchain;
 mod c3_consensus;
 mod c4_framework;
 
+/// A hash function that can be plugged into the parts of the crate that hash headers, such as
+/// PoW and the fork choice rules. Tests can swap in a deterministic or adversarial hasher to
+/// exercise cases that would otherwise take a lot of searching to produce.
+pub trait Hasher {
+    fn hash<T: Hash + ?Sized>(&self, t: &T) -> u64;
+}
+
+/// The hasher used throughout the crate, backed by the standard library's `DefaultHasher`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DefaultHash;
+
+impl Hasher for DefaultHash {
+    fn hash<T: Hash + ?Sized>(&self, t: &T) -> u64 {
+        let mut s = DefaultHasher::new();
+        t.hash(&mut s);
+        s.finish()
+    }
+}
+
 // Simple helper to do some hashing.
 fn hash<T: Hash>(t: &T) -> u64 {
-    let mut s = DefaultHasher::new();
-    t.hash(&mut s);
-    s.finish()
+    DefaultHash.hash(t)
+}
+
+/// A 256-bit hash. A u64 collides far too easily for realistic difficulty experiments, and
+/// leaves very little room between difficulty levels. Hashes compare lexicographically by byte,
+/// so they can be checked against a 32-byte target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hash256(pub [u8; 32]);
+
+// Helper to do some 256-bit hashing. Each 8-byte chunk is the regular hash of the value
+// together with the chunk's index. This is not a cryptographic hash, just a wider one.
+fn hash256<T: Hash>(t: &T) -> Hash256 {
+    let mut bytes = [0u8; 32];
+    for (i, chunk) in bytes.chunks_mut(8).enumerate() {
+        chunk.copy_from_slice(&hash(&(i, t)).to_be_bytes());
+    }
+    Hash256(bytes)
 }